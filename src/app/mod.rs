@@ -1,19 +1,27 @@
 use anyhow::{Context, Result, anyhow};
 use clap::{Parser, ValueEnum};
 use crossbeam_channel::bounded;
-use osmpbf::{BlobDecode, BlobReader, Element, HeaderBlock};
+use osmpbf::{Blob, BlobDecode, BlobReader, ByteOffset, Element, HeaderBlock, PrimitiveBlock};
 use rayon::prelude::*;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::ops::RangeInclusive;
 use std::path::{Path, PathBuf};
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
 
-use crate::config::{CompiledConfig, NodeCacheMode, RuntimeConfig};
+use crate::config::{CompiledConfig, NodeCacheMode, ProgressMode, RuntimeConfig};
+use crate::index::{BlobEntry, BlobIndex, BlobKinds, IndexRecorder, InputFingerprint};
 use crate::pipeline::{BlockProcessor, NodesOnlyProcessor, StandardProcessor};
+use crate::report::RunStats;
 use crate::sinks::{
-    ColumnSpec, DataSink, FeatureRow, GeoJsonSink, GeoJsonlSink, GeoParquetSink,
+    ColumnSpec, DataSink, EncodedBatch, FeatureEncoder, GeoJsonSink, GeoJsonlSink, GeoParquetSink,
+};
+use crate::storage::{BuiltinNodeStore, NodeStore, NodeStoreWriter};
+use crate::utils::{
+    PositionReader, Progress, available_disk_bytes, available_memory_bytes, supports_sparse_files,
 };
-use crate::storage::{NodeStoreReader, NodeStoreWriter};
-use crate::utils::ProgressCounter;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -57,6 +65,51 @@ pub struct Cli {
     /// Include all tags in a 'tags' JSON column
     #[arg(long)]
     pub all_tags: bool,
+
+    /// Write features in input file order (reproducible output; decoding stays parallel)
+    #[arg(long)]
+    pub ordered: bool,
+
+    /// Blob index sidecar file: reused when it matches the input, written otherwise
+    #[arg(long)]
+    pub blob_index: Option<PathBuf>,
+
+    /// Approximate memory budget for buffered batches, e.g. 512M or 4G (default: unbounded)
+    #[arg(long, value_parser = parse_byte_size)]
+    pub memory_limit: Option<u64>,
+
+    /// Progress reporting on stderr: text status line or JSON events (one per line)
+    #[arg(long, value_enum, default_value = "text")]
+    pub progress: ProgressMode,
+
+    /// Write a JSON summary of the run (counts, errors, node cache, timings) to this file
+    #[arg(long)]
+    pub report: Option<PathBuf>,
+}
+
+/// Parse a byte size such as `4096`, `512K`, `256MB`, `4G` or `1.5GiB` (binary units).
+pub fn parse_byte_size(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid size '{value}': expected a number with optional unit"))?;
+    let multiplier: u64 = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        "T" | "TB" | "TIB" => 1 << 40,
+        other => return Err(format!("invalid size unit '{other}': use K, M, G or T")),
+    };
+    let bytes = number * multiplier as f64;
+    if bytes < 1.0 {
+        return Err(format!("invalid size '{value}': must be at least one byte"));
+    }
+    Ok(bytes as u64)
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug)]
@@ -72,32 +125,204 @@ pub enum OutputFormat {
 
 pub type SinkHandle = Arc<Mutex<Box<dyn DataSink + Send>>>;
 
+// Rough PBF density used for auto selection: the planet is ~80 GB for ~10B nodes.
+const INPUT_BYTES_PER_NODE: u64 = 8;
+// HashMap entry plus allocator and table overhead.
+const MEMORY_BYTES_PER_NODE: u64 = 40;
+// Typical compressed page entry for sorted input (see storage).
+const COMPRESSED_BYTES_PER_NODE: u64 = 6;
+// Worst case for dense mode: every node lands on its own (sparse) 4 KiB page.
+const DENSE_PAGE_BYTES: u64 = 4096;
+// Blobs each worker may run ahead of the sink writer in ordered mode.
+const ORDERED_BLOBS_PER_THREAD: u64 = 4;
+// Share of `--memory-limit` for batches in flight between workers and the writer; the
+// Parquet sink's buffers take most of the rest.
+const IN_FLIGHT_SHARE: u64 = 2;
+
+/// Facts about the input and host that `auto` mode uses to pick a node cache.
+#[derive(Debug, Clone)]
+pub struct CacheEnvironment {
+    pub input_bytes: u64,
+    /// Input declares `Sort.Type_then_ID` in its header
+    pub sorted: bool,
+    pub available_memory: Option<u64>,
+    /// Free space on the cache directory's filesystem
+    pub available_disk: Option<u64>,
+    pub sparse_files: bool,
+    pub max_nodes: u64,
+}
+
+impl CacheEnvironment {
+    pub fn probe(input_path: &Path, cache_dir: &Path, max_nodes: u64) -> Self {
+        Self {
+            input_bytes: std::fs::metadata(input_path).map(|m| m.len()).unwrap_or(0),
+            sorted: input_has_sort_header(input_path),
+            available_memory: available_memory_bytes(),
+            available_disk: available_disk_bytes(cache_dir),
+            sparse_files: supports_sparse_files(cache_dir),
+            max_nodes,
+        }
+    }
+
+    fn estimated_nodes(&self) -> u64 {
+        (self.input_bytes / INPUT_BYTES_PER_NODE).max(1)
+    }
+
+    /// Disk fits if the estimate stays below 90% of free space. Unknown free space is
+    /// treated as fitting, which matches the behaviour before free space was checked.
+    fn disk_fits(&self, bytes: u64) -> bool {
+        self.available_disk
+            .is_none_or(|free| bytes <= free / 10 * 9)
+    }
+
+    /// Memory must be known and the estimate must stay below 75% of what is available.
+    fn memory_fits(&self, bytes: u64) -> bool {
+        self.available_memory
+            .is_some_and(|free| bytes <= free / 4 * 3)
+    }
+}
+
+/// Pick a concrete node cache mode for `auto`, returning the mode and a human-readable reason.
+pub fn select_node_cache_mode(env: &CacheEnvironment) -> (NodeCacheMode, String) {
+    let nodes = env.estimated_nodes();
+    let dense_bytes = nodes
+        .saturating_mul(DENSE_PAGE_BYTES)
+        .min(env.max_nodes.saturating_mul(8));
+    let sparse_bytes = nodes.saturating_mul(16);
+    let compressed_bytes = nodes.saturating_mul(COMPRESSED_BYTES_PER_NODE);
+    let memory_bytes = nodes.saturating_mul(MEMORY_BYTES_PER_NODE);
+
+    let summary = format!(
+        "{} input, ~{} nodes, {}",
+        format_gb(env.input_bytes),
+        nodes,
+        if env.sorted {
+            "sorted"
+        } else {
+            "not sorted by type then id"
+        }
+    );
+    let pick = |mode: NodeCacheMode, why: String| {
+        (
+            mode,
+            format!("{} (auto-selected: {}; {})", mode.label(), summary, why),
+        )
+    };
+
+    let large = env.input_bytes >= crate::config::DENSE_THRESHOLD_BYTES;
+    if large && env.sparse_files && env.disk_fits(dense_bytes) {
+        return pick(
+            NodeCacheMode::Dense,
+            "large input, filesystem supports sparse files".to_string(),
+        );
+    }
+
+    if env.sorted {
+        if env.disk_fits(sparse_bytes) {
+            return pick(
+                NodeCacheMode::Sparse,
+                format!("~{} needed on disk", format_gb(sparse_bytes)),
+            );
+        }
+        if env.disk_fits(compressed_bytes) {
+            return pick(
+                NodeCacheMode::Compressed,
+                format!(
+                    "not enough free disk for sparse (~{}), compressed needs ~{}",
+                    format_gb(sparse_bytes),
+                    format_gb(compressed_bytes)
+                ),
+            );
+        }
+    }
+
+    if env.memory_fits(memory_bytes) {
+        return pick(
+            NodeCacheMode::Memory,
+            format!(
+                "~{} of RAM needed, {} available",
+                format_gb(memory_bytes),
+                env.available_memory
+                    .map_or_else(|| "?".to_string(), format_gb)
+            ),
+        );
+    }
+
+    if env.sparse_files && env.disk_fits(dense_bytes) {
+        return pick(
+            NodeCacheMode::Dense,
+            "filesystem supports sparse files and there is not enough RAM for memory mode"
+                .to_string(),
+        );
+    }
+
+    // Nothing fits comfortably; choose the mode most likely to get through
+    if env.sorted {
+        pick(
+            NodeCacheMode::Compressed,
+            "low on disk and memory, using the smallest on-disk format".to_string(),
+        )
+    } else if env.sparse_files {
+        pick(
+            NodeCacheMode::Dense,
+            "low on disk and memory; unsorted input rules out sparse and compressed".to_string(),
+        )
+    } else {
+        pick(
+            NodeCacheMode::Memory,
+            "unsorted input and no sparse file support; memory is the only remaining option"
+                .to_string(),
+        )
+    }
+}
+
 pub fn resolve_node_cache_mode(
     requested: NodeCacheMode,
     input_path: &Path,
+    cache_dir: &Path,
+    max_nodes: u64,
 ) -> (NodeCacheMode, String) {
     match requested {
         NodeCacheMode::Auto => {
-            let file_size = std::fs::metadata(input_path).map(|m| m.len()).unwrap_or(0);
-            let size_gb = file_size as f64 / (1024.0 * 1024.0 * 1024.0);
-
-            let dense_threshold = crate::config::DENSE_THRESHOLD_BYTES;
-            if file_size >= dense_threshold {
-                (
-                    NodeCacheMode::Dense,
-                    format!("dense (auto-selected for {:.1} GB input)", size_gb),
-                )
-            } else {
-                (
-                    NodeCacheMode::Sparse,
-                    format!("sparse (auto-selected for {:.1} GB input)", size_gb),
-                )
-            }
+            let env = CacheEnvironment::probe(input_path, cache_dir, max_nodes);
+            tracing::info!(
+                "Auto node cache: memory available: {}, free disk in {:?}: {}, sparse files: {}, Sort.Type_then_ID: {}",
+                env.available_memory
+                    .map_or_else(|| "unknown".to_string(), format_gb),
+                cache_dir,
+                env.available_disk
+                    .map_or_else(|| "unknown".to_string(), format_gb),
+                env.sparse_files,
+                env.sorted
+            );
+            select_node_cache_mode(&env)
         }
         requested => (requested, requested.label().to_string()),
     }
 }
 
+/// Check the header blob for the `Sort.Type_then_ID` feature (set by `osmium sort`).
+pub fn input_has_sort_header(path: &Path) -> bool {
+    let Ok(mut reader) = BlobReader::from_path(path) else {
+        return false;
+    };
+    let Some(Ok(blob)) = reader.next() else {
+        return false;
+    };
+    match blob.decode() {
+        Ok(BlobDecode::OsmHeader(header)) => header
+            .required_features()
+            .iter()
+            .chain(header.optional_features())
+            .any(|feature| feature.trim().eq_ignore_ascii_case("Sort.Type_then_ID")),
+        _ => false,
+    }
+}
+
+fn format_gb(bytes: u64) -> String {
+    format!("{:.1} GB", bytes as f64 / (1024.0 * 1024.0 * 1024.0))
+}
+
 pub fn summarize_filters_compiled(config: &CompiledConfig) -> (String, usize, bool, bool, bool) {
     let table = &config.table;
 
@@ -152,6 +377,7 @@ pub fn init_sink(
     format: &OutputFormat,
     output: &Path,
     config: &CompiledConfig,
+    runtime: &RuntimeConfig,
 ) -> Result<Box<dyn DataSink + Send>> {
     match format {
         OutputFormat::GeoJson => {
@@ -183,7 +409,11 @@ pub fn init_sink(
                 output,
                 columns.len()
             );
-            Ok(Box::new(GeoParquetSink::new(output, columns)?))
+            let sink = GeoParquetSink::new(output, columns)?;
+            Ok(Box::new(match runtime.memory_limit {
+                Some(limit) => sink.with_memory_limit(limit),
+                None => sink,
+            }))
         }
     }
 }
@@ -207,203 +437,571 @@ pub fn needs_node_store_compiled(config: &CompiledConfig) -> bool {
     config.table.geometry.way.enabled() || config.table.geometry.relation
 }
 
+/// Node IDs and coordinates of a block, as (id, lat, lon).
+fn block_nodes(block: &PrimitiveBlock) -> impl Iterator<Item = (i64, f64, f64)> + '_ {
+    block.elements().filter_map(|element| match element {
+        Element::Node(node) => Some((node.id(), node.lat(), node.lon())),
+        Element::DenseNode(node) => Some((node.id(), node.lat(), node.lon())),
+        _ => None,
+    })
+}
+
+/// A pass over the input, as named in log messages and progress reports.
+#[derive(Debug, Clone, Copy)]
+pub struct Pass {
+    /// Stable identifier used in JSON progress events
+    pub id: &'static str,
+    pub label: &'static str,
+}
+
+impl Pass {
+    pub const INDEX_NODES: Pass = Pass {
+        id: "pass1",
+        label: "Pass 1/2: indexing nodes",
+    };
+    pub const FEATURES: Pass = Pass {
+        id: "pass2",
+        label: "Pass 2/2: features",
+    };
+    pub const SINGLE: Pass = Pass {
+        id: "single",
+        label: "Single pass: features",
+    };
+}
+
+/// Blobs read by one pass: the indexed blobs containing the wanted element types, then every
+/// blob after the indexed part of the file. Without an index, every blob in file order.
+struct PassBlobs {
+    reader: BlobReader<BufReader<PositionReader<File>>>,
+    /// Reader position in the input file, for progress reporting
+    position: Arc<AtomicU64>,
+    offsets: std::vec::IntoIter<u64>,
+    /// Where sequential reading continues once `offsets` is exhausted
+    tail: Option<u64>,
+    in_tail: bool,
+}
+
+impl PassBlobs {
+    fn open(
+        path: &Path,
+        index: Option<&BlobIndex>,
+        kinds: BlobKinds,
+        ids: &RangeInclusive<i64>,
+        label: &str,
+    ) -> Result<Self> {
+        let file = PositionReader::new(File::open(path)?);
+        let position = file.position();
+        let reader = BlobReader::new_seekable(BufReader::new(file))?;
+        let Some(index) = index else {
+            return Ok(Self {
+                reader,
+                position,
+                offsets: Vec::new().into_iter(),
+                tail: None,
+                in_tail: true,
+            });
+        };
+
+        let offsets = index.offsets_with(kinds, ids);
+        tracing::info!(
+            "{}: decoding {} of {} indexed blobs{}",
+            label,
+            offsets.len(),
+            index.entries.len(),
+            if index.is_complete() {
+                ""
+            } else {
+                " plus the unindexed rest of the file"
+            }
+        );
+        Ok(Self {
+            reader,
+            position,
+            offsets: offsets.into_iter(),
+            tail: index.unindexed_from,
+            in_tail: false,
+        })
+    }
+}
+
+impl PassBlobs {
+    /// Progress reporter for this pass, following the reader through the file.
+    fn progress(&self, pass: Pass, runtime: &RuntimeConfig, input_len: u64) -> Progress {
+        Progress::new(
+            pass.id,
+            pass.label,
+            runtime.progress,
+            input_len,
+            Arc::clone(&self.position),
+        )
+    }
+}
+
+impl Iterator for PassBlobs {
+    type Item = osmpbf::Result<Blob>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if !self.in_tail {
+            if let Some(offset) = self.offsets.next() {
+                return Some(self.reader.blob_from_offset(ByteOffset(offset)));
+            }
+            let tail = self.tail.take()?;
+            if let Err(err) = self.reader.seek(ByteOffset(tail)) {
+                return Some(Err(err));
+            }
+            self.in_tail = true;
+        }
+        self.reader.next()
+    }
+}
+
+/// Index entry for a decoded blob, if the recorder doesn't have one yet.
+fn index_entry(recorder: &IndexRecorder, blob: &Blob, block: &PrimitiveBlock) -> Option<BlobEntry> {
+    let offset = blob.offset()?.0;
+    recorder
+        .wants(offset)
+        .then(|| BlobEntry::from_block(offset, block))
+}
+
+/// Decode one pass 1 blob and return its nodes.
+///
+/// With sorted input (`Sort.Type_then_ID`) the first data blob without nodes sets `stop`:
+/// nothing after it contains nodes, so the rest of the file is left unread and unindexed.
+fn pass1_blob(
+    blob_result: osmpbf::Result<Blob>,
+    recorder: &IndexRecorder,
+    sorted: bool,
+    stop: &AtomicBool,
+    header_logged: &Arc<AtomicBool>,
+) -> Result<Vec<(i64, f64, f64)>> {
+    let blob = blob_result?;
+    match blob.decode() {
+        Ok(BlobDecode::OsmHeader(header)) => {
+            log_sorted_header(&header, header_logged);
+            Ok(Vec::new())
+        }
+        Ok(BlobDecode::OsmData(block)) => {
+            if let Some(entry) = index_entry(recorder, &blob, &block) {
+                if sorted && !entry.kinds.is_empty() && !entry.kinds.intersects(BlobKinds::NODES) {
+                    stop.store(true, Ordering::Relaxed);
+                    recorder.stop_at(entry.offset);
+                    return Ok(Vec::new());
+                }
+                recorder.record(entry);
+            }
+            Ok(block_nodes(&block).collect())
+        }
+        Ok(BlobDecode::Unknown(unknown)) => {
+            tracing::info!("Unknown blob: {}", unknown);
+            Ok(Vec::new())
+        }
+        Err(error) => Err(error.into()),
+    }
+}
+
+/// Pass 1: write every node coordinate into the node store and return the node count.
+///
+/// Stores that accept concurrent writes (dense, memory) are filled directly by the decoding
+/// workers. Otherwise blobs are decoded in file order and batches go through a channel to a
+/// single writer thread, which preserves the node ID order of sorted input.
+///
+/// With an `index`, only blobs containing nodes are decoded. Blobs outside the index are
+/// recorded in `recorder`. `runtime.memory_limit` bounds the node batches queued for the
+/// writer.
 pub fn pass1_index_nodes(
     path: &Path,
-    node_store: NodeStoreWriter,
-    use_parallel: bool,
-) -> Result<(NodeStoreWriter, u64)> {
-    let mut reader = BlobReader::from_path(path)?;
-    let (tx, rx) = bounded::<Vec<(u64, f64, f64)>>(64);
+    node_store: &dyn NodeStore,
+    index: Option<&BlobIndex>,
+    recorder: &IndexRecorder,
+    runtime: &RuntimeConfig,
+) -> Result<u64> {
+    let sorted = input_has_sort_header(path);
+    let blobs = PassBlobs::open(
+        path,
+        index,
+        BlobKinds::NODES,
+        &(i64::MIN..=i64::MAX),
+        "Pass 1",
+    )?;
+    let progress = blobs.progress(Pass::INDEX_NODES, runtime, input_len(path)?);
+
+    if node_store.concurrent_writes() {
+        return pass1_index_nodes_shared(blobs, node_store, recorder, sorted, &progress);
+    }
+    let memory_limit = runtime.memory_limit;
+
+    let (tx, rx) = bounded::<Vec<(i64, f64, f64)>>(64);
     let header_logged = Arc::new(AtomicBool::new(false));
+    let stop = AtomicBool::new(false);
+    let budget = ByteBudget::new(memory_limit.map(|limit| limit / IN_FLIGHT_SHARE));
+    let batch_bytes = |batch: &Vec<(i64, f64, f64)>| {
+        (batch.len() * std::mem::size_of::<(i64, f64, f64)>()) as u64
+    };
+
+    std::thread::scope(|scope| {
+        let budget = &budget;
+        let progress = &progress;
+        let writer = scope.spawn(move || -> Result<u64> {
+            let mut node_count = 0u64;
+
+            let result = rx.iter().try_for_each(|batch| -> Result<()> {
+                node_store.put_batch(&batch)?;
+                budget.release(batch_bytes(&batch));
+                let batch_len = batch.len() as u64;
+                node_count += batch_len;
+                progress.add_nodes(batch_len);
+                Ok(())
+            });
+            budget.close();
+            result?;
 
-    let writer = std::thread::spawn(move || -> Result<(NodeStoreWriter, u64)> {
-        let mut node_store = node_store;
-        let mut node_count = 0u64;
-        let progress = ProgressCounter::new("Pass 1/2: indexing nodes", 100_000);
-
-        for batch in rx {
-            let batch_len = batch.len() as u64;
-            for (id, lat, lon) in batch {
-                node_store
-                    .put(id, lat, lon)
-                    .with_context(|| format!("Pipeline: Failed writing node {}", id))?;
-                node_count += 1;
+            progress.finish();
+            Ok(node_count)
+        });
+
+        let decode_result = blobs
+            .take_while(|_| !stop.load(Ordering::Relaxed))
+            .try_for_each(|blob_result| -> Result<()> {
+                let batch = pass1_blob(blob_result, recorder, sorted, &stop, &header_logged)?;
+                if !batch.is_empty() {
+                    budget.acquire(batch_bytes(&batch), || false);
+                    tx.send(batch)
+                        .map_err(|err| anyhow!("Pipeline: Failed to send node batch: {}", err))?;
+                }
+                Ok(())
+            });
+
+        drop(tx);
+
+        // Get writer thread result - it contains the root cause if there was an error
+        let writer_join = writer.join();
+
+        // Check writer thread first - it has the real error if the channel disconnected
+        let node_count = match writer_join {
+            Ok(Ok(result)) => result,
+            Ok(Err(writer_err)) => {
+                // Writer had an error - this is the root cause
+                return if decode_result.is_err() {
+                    Err(writer_err.context("writer thread failed (caused channel disconnect)"))
+                } else {
+                    Err(writer_err)
+                };
             }
-            if batch_len > 0 {
-                progress.inc(batch_len);
+            Err(panic_payload) => {
+                // Thread panicked - try to extract useful info
+                let panic_msg = panic_payload
+                    .downcast_ref::<&str>()
+                    .map(|s| s.to_string())
+                    .or_else(|| panic_payload.downcast_ref::<String>().cloned())
+                    .unwrap_or_else(|| "unknown panic".to_string());
+                return Err(anyhow!(
+                    "Pipeline: Node writer thread panicked: {}",
+                    panic_msg
+                ));
             }
+        };
+
+        // Only check decode_result if writer succeeded
+        decode_result?;
+
+        if stop.load(Ordering::Relaxed) {
+            tracing::info!("Pass 1: Sorted input, stopped reading after the last node blob");
         }
+        Ok(node_count)
+    })
+}
 
-        progress.finish();
-        Ok((node_store, node_count))
-    });
+fn pass1_index_nodes_shared(
+    blobs: PassBlobs,
+    node_store: &dyn NodeStore,
+    recorder: &IndexRecorder,
+    sorted: bool,
+    progress: &Progress,
+) -> Result<u64> {
+    let header_logged = Arc::new(AtomicBool::new(false));
+    let stop = AtomicBool::new(false);
+    let node_count = AtomicU64::new(0);
 
-    let decode_result = if use_parallel {
-        let header_logged = Arc::clone(&header_logged);
-        let tx = tx.clone();
-        reader
-            .par_bridge()
-            .try_for_each(move |blob_result| -> Result<()> {
-                let blob = blob_result?;
-                match blob.decode() {
-                    Ok(BlobDecode::OsmHeader(header)) => {
-                        log_sorted_header(&header, &header_logged);
-                        Ok(())
-                    }
-                    Ok(BlobDecode::OsmData(block)) => {
-                        let mut batch = Vec::new();
-                        for element in block.elements() {
-                            match element {
-                                Element::Node(node) => {
-                                    batch.push((node.id() as u64, node.lat(), node.lon()));
-                                }
-                                Element::DenseNode(node) => {
-                                    batch.push((node.id() as u64, node.lat(), node.lon()));
-                                }
-                                _ => {}
-                            }
-                        }
-
-                        if !batch.is_empty() {
-                            tx.send(batch).map_err(|err| {
-                                anyhow!("Pipeline: Failed to send node batch: {}", err)
-                            })?;
-                        }
-                        Ok(())
-                    }
-                    Ok(BlobDecode::Unknown(unknown)) => {
-                        tracing::info!("Unknown blob: {}", unknown);
-                        Ok(())
-                    }
-                    Err(error) => Err(error.into()),
-                }
-            })
-    } else {
-        // Sequential processing to preserve node ID order (for sparse mode)
-        reader.try_for_each(|blob_result| -> Result<()> {
-            let blob = blob_result?;
-            match blob.decode() {
-                Ok(BlobDecode::OsmHeader(header)) => {
-                    log_sorted_header(&header, &header_logged);
-                    Ok(())
-                }
-                Ok(BlobDecode::OsmData(block)) => {
-                    let mut batch = Vec::new();
-                    for element in block.elements() {
-                        match element {
-                            Element::Node(node) => {
-                                batch.push((node.id() as u64, node.lat(), node.lon()));
-                            }
-                            Element::DenseNode(node) => {
-                                batch.push((node.id() as u64, node.lat(), node.lon()));
-                            }
-                            _ => {}
-                        }
-                    }
-
-                    if !batch.is_empty() {
-                        tx.send(batch).map_err(|err| {
-                            anyhow!("Pipeline: Failed to send node batch: {}", err)
-                        })?;
-                    }
-                    Ok(())
-                }
-                Ok(BlobDecode::Unknown(unknown)) => {
-                    tracing::info!("Unknown blob: {}", unknown);
-                    Ok(())
-                }
-                Err(error) => Err(error.into()),
+    blobs
+        .take_while(|_| !stop.load(Ordering::Relaxed))
+        .par_bridge()
+        .try_for_each(|blob_result| -> Result<()> {
+            let batch = pass1_blob(blob_result, recorder, sorted, &stop, &header_logged)?;
+            if !batch.is_empty() {
+                node_store.put_batch(&batch)?;
+                let batch_len = batch.len() as u64;
+                node_count.fetch_add(batch_len, Ordering::Relaxed);
+                progress.add_nodes(batch_len);
             }
-        })
-    };
+            Ok(())
+        })?;
 
-    drop(tx);
+    progress.finish();
+    if stop.load(Ordering::Relaxed) {
+        tracing::info!("Pass 1: Sorted input, stopped reading after the last node blob");
+    }
+    Ok(node_count.into_inner())
+}
 
-    // Get writer thread result - it contains the root cause if there was an error
-    let writer_join = writer.join();
+/// Re-sequences per-blob results so they leave in blob order, whatever order they arrive in.
+struct ReorderBuffer<T> {
+    next: u64,
+    pending: BTreeMap<u64, T>,
+}
 
-    // Check writer thread first - it has the real error if the channel disconnected
-    let (node_store, node_count) = match writer_join {
-        Ok(Ok(result)) => result,
-        Ok(Err(writer_err)) => {
-            // Writer had an error - this is the root cause
-            return if decode_result.is_err() {
-                Err(writer_err.context("writer thread failed (caused channel disconnect)"))
-            } else {
-                Err(writer_err)
-            };
+impl<T> ReorderBuffer<T> {
+    fn new() -> Self {
+        Self {
+            next: 0,
+            pending: BTreeMap::new(),
         }
-        Err(panic_payload) => {
-            // Thread panicked - try to extract useful info
-            let panic_msg = panic_payload
-                .downcast_ref::<&str>()
-                .map(|s| s.to_string())
-                .or_else(|| panic_payload.downcast_ref::<String>().cloned())
-                .unwrap_or_else(|| "unknown panic".to_string());
-            return Err(anyhow!(
-                "Pipeline: Node writer thread panicked: {}",
-                panic_msg
-            ));
+    }
+
+    /// Add the result for blob `seq` and return every result that is now in sequence.
+    fn push(&mut self, seq: u64, item: T) -> Vec<T> {
+        self.pending.insert(seq, item);
+        let mut ready = Vec::new();
+        while let Some(item) = self.pending.remove(&self.next) {
+            ready.push(item);
+            self.next += 1;
+        }
+        ready
+    }
+
+    /// Next blob index the writer is waiting for
+    fn next(&self) -> u64 {
+        self.next
+    }
+}
+
+/// Bounds how far decoding may run ahead of the writer in ordered mode, so a slow blob
+/// cannot make the reorder buffer grow without limit.
+struct ReorderWindow {
+    /// (next blob index the writer waits for, closed)
+    state: Mutex<(u64, bool)>,
+    cond: Condvar,
+    size: u64,
+}
+
+impl ReorderWindow {
+    fn new(size: u64) -> Self {
+        Self {
+            state: Mutex::new((0, false)),
+            cond: Condvar::new(),
+            size: size.max(1),
+        }
+    }
+
+    /// Block until blob `seq` is within the window. The blob the writer waits for is always
+    /// within it, so this cannot deadlock as long as that blob is being processed.
+    fn wait(&self, seq: u64) {
+        let mut state = self.state.lock().unwrap();
+        while !state.1 && seq >= state.0 + self.size {
+            state = self.cond.wait(state).unwrap();
+        }
+    }
+
+    fn advance(&self, next: u64) {
+        self.state.lock().unwrap().0 = next;
+        self.cond.notify_all();
+    }
+
+    /// Next blob index the writer waits for
+    fn next(&self) -> u64 {
+        self.state.lock().unwrap().0
+    }
+
+    /// Release all waiting workers, e.g. after an error stopped the writer or a worker.
+    fn close(&self) {
+        self.state.lock().unwrap().1 = true;
+        self.cond.notify_all();
+    }
+}
+
+/// Bounds the bytes of batches between the decoding workers and the writer thread
+/// (`--memory-limit`). The channels only bound the number of batches, which says little
+/// about memory when blocks or rows vary a lot in size.
+struct ByteBudget {
+    /// (bytes in flight, closed)
+    state: Mutex<(u64, bool)>,
+    cond: Condvar,
+    limit: u64,
+}
+
+impl ByteBudget {
+    fn new(limit: Option<u64>) -> Self {
+        Self {
+            state: Mutex::new((0, false)),
+            cond: Condvar::new(),
+            limit: limit.unwrap_or(u64::MAX).max(1),
+        }
+    }
+
+    /// Wait until `bytes` fit in the budget, then take them. A batch is always admitted when
+    /// nothing else is in flight, so a single oversized block cannot stall the pass, and
+    /// whenever `urgent` returns true (the writer is waiting for exactly this batch).
+    fn acquire(&self, bytes: u64, urgent: impl Fn() -> bool) {
+        let mut state = self.state.lock().unwrap();
+        while !state.1 && state.0 > 0 && state.0 + bytes > self.limit && !urgent() {
+            state = self.cond.wait(state).unwrap();
+        }
+        state.0 += bytes;
+    }
+
+    fn release(&self, bytes: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.0 = state.0.saturating_sub(bytes);
+        drop(state);
+        self.cond.notify_all();
+    }
+
+    /// Release all waiting workers, e.g. after an error stopped the writer.
+    fn close(&self) {
+        self.state.lock().unwrap().1 = true;
+        self.cond.notify_all();
+    }
+}
+
+/// Decode one blob, run the processor on it and encode the resulting features.
+fn process_blob<P: BlockProcessor>(
+    blob_result: osmpbf::Result<Blob>,
+    processor: &P,
+    encoder: Option<&dyn FeatureEncoder>,
+    progress: &Progress,
+    recorder: &IndexRecorder,
+) -> Result<EncodedBatch> {
+    let blob = blob_result?;
+    let block = match blob.decode() {
+        Ok(BlobDecode::OsmHeader(_)) => return Ok(EncodedBatch::Rows(Vec::new())),
+        Ok(BlobDecode::OsmData(block)) => block,
+        Ok(BlobDecode::Unknown(unknown)) => {
+            tracing::info!("Unknown blob: {}", unknown);
+            return Ok(EncodedBatch::Rows(Vec::new()));
         }
+        Err(error) => return Err(error.into()),
     };
 
-    // Only check decode_result if writer succeeded
-    decode_result?;
+    progress.tick();
+
+    if let Some(entry) = index_entry(recorder, &blob, &block) {
+        let wanted = entry.kinds.intersects(processor.element_kinds());
+        recorder.record(entry);
+        if !wanted {
+            return Ok(EncodedBatch::Rows(Vec::new()));
+        }
+    }
 
-    Ok((node_store, node_count))
+    let rows = processor.process_block(block)?;
+    match encoder {
+        Some(encoder) if !rows.is_empty() => encoder.encode(rows),
+        _ => Ok(EncodedBatch::Rows(rows)),
+    }
 }
 
+/// Decode and process blobs in parallel and write the resulting features to the sink.
+///
+/// With `ordered`, block results are re-sequenced by blob index before reaching the sink, so
+/// output order follows the input file. At most a few blobs per thread are buffered.
+///
+/// With an `index`, only blobs holding element types the processor handles are decoded.
 pub fn run_pass<P>(
     path: &Path,
     processor: Arc<P>,
     sink: SinkHandle,
-    label: &'static str,
+    pass: Pass,
+    runtime: &RuntimeConfig,
+    index: Option<&BlobIndex>,
+    recorder: &IndexRecorder,
 ) -> Result<u64>
 where
     P: BlockProcessor + 'static,
 {
-    let reader = BlobReader::from_path(path)?;
-    let (tx, rx) = bounded::<Vec<FeatureRow>>(64);
-    let progress = Arc::new(ProgressCounter::new(label, 100));
+    let ordered = runtime.ordered;
+    let reader = PassBlobs::open(
+        path,
+        index,
+        processor.element_kinds(),
+        &processor.element_ids(),
+        pass.label,
+    )?;
+    let (tx, rx) = bounded::<(u64, EncodedBatch, u64)>(64);
+    let progress = Arc::new(reader.progress(pass, runtime, input_len(path)?));
+    // Geometry and value encoding run in the workers; the writer thread only appends
+    let encoder = sink.lock().unwrap().encoder();
+    let window = Arc::new(ReorderWindow::new(
+        ORDERED_BLOBS_PER_THREAD * rayon::current_num_threads() as u64,
+    ));
+    let budget = Arc::new(ByteBudget::new(
+        runtime.memory_limit.map(|limit| limit / IN_FLIGHT_SHARE),
+    ));
 
     let sink_handle = sink.clone();
+    let writer_window = Arc::clone(&window);
+    let writer_budget = Arc::clone(&budget);
+    let writer_progress = Arc::clone(&progress);
     let writer = std::thread::spawn(move || -> Result<u64> {
         let mut sink = sink_handle.lock().unwrap();
         let mut match_count = 0u64;
-        for batch in rx {
-            for row in batch {
-                sink.add_feature(row)?;
-                match_count += 1;
+        let mut reorder = ReorderBuffer::new();
+        let result = (|| -> Result<()> {
+            for (seq, batch, bytes) in rx {
+                let batches = if ordered {
+                    let ready = reorder.push(seq, (batch, bytes));
+                    writer_window.advance(reorder.next());
+                    ready
+                } else {
+                    vec![(batch, bytes)]
+                };
+                for (batch, bytes) in batches {
+                    let len = batch.len() as u64;
+                    match_count += len;
+                    sink.add_encoded(batch)?;
+                    writer_progress.add_features(len);
+                    writer_budget.release(bytes);
+                }
             }
-        }
-        Ok(match_count)
+            Ok(())
+        })();
+        writer_window.close();
+        writer_budget.close();
+        result.map(|()| match_count)
     });
 
     let processor = processor.clone();
-    let decode_result = reader
-        .par_bridge()
-        .try_for_each(|blob_result| -> Result<()> {
-            let blob = blob_result?;
-            let block = match blob.decode() {
-                Ok(BlobDecode::OsmHeader(_)) => return Ok(()),
-                Ok(BlobDecode::OsmData(block)) => block,
-                Ok(BlobDecode::Unknown(unknown)) => {
-                    tracing::info!("Unknown blob: {}", unknown);
-                    return Ok(());
+    let close = || {
+        window.close();
+        budget.close();
+    };
+    let decode_result =
+        reader
+            .enumerate()
+            .par_bridge()
+            .try_for_each(|(seq, blob_result)| -> Result<()> {
+                let seq = seq as u64;
+                if ordered {
+                    window.wait(seq);
+                }
+                let batch = process_blob(
+                    blob_result,
+                    processor.as_ref(),
+                    encoder.as_deref(),
+                    &progress,
+                    recorder,
+                )
+                .inspect_err(|_| close())?;
+                // In ordered mode every blob must reach the writer, even without features
+                if ordered || !batch.is_empty() {
+                    let bytes = batch.estimated_bytes() as u64;
+                    // The blob the writer waits for must never wait for later blobs' budget
+                    budget.acquire(bytes, || ordered && window.next() >= seq);
+                    tx.send((seq, batch, bytes)).map_err(|err| {
+                        close();
+                        anyhow!("Pipeline: Failed to send feature batch: {}", err)
+                    })?;
                 }
-                Err(error) => return Err(error.into()),
-            };
-
-            progress.inc(1);
 
-            let batch = processor.process_block(block)?;
-            if !batch.is_empty() {
-                tx.send(batch)
-                    .map_err(|err| anyhow!("Pipeline: Failed to send feature batch: {}", err))?;
-            }
-
-            Ok(())
-        });
+                Ok(())
+            });
 
     drop(tx);
 
@@ -443,19 +1041,33 @@ where
     Ok(match_count)
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn pass2_process(
     path: &Path,
     config: Arc<CompiledConfig>,
     runtime: Arc<RuntimeConfig>,
-    node_store: Arc<NodeStoreReader>,
+    node_store: Arc<dyn NodeStore>,
     sink: SinkHandle,
+    index: Option<&BlobIndex>,
+    recorder: &IndexRecorder,
+    stats: Arc<RunStats>,
 ) -> Result<u64> {
     let processor = Arc::new(StandardProcessor {
         config,
         runtime,
         node_store,
+        stats,
     });
-    run_pass(path, processor, sink, "Pass 2/2: blocks")
+    let runtime = processor.runtime.clone();
+    run_pass(
+        path,
+        processor,
+        sink,
+        Pass::FEATURES,
+        &runtime,
+        index,
+        recorder,
+    )
 }
 
 pub fn pass_nodes_only(
@@ -463,17 +1075,109 @@ pub fn pass_nodes_only(
     config: Arc<CompiledConfig>,
     runtime: Arc<RuntimeConfig>,
     sink: SinkHandle,
+    stats: Arc<RunStats>,
 ) -> Result<u64> {
-    let processor = Arc::new(NodesOnlyProcessor { config, runtime });
-    run_pass(path, processor, sink, "Single pass: blocks")
+    let start = std::time::Instant::now();
+    let input = input_fingerprint(path)?;
+    let loaded = load_blob_index(&runtime, &input);
+    let recorder = IndexRecorder::new(input, loaded.as_ref());
+
+    let processor = Arc::new(NodesOnlyProcessor {
+        config,
+        runtime: runtime.clone(),
+        stats: stats.clone(),
+    });
+    let match_count = run_pass(
+        path,
+        processor,
+        sink,
+        Pass::SINGLE,
+        &runtime,
+        loaded.as_ref(),
+        &recorder,
+    )?;
+
+    let loaded_complete = loaded.as_ref().is_some_and(BlobIndex::is_complete);
+    save_blob_index(&runtime, loaded_complete, loaded, recorder);
+    stats.record_pass(Pass::SINGLE.id, start.elapsed());
+    Ok(match_count)
+}
+
+fn input_len(path: &Path) -> Result<u64> {
+    Ok(std::fs::metadata(path)
+        .with_context(|| format!("Pipeline: Failed to read input {:?}", path))?
+        .len())
+}
+
+fn input_fingerprint(path: &Path) -> Result<InputFingerprint> {
+    InputFingerprint::of(path).with_context(|| format!("Pipeline: Failed to read input {:?}", path))
+}
+
+/// Load the `--blob-index` sidecar if it exists and matches the input.
+fn load_blob_index(runtime: &RuntimeConfig, input: &InputFingerprint) -> Option<BlobIndex> {
+    let path = runtime.blob_index.as_deref()?;
+    let index = BlobIndex::load(path, input)?;
+    tracing::info!(
+        "Blob index: {} blobs from {:?}{}",
+        index.entries.len(),
+        path,
+        if index.is_complete() {
+            ""
+        } else {
+            " (partial)"
+        }
+    );
+    Some(index)
 }
 
+/// Merge what the last pass recorded into `index` and write the sidecar once it covers the
+/// whole file. A sidecar that could not be written only costs the next run its speedup, so
+/// failures are logged rather than returned.
+fn save_blob_index(
+    runtime: &RuntimeConfig,
+    loaded_complete: bool,
+    index: Option<BlobIndex>,
+    recorder: IndexRecorder,
+) {
+    let Some(path) = runtime.blob_index.as_deref() else {
+        return;
+    };
+    let Some(index) = index_to_save(loaded_complete, index, recorder) else {
+        return;
+    };
+    match index.save(path) {
+        Ok(()) => tracing::info!(
+            "Blob index: wrote {} blobs to {:?}",
+            index.entries.len(),
+            path
+        ),
+        Err(err) => tracing::warn!("{:#}", err),
+    }
+}
+
+/// The index to write to the sidecar: the merged index once it is complete, unless the
+/// sidecar read at the start (`loaded_complete`) already covered the whole file. `index` may
+/// already be complete from an earlier pass of this run, e.g. pass 1 on unsorted input.
+fn index_to_save(
+    loaded_complete: bool,
+    index: Option<BlobIndex>,
+    recorder: IndexRecorder,
+) -> Option<BlobIndex> {
+    if loaded_complete {
+        return None;
+    }
+    let index = BlobIndex::extend(index, recorder);
+    index.is_complete().then_some(index)
+}
+
+/// Run the passes the table needs. Counters and timings for `--report` go to `stats`.
 pub fn process_pbf(
     cli: &Cli,
     config: Arc<CompiledConfig>,
     runtime: Arc<RuntimeConfig>,
     sink: SinkHandle,
     needs_nodes: bool,
+    stats: Arc<RunStats>,
 ) -> Result<u64> {
     tracing::info!("Node cache required: {}", needs_nodes);
     if needs_nodes {
@@ -483,9 +1187,21 @@ pub fn process_pbf(
             let size_gb = metadata.len() as f64 / (1024.0 * 1024.0 * 1024.0);
             tracing::info!("Input size: {:.2} GB", size_gb);
         }
-        // Resolve auto mode to concrete mode based on input file size
-        let (resolved_mode, mode_desc) =
-            resolve_node_cache_mode(runtime.node_cache_mode, &cli.input);
+        // Resolve auto mode to concrete mode based on input, memory and cache filesystem
+        let cache_dir = cli
+            .node_cache
+            .as_deref()
+            .and_then(Path::parent)
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .map(Path::to_path_buf)
+            .unwrap_or_else(std::env::temp_dir);
+        let (resolved_mode, mode_desc) = resolve_node_cache_mode(
+            runtime.node_cache_mode,
+            &cli.input,
+            &cache_dir,
+            runtime.node_cache_max_nodes,
+        );
+        stats.set_node_cache(resolved_mode.label());
 
         // Create node store based on resolved mode
         let node_store = match resolved_mode {
@@ -516,6 +1232,11 @@ pub fn process_pbf(
                         .context("Pipeline: Failed to create temporary dense node store")?
                 }
             }
+            NodeCacheMode::Compressed => {
+                tracing::info!("Node cache: {} (temp file)", mode_desc);
+                NodeStoreWriter::new_compressed()
+                    .context("Pipeline: Failed to create compressed node store")?
+            }
             NodeCacheMode::Memory => {
                 tracing::info!("Node cache: {}", mode_desc);
                 NodeStoreWriter::new_memory()
@@ -525,39 +1246,355 @@ pub fn process_pbf(
             }
         };
 
-        // Use sequential processing for sparse mode to preserve sort order and avoid in-memory sort
-        let use_parallel = !matches!(resolved_mode, NodeCacheMode::Sparse);
-        let pass1_mode = if use_parallel {
-            "parallel"
+        let finalize_step = match resolved_mode {
+            NodeCacheMode::Sparse => "Finalizing node cache (flush + mmap)...",
+            NodeCacheMode::Dense => "Finalizing node cache (mmap read-only)...",
+            NodeCacheMode::Compressed => "Finalizing node cache (flush + mmap)...",
+            NodeCacheMode::Memory => "Finalizing node cache (in-memory)...",
+            NodeCacheMode::Auto => "Finalizing node cache...",
+        };
+        // Temp file (if any) is cleaned up when the node store is dropped
+        process_with_node_store(
+            &cli.input,
+            config,
+            runtime,
+            sink,
+            Box::new(BuiltinNodeStore::new(node_store)),
+            finalize_step,
+            stats,
+        )
+    } else {
+        tracing::info!("Single pass: Processing nodes (parallel)...");
+        pass_nodes_only(&cli.input, config, runtime, sink, stats)
+    }
+}
+
+/// Run pass 1 (unless the store is already populated) and pass 2 against a caller-supplied
+/// node store. This is the entry point for embedding cosmo with a custom `NodeStore` backend.
+pub fn process_pbf_with_node_store(
+    input: &Path,
+    config: Arc<CompiledConfig>,
+    runtime: Arc<RuntimeConfig>,
+    sink: SinkHandle,
+    node_store: Box<dyn NodeStore>,
+    stats: Arc<RunStats>,
+) -> Result<u64> {
+    process_with_node_store(
+        input,
+        config,
+        runtime,
+        sink,
+        node_store,
+        "Finalizing node cache...",
+        stats,
+    )
+}
+
+fn process_with_node_store(
+    input: &Path,
+    config: Arc<CompiledConfig>,
+    runtime: Arc<RuntimeConfig>,
+    sink: SinkHandle,
+    mut node_store: Box<dyn NodeStore>,
+    finalize_step: &str,
+    stats: Arc<RunStats>,
+) -> Result<u64> {
+    let fingerprint = input_fingerprint(input)?;
+    let loaded = load_blob_index(&runtime, &fingerprint);
+    let loaded_complete = loaded.as_ref().is_some_and(BlobIndex::is_complete);
+    let mut index = loaded;
+
+    if node_store.requires_indexing() {
+        // Sorted stores get sequential decoding to preserve sort order and avoid an in-memory sort
+        let pass1_mode = if node_store.concurrent_writes() {
+            "parallel, direct writes"
         } else {
             "sequential"
         };
         tracing::info!(
             "Pass 1: Indexing nodes from {:?} ({})...",
-            cli.input,
+            input,
             pass1_mode
         );
-        let (node_store, node_count) = pass1_index_nodes(&cli.input, node_store, use_parallel)?;
+        let start = std::time::Instant::now();
+        let recorder = IndexRecorder::new(fingerprint, index.as_ref());
+        let node_count = pass1_index_nodes(
+            input,
+            node_store.as_ref(),
+            index.as_ref(),
+            &recorder,
+            &runtime,
+        )?;
+        index = Some(BlobIndex::extend(index, recorder));
+        stats.record_pass(Pass::INDEX_NODES.id, start.elapsed());
+        stats.set_nodes_indexed(node_count);
         tracing::info!("Indexed {} nodes.", node_count);
+    } else {
+        tracing::info!("Pass 1: Skipped (node store is already populated)");
+    }
 
-        let finalize_step = match resolved_mode {
-            NodeCacheMode::Sparse => "Finalizing node cache (flush + mmap)...",
-            NodeCacheMode::Dense => "Finalizing node cache (mmap read-only)...",
-            NodeCacheMode::Memory => "Finalizing node cache (in-memory)...",
-            NodeCacheMode::Auto => "Finalizing node cache...",
+    tracing::info!("{}", finalize_step);
+    let start = std::time::Instant::now();
+    node_store.finalize()?;
+    stats.record_pass("node_cache", start.elapsed());
+    tracing::info!("Node cache ready.");
+
+    tracing::info!("Pass 2: Processing elements (parallel)...");
+    let start = std::time::Instant::now();
+    let recorder = IndexRecorder::new(fingerprint, index.as_ref());
+    let match_count = pass2_process(
+        input,
+        config,
+        runtime.clone(),
+        Arc::from(node_store),
+        sink,
+        index.as_ref(),
+        &recorder,
+        stats.clone(),
+    )?;
+    stats.record_pass(Pass::FEATURES.id, start.elapsed());
+
+    save_blob_index(&runtime, loaded_complete, index, recorder);
+    Ok(match_count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GB: u64 = 1024 * 1024 * 1024;
+
+    const INPUT: InputFingerprint = InputFingerprint {
+        len: 1000,
+        modified: None,
+        sample_hash: 42,
+    };
+
+    #[test]
+    fn index_from_a_full_pass_1_is_saved() {
+        // Unsorted input without a sidecar: pass 1 reads and records every blob
+        let pass1 = IndexRecorder::new(INPUT, None);
+        pass1.record(BlobEntry {
+            offset: 100,
+            kinds: BlobKinds::NODES,
+            min_id: 1,
+            max_id: 10,
+        });
+        pass1.record(BlobEntry {
+            offset: 200,
+            kinds: BlobKinds::WAYS,
+            min_id: 1,
+            max_id: 10,
+        });
+        let index = BlobIndex::extend(None, pass1);
+        assert!(index.is_complete());
+
+        // Pass 2 then has nothing left to record
+        let pass2 = IndexRecorder::new(INPUT, Some(&index));
+        assert!(!pass2.wants(100) && !pass2.wants(200));
+        let saved = index_to_save(false, Some(index.clone()), pass2).expect("index is saved");
+        assert_eq!(saved, index);
+
+        // A complete sidecar from disk is not written again
+        let pass2 = IndexRecorder::new(INPUT, Some(&index));
+        assert_eq!(index_to_save(true, Some(index), pass2), None);
+    }
+
+    #[test]
+    fn partial_index_is_not_saved() {
+        let pass1 = IndexRecorder::new(INPUT, None);
+        pass1.record(BlobEntry {
+            offset: 100,
+            kinds: BlobKinds::NODES,
+            min_id: 1,
+            max_id: 10,
+        });
+        pass1.stop_at(200);
+        assert_eq!(index_to_save(false, None, pass1), None);
+    }
+
+    fn env(input_bytes: u64, sorted: bool) -> CacheEnvironment {
+        CacheEnvironment {
+            input_bytes,
+            sorted,
+            available_memory: Some(16 * GB),
+            available_disk: Some(500 * GB),
+            sparse_files: true,
+            max_nodes: crate::config::DEFAULT_MAX_NODES,
+        }
+    }
+
+    #[test]
+    fn auto_picks_sparse_for_small_sorted_input() {
+        let (mode, reason) = select_node_cache_mode(&env(GB, true));
+        assert_eq!(mode, NodeCacheMode::Sparse);
+        assert!(reason.contains("auto-selected"));
+    }
+
+    #[test]
+    fn auto_picks_dense_for_large_input() {
+        let (mode, _) = select_node_cache_mode(&env(40 * GB, true));
+        assert_eq!(mode, NodeCacheMode::Dense);
+    }
+
+    #[test]
+    fn auto_avoids_sparse_for_unsorted_input() {
+        let (mode, reason) = select_node_cache_mode(&env(GB, false));
+        assert_eq!(mode, NodeCacheMode::Memory);
+        assert!(reason.contains("not sorted"));
+    }
+
+    #[test]
+    fn auto_falls_back_to_dense_for_unsorted_input_without_memory() {
+        let mut env = env(GB, false);
+        env.available_memory = Some(GB);
+        let (mode, _) = select_node_cache_mode(&env);
+        assert_eq!(mode, NodeCacheMode::Dense);
+    }
+
+    #[test]
+    fn auto_picks_compressed_when_disk_is_tight() {
+        let mut env = env(4 * GB, true);
+        // Sparse needs ~8 GB here, compressed ~3 GB
+        env.available_disk = Some(5 * GB);
+        let (mode, reason) = select_node_cache_mode(&env);
+        assert_eq!(mode, NodeCacheMode::Compressed);
+        assert!(reason.contains("not enough free disk"));
+    }
+
+    #[test]
+    fn auto_skips_dense_without_sparse_file_support() {
+        let mut env = env(40 * GB, true);
+        env.sparse_files = false;
+        let (mode, _) = select_node_cache_mode(&env);
+        assert_ne!(mode, NodeCacheMode::Dense);
+    }
+
+    #[test]
+    fn auto_does_not_pick_memory_when_memory_is_unknown() {
+        let mut env = env(GB, false);
+        env.available_memory = None;
+        let (mode, _) = select_node_cache_mode(&env);
+        assert_eq!(mode, NodeCacheMode::Dense);
+    }
+
+    #[test]
+    fn explicit_mode_is_kept() {
+        let (mode, desc) = resolve_node_cache_mode(
+            NodeCacheMode::Memory,
+            Path::new("does-not-exist.osm.pbf"),
+            Path::new("."),
+            crate::config::DEFAULT_MAX_NODES,
+        );
+        assert_eq!(mode, NodeCacheMode::Memory);
+        assert_eq!(desc, "memory");
+    }
+
+    // ============================================
+    // Ordered output tests
+    // ============================================
+
+    #[test]
+    fn reorder_buffer_releases_results_in_blob_order() {
+        let mut buffer = ReorderBuffer::new();
+        assert!(buffer.push(2, "c").is_empty());
+        assert!(buffer.push(1, "b").is_empty());
+        assert_eq!(buffer.push(0, "a"), vec!["a", "b", "c"]);
+        assert_eq!(buffer.next(), 3);
+        assert!(buffer.push(4, "e").is_empty());
+        assert_eq!(buffer.push(3, "d"), vec!["d", "e"]);
+        assert!(buffer.pending.is_empty());
+    }
+
+    #[test]
+    fn reorder_window_blocks_until_writer_catches_up() {
+        let window = Arc::new(ReorderWindow::new(2));
+        // Within the window: returns immediately
+        window.wait(0);
+        window.wait(1);
+
+        let waiter = {
+            let window = Arc::clone(&window);
+            std::thread::spawn(move || window.wait(5))
         };
-        tracing::info!("{}", finalize_step);
-        let node_store = Arc::new(node_store.finalize()?);
-        tracing::info!("Node cache ready.");
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        assert!(!waiter.is_finished());
 
-        tracing::info!("Pass 2: Processing elements (parallel)...");
-        let result = pass2_process(&cli.input, config, runtime, node_store, sink)?;
+        window.advance(4);
+        waiter.join().unwrap();
+    }
 
-        // Temp file (if any) is cleaned up when node_store is dropped
+    #[test]
+    fn reorder_window_close_releases_waiters() {
+        let window = Arc::new(ReorderWindow::new(1));
+        let waiter = {
+            let window = Arc::clone(&window);
+            std::thread::spawn(move || window.wait(100))
+        };
+        window.close();
+        waiter.join().unwrap();
+    }
 
-        Ok(result)
-    } else {
-        tracing::info!("Single pass: Processing nodes (parallel)...");
-        pass_nodes_only(&cli.input, config, runtime, sink)
+    #[test]
+    fn byte_budget_admits_oversized_batch_when_idle() {
+        let budget = ByteBudget::new(Some(100));
+        budget.acquire(1_000, || false);
+        budget.release(1_000);
+        budget.acquire(60, || false);
+        budget.acquire(40, || false);
+    }
+
+    #[test]
+    fn byte_budget_blocks_until_bytes_are_released() {
+        let budget = Arc::new(ByteBudget::new(Some(100)));
+        budget.acquire(80, || false);
+
+        let acquired = Arc::new(AtomicBool::new(false));
+        let waiter = {
+            let budget = Arc::clone(&budget);
+            let acquired = Arc::clone(&acquired);
+            std::thread::spawn(move || {
+                budget.acquire(50, || false);
+                acquired.store(true, Ordering::SeqCst);
+            })
+        };
+
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        assert!(!acquired.load(Ordering::SeqCst));
+        budget.release(80);
+        waiter.join().unwrap();
+        assert!(acquired.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn byte_budget_lets_urgent_batches_through() {
+        let budget = ByteBudget::new(Some(100));
+        budget.acquire(100, || false);
+        budget.acquire(100, || true);
+    }
+
+    #[test]
+    fn byte_budget_close_releases_waiters() {
+        let budget = Arc::new(ByteBudget::new(Some(10)));
+        budget.acquire(10, || false);
+        let waiter = {
+            let budget = Arc::clone(&budget);
+            std::thread::spawn(move || budget.acquire(10, || false))
+        };
+        budget.close();
+        waiter.join().unwrap();
+    }
+
+    #[test]
+    fn parses_byte_sizes_with_binary_units() {
+        assert_eq!(parse_byte_size("4096"), Ok(4096));
+        assert_eq!(parse_byte_size("512K"), Ok(512 * 1024));
+        assert_eq!(parse_byte_size("256MB"), Ok(256 * 1024 * 1024));
+        assert_eq!(parse_byte_size("4G"), Ok(4 * GB));
+        assert_eq!(parse_byte_size("1.5GiB"), Ok(3 * GB / 2));
+        assert_eq!(parse_byte_size(" 2 g "), Ok(2 * GB));
+        assert!(parse_byte_size("lots").is_err());
+        assert!(parse_byte_size("4X").is_err());
+        assert!(parse_byte_size("0").is_err());
     }
 }
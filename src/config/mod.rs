@@ -1,9 +1,9 @@
-use crate::dsl::{FilterAst, parse_filter};
+use crate::dsl::{FilterAnalysis, FilterAst, ParseError, parse_filter};
 use crate::expr::{CelProgram, compile_cel};
 use crate::mapping::{Mapping, MappingConfig};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
 #[cfg(test)]
@@ -16,6 +16,62 @@ pub struct FiltersConfig {
     /// Named mappings for derived columns
     #[serde(default)]
     pub mappings: HashMap<String, MappingConfig>,
+    /// The file the config was loaded from, to point errors at their line
+    #[serde(skip)]
+    source: Option<SourceFile>,
+}
+
+#[derive(Debug)]
+struct SourceFile {
+    path: PathBuf,
+    text: String,
+}
+
+impl SourceFile {
+    /// Set the location of a parse error to the line of the file it is on. Expressions are
+    /// found as the value of a `filter:` or `match:` line, or as the first line of a block
+    /// scalar after one, so the first of several identical ones wins.
+    fn locate(&self, error: &mut ParseError) {
+        let Some((first, first_line)) = error
+            .expression
+            .lines()
+            .enumerate()
+            .find(|(_, line)| !line.trim().is_empty())
+        else {
+            return;
+        };
+        let (line, _) = error.line_column();
+        let lines: Vec<&str> = self.text.lines().collect();
+        // 1-based line in the file of the error's line in the expression
+        let Some(found) = lines.iter().enumerate().find_map(|(i, text)| {
+            let value = expression_value(text)?;
+            if value.starts_with(['|', '>']) {
+                let (start, text) = lines
+                    .iter()
+                    .enumerate()
+                    .skip(i + 1)
+                    .find(|(_, text)| !text.trim().is_empty())?;
+                (text.trim() == first_line.trim()).then_some(start + line - first)
+            } else {
+                let parsed = serde_yaml::from_str::<String>(value).ok();
+                (parsed.as_deref().unwrap_or(value) == error.expression).then_some(i + 1)
+            }
+        }) else {
+            return;
+        };
+        error.location = Some(format!("{}:{}", self.path.display(), found));
+    }
+}
+
+/// The value written after `filter:` or `match:` on a YAML line, including a block scalar
+/// indicator like `|`.
+fn expression_value(line: &str) -> Option<&str> {
+    let line = line.trim_start();
+    let line = line.strip_prefix("- ").unwrap_or(line).trim_start();
+    let value = line
+        .strip_prefix("filter:")
+        .or_else(|| line.strip_prefix("match:"))?;
+    Some(value.trim())
 }
 
 impl FiltersConfig {
@@ -35,7 +91,20 @@ impl FiltersConfig {
         let settings = ::config::Config::builder()
             .add_source(::config::File::from(path))
             .build()?;
-        Ok(settings.try_deserialize()?)
+        let mut config: FiltersConfig = settings.try_deserialize()?;
+        config.source = Some(SourceFile {
+            path: path.to_path_buf(),
+            text: content,
+        });
+        Ok(config)
+    }
+
+    /// Add the file and line to a parse error, when the config was loaded from a file.
+    fn locate(&self, mut error: ParseError) -> ParseError {
+        if let Some(source) = &self.source {
+            source.locate(&mut error);
+        }
+        error
     }
 
     /// Compile the config, parsing all DSL strings and CEL expressions.
@@ -44,7 +113,7 @@ impl FiltersConfig {
 
         // Compile mappings first (they may be referenced by columns)
         for (name, config) in &self.mappings {
-            let mapping = Mapping::compile(name.clone(), config)?;
+            let mapping = Mapping::compile(name.clone(), config, |e| self.locate(e))?;
             mappings.insert(name.clone(), mapping);
         }
 
@@ -53,10 +122,12 @@ impl FiltersConfig {
         let table_name = table.name.clone();
 
         let filter = match &table.filter {
-            FilterInput::Dsl(s) => parse_filter(s)
-                .map_err(|e| anyhow::anyhow!("Filter error in table '{}': {}", table_name, e))?,
+            FilterInput::Dsl(s) => parse_filter(s).map_err(|e| {
+                anyhow::anyhow!("Filter error in table '{}': {}", table_name, self.locate(e))
+            })?,
             FilterInput::Structured(expr) => convert_structured_filter(expr)?,
-        };
+        }
+        .optimize();
 
         let mut columns = Vec::new();
         for col in &table.columns {
@@ -70,6 +141,7 @@ impl FiltersConfig {
 
         let compiled_table = CompiledTable {
             name: table_name,
+            analysis: filter.analyze(),
             filter,
             columns,
             geometry: table.geometry.clone(),
@@ -90,6 +162,14 @@ pub struct RuntimeConfig {
     pub node_cache_mode: NodeCacheMode,
     pub node_cache_max_nodes: u64,
     pub all_tags: bool,
+    /// Write features in input file order instead of completion order
+    pub ordered: bool,
+    /// Sidecar file for the blob index, read when it matches the input and written otherwise
+    pub blob_index: Option<PathBuf>,
+    /// Approximate byte budget for batches in flight and sink buffers; `None` is unbounded
+    pub memory_limit: Option<u64>,
+    /// How pass progress is reported on stderr
+    pub progress: ProgressMode,
 }
 
 impl Default for RuntimeConfig {
@@ -99,6 +179,10 @@ impl Default for RuntimeConfig {
             // OSM has ~10.3B nodes as of 2025; use generous headroom to skip prepass scan
             node_cache_max_nodes: DEFAULT_MAX_NODES,
             all_tags: false,
+            ordered: false,
+            blob_index: None,
+            memory_limit: None,
+            progress: ProgressMode::Text,
         }
     }
 }
@@ -106,12 +190,14 @@ impl Default for RuntimeConfig {
 #[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
 #[serde(rename_all = "lowercase")]
 pub enum NodeCacheMode {
-    /// Automatically select based on input file size (default)
+    /// Automatically select based on input, memory and cache filesystem (default)
     Auto,
     /// Sorted array - memory-efficient for extracts (<5GB)
     Sparse,
     /// Direct ID indexing - best for planet/continent (≥5GB)
     Dense,
+    /// Delta-encoded pages - several times smaller than sparse, requires sorted input
+    Compressed,
     /// In-memory HashMap (no disk usage)
     Memory,
 }
@@ -124,6 +210,7 @@ impl FromStr for NodeCacheMode {
             "auto" => Ok(NodeCacheMode::Auto),
             "sparse" => Ok(NodeCacheMode::Sparse),
             "dense" | "mmap" => Ok(NodeCacheMode::Dense), // mmap kept for backwards compatibility
+            "compressed" => Ok(NodeCacheMode::Compressed),
             "memory" => Ok(NodeCacheMode::Memory),
             _ => Err(format!("invalid node_cache_mode: {value}")),
         }
@@ -136,11 +223,33 @@ impl NodeCacheMode {
             NodeCacheMode::Auto => "auto",
             NodeCacheMode::Sparse => "sparse",
             NodeCacheMode::Dense => "dense",
+            NodeCacheMode::Compressed => "compressed",
             NodeCacheMode::Memory => "memory",
         }
     }
 }
 
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ProgressMode {
+    /// Status line with percentage, throughput and ETA (default)
+    Text,
+    /// One JSON object per line, for job schedulers
+    Json,
+}
+
+impl FromStr for ProgressMode {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "text" => Ok(ProgressMode::Text),
+            "json" => Ok(ProgressMode::Json),
+            _ => Err(format!("invalid progress mode: {value}")),
+        }
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct TableConfig {
     /// Table name (used in output file naming and logging)
@@ -268,6 +377,8 @@ pub struct CompiledConfig {
 pub struct CompiledTable {
     pub name: String,
     pub filter: FilterAst,
+    /// `filter.analyze()`, computed once instead of per block
+    pub analysis: FilterAnalysis,
     pub columns: Vec<CompiledColumn>,
     pub geometry: GeometryConfig,
 }
@@ -338,6 +449,8 @@ fn convert_structured_filter(expr: &FilterExpr) -> anyhow::Result<FilterAst> {
                 .map(|(k, v)| FilterAst::TagMatch {
                     key: k.clone(),
                     values: vec![crate::dsl::TagValue::Exact(v.clone())],
+                    normalize: false,
+                    list: false,
                 })
                 .collect();
             Ok(FilterAst::And(conditions).simplify())
@@ -349,22 +462,34 @@ fn convert_structured_filter(expr: &FilterExpr) -> anyhow::Result<FilterAst> {
                     negated: false,
                 })
             } else {
+                // With `normalize`, values are compared like the DSL's `^=`
+                let normalize = |v: &str| {
+                    if tag_match.normalize {
+                        crate::dsl::normalize_value(v).into_owned()
+                    } else {
+                        v.to_string()
+                    }
+                };
                 let mut values = Vec::new();
                 if let Some(v) = &tag_match.value {
-                    values.push(crate::dsl::TagValue::Exact(v.clone()));
+                    values.push(crate::dsl::TagValue::Exact(normalize(v)));
                 }
-                for v in &tag_match.values {
+                for v in tag_match.values.iter().map(|v| normalize(v)) {
                     if v == "*" {
                         values.push(crate::dsl::TagValue::Any);
-                    } else if v.contains('*') {
-                        values.push(crate::dsl::TagValue::Glob(v.clone()));
+                    } else if crate::dsl::GlobPattern::is_glob(&v) {
+                        let glob = crate::dsl::GlobPattern::new(&v)
+                            .map_err(|e| anyhow::anyhow!("Filter error: {}", e))?;
+                        values.push(crate::dsl::TagValue::Glob(glob));
                     } else {
-                        values.push(crate::dsl::TagValue::Exact(v.clone()));
+                        values.push(crate::dsl::TagValue::Exact(v));
                     }
                 }
                 Ok(FilterAst::TagMatch {
                     key: tag_match.tag.clone(),
                     values,
+                    normalize: tag_match.normalize,
+                    list: false,
                 })
             }
         }
@@ -387,6 +512,9 @@ pub struct TagMatch {
     pub value: Option<String>,
     #[serde(default)]
     pub values: Vec<String>,
+    /// Match values ignoring case and extra whitespace, like `^=` in the DSL.
+    #[serde(default)]
+    pub normalize: bool,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
@@ -456,6 +584,18 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn node_cache_mode_parses_compressed() {
+        assert!(matches!(
+            NodeCacheMode::from_str("compressed"),
+            Ok(NodeCacheMode::Compressed)
+        ));
+        assert!(matches!(
+            NodeCacheMode::from_str("COMPRESSED"),
+            Ok(NodeCacheMode::Compressed)
+        ));
+    }
+
     #[test]
     fn node_cache_mode_parses_memory() {
         assert!(matches!(
@@ -485,6 +625,10 @@ mod tests {
         assert!(matches!(config.node_cache_mode, NodeCacheMode::Auto));
         assert_eq!(config.node_cache_max_nodes, 16_000_000_000);
         assert!(!config.all_tags);
+        assert!(!config.ordered);
+        assert!(config.blob_index.is_none());
+        assert!(config.memory_limit.is_none());
+        assert_eq!(config.progress, ProgressMode::Text);
     }
 
     // ============================================
@@ -858,6 +1002,167 @@ table:
         }
     }
 
+    #[test]
+    fn compiles_normalized_tag_filter() {
+        let yaml = r#"
+table:
+  name: oneways
+  filter:
+    tag: "oneway"
+    values: ["Yes", " TRUE "]
+    normalize: true
+  columns:
+    - name: "oneway"
+      source: "tag:oneway"
+      type: "string"
+"#;
+        let file = write_temp_yaml(yaml);
+        let config = FiltersConfig::load(file.path()).unwrap();
+        let filter = config.compile().unwrap().table.filter;
+
+        assert_eq!(
+            filter,
+            FilterAst::TagMatch {
+                key: "oneway".into(),
+                values: vec![
+                    crate::dsl::TagValue::Exact("yes".into()),
+                    crate::dsl::TagValue::Exact("true".into()),
+                ],
+                normalize: true,
+                list: false,
+            }
+        );
+    }
+
+    #[test]
+    fn filter_errors_point_at_the_yaml_line() {
+        let yaml = r#"
+table:
+  name: roads
+  filter: "highway=|primary"
+  columns:
+    - name: "name"
+      source: "tag:name"
+      type: "string"
+"#;
+        let file = write_temp_yaml(yaml);
+        let config = FiltersConfig::load(file.path()).unwrap();
+        let Err(err) = config.compile() else {
+            panic!("expected a filter error");
+        };
+        let err = err.to_string();
+        assert!(
+            err.starts_with("Filter error in table 'roads': Expected value"),
+            "{err}"
+        );
+        assert!(
+            err.contains(&format!("--> {}:4", file.path().display())),
+            "{err}"
+        );
+        assert!(
+            err.contains("   | highway=|primary\n   |         ^"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn mapping_errors_point_at_the_yaml_line() {
+        let yaml = r#"
+table:
+  name: roads
+  filter: "highway"
+  columns:
+    - name: "class"
+      source: "map:class"
+      type: "string"
+mappings:
+  class:
+    rules:
+      - match: "highway=motorway"
+        value: "major"
+      - match: "highway=primary secondary"
+        value: "minor"
+"#;
+        let file = write_temp_yaml(yaml);
+        let config = FiltersConfig::load(file.path()).unwrap();
+        let Err(err) = config.compile() else {
+            panic!("expected a mapping error");
+        };
+        let err = err.to_string();
+        assert!(
+            err.starts_with("Error parsing rule 2 in mapping 'class': "),
+            "{err}"
+        );
+        assert!(
+            err.contains(&format!("--> {}:14", file.path().display())),
+            "{err}"
+        );
+        assert!(
+            err.contains("   | highway=primary secondary\n   |                 ^"),
+            "{err}"
+        );
+        assert!(err.contains("   = hint: ") && err.contains("`|`"), "{err}");
+    }
+
+    #[test]
+    fn errors_are_located_by_the_whole_expression() {
+        // The failing rule is a prefix of an earlier rule, the table filter and a column
+        let yaml = r#"
+table:
+  name: roads
+  filter: highway
+  columns:
+    - name: "highway"
+      source: "tag:highway"
+      type: "string"
+    - name: "class"
+      source: "map:class"
+      type: "string"
+mappings:
+  class:
+    rules:
+      - match: "highway=motorway"
+        value: "major"
+      - match: 'highway='
+        value: "minor"
+"#;
+        let file = write_temp_yaml(yaml);
+        let config = FiltersConfig::load(file.path()).unwrap();
+        let Err(err) = config.compile() else {
+            panic!("expected a mapping error");
+        };
+        let err = err.to_string();
+        assert!(
+            err.contains(&format!("--> {}:17", file.path().display())),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn block_scalar_filters_point_at_the_yaml_line() {
+        let yaml = r#"
+table:
+  name: roads
+  filter: |
+    highway
+    & name=Main)
+  columns:
+    - name: "name"
+      source: "tag:name"
+      type: "string"
+"#;
+        let file = write_temp_yaml(yaml);
+        let config = FiltersConfig::load(file.path()).unwrap();
+        let Err(err) = config.compile() else {
+            panic!("expected a filter error");
+        };
+        let err = err.to_string();
+        assert!(
+            err.contains(&format!("--> {}:6", file.path().display())),
+            "{err}"
+        );
+    }
+
     // ============================================
     // ClosedWayMode default tests
     // ============================================
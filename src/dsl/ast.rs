@@ -1,6 +1,10 @@
 //! AST types for the filter DSL.
 
+use super::spatial::Region;
+use regex::Regex;
+use std::borrow::Cow;
 use std::fmt;
+use std::ops::RangeInclusive;
 
 /// Root filter expression.
 #[derive(Debug, Clone, PartialEq)]
@@ -8,16 +12,33 @@ pub enum FilterAst {
     /// Tag existence check: `name` or `!name`
     TagExists { key: String, negated: bool },
 
-    /// Tag value match: `highway=primary` or `highway=primary|secondary`
-    TagMatch { key: String, values: Vec<TagValue> },
+    /// Tag value match: `highway=primary` or `highway=primary|secondary`.
+    ///
+    /// With `normalize` (`oneway^=yes`) the tag value goes through [`normalize_value`] before
+    /// matching; the patterns are normalized when the filter is parsed. With `list`
+    /// (`cuisine[]=pizza`) any of the `;`-separated items of the value may match.
+    TagMatch {
+        key: String,
+        values: Vec<TagValue>,
+        normalize: bool,
+        list: bool,
+    },
+
+    /// Any tag whose key matches the pattern exists: `~"^name:"` or `name:*`
+    KeyMatch(KeyPattern),
 
-    /// Numeric comparison: `lanes>=2`, `maxspeed<50`
+    /// Numeric comparison: `lanes>=2`, `maxspeed<50`. Values of speed, length and weight keys
+    /// (`30 mph`) are converted to OSM's default unit first. With `list` (`lanes[]>=3`) any item may match.
     NumericCompare {
         key: String,
         op: CompareOp,
         value: f64,
+        list: bool,
     },
 
+    /// A property of the element itself rather than its tags: `@type=way`, `@version>=3`
+    Element(ElementPredicate),
+
     /// Boolean AND: `expr1 & expr2`
     And(Vec<FilterAst>),
 
@@ -29,6 +50,30 @@ pub enum FilterAst {
 
     /// Always true (empty filter)
     True,
+
+    /// Never true; only produced by [`FilterAst::optimize`], for contradictions like `k=a & k=b`
+    False,
+}
+
+/// Static facts about which tags a filter depends on, used to reject elements early.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FilterAnalysis {
+    /// Whether an element without any tags can match.
+    pub matches_empty: bool,
+    /// Keys of which every matching element has at least one, or `None` when there is no
+    /// such set (e.g. the filter contains a negation).
+    pub required_keys: Option<Vec<String>>,
+    /// Whether the filter has element predicates, so untagged elements must be evaluated too.
+    pub uses_element: bool,
+    /// Whether any element predicate reads metadata (`@id` aside), which has to be decoded
+    /// before the filter runs.
+    pub uses_metadata: bool,
+    /// Whether the filter has spatial predicates, which are decided after geometry construction.
+    pub uses_geometry: bool,
+    /// The exact keys the filter looks up (see [`FilterAst::keys`]).
+    pub keys: Vec<String>,
+    /// Element IDs that can match, from `@id` comparisons. Empty if none can.
+    pub id_bounds: RangeInclusive<i64>,
 }
 
 /// A value to match against a tag.
@@ -38,8 +83,261 @@ pub enum TagValue {
     Exact(String),
     /// Wildcard (any value)
     Any,
-    /// Glob pattern (e.g., `*_link`)
-    Glob(String),
+    /// Glob pattern (e.g., `*_link`, `?[0-9]`)
+    Glob(GlobPattern),
+    /// Regular expression (`ref~"^A[0-9]+$"`), unanchored like `grep`
+    Regex(RegexPattern),
+    /// Inclusive numeric range (`lanes=2..4`, `maxspeed=..50`), compared with units converted
+    Range { min: Option<f64>, max: Option<f64> },
+}
+
+/// A regular expression compiled once when the filter is parsed.
+///
+/// Patterns compare equal when their source text is equal, so filters stay comparable.
+#[derive(Debug, Clone)]
+pub struct RegexPattern(Regex);
+
+impl RegexPattern {
+    pub fn new(pattern: &str) -> Result<Self, String> {
+        Regex::new(pattern)
+            .map(RegexPattern)
+            .map_err(|e| format!("Invalid regex \"{}\": {}", pattern, e))
+    }
+
+    pub fn is_match(&self, value: &str) -> bool {
+        self.0.is_match(value)
+    }
+
+    pub fn as_str(&self) -> &str {
+        self.0.as_str()
+    }
+}
+
+impl PartialEq for RegexPattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+/// Predicates on the element: `@type`, `@closed` and metadata fields.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ElementPredicate {
+    /// `@type=node|way`
+    Type(Vec<ElementKind>),
+    /// `@closed`: a way whose first and last node are the same
+    Closed,
+    /// `@id>100`, `@version>=3`, `@timestamp>=2024-01-01`; elements without the field don't match
+    Compare {
+        field: MetaField,
+        op: CompareOp,
+        value: i64,
+    },
+    /// `@user=alice|bob`, `@user~"bot$"`; elements without a user name don't match
+    User(Vec<TagValue>),
+    /// `@within(bbox:...)`: the geometry lies inside the region
+    Within(Region),
+    /// `@intersects(file:area.geojson)`: the geometry and the region share a point
+    Intersects(Region),
+    /// `@area>1000`, in square metres
+    Area { op: CompareOp, value: f64 },
+    /// `@length<50`, in metres
+    Length { op: CompareOp, value: f64 },
+}
+
+impl ElementPredicate {
+    /// Whether evaluating the predicate needs the element's metadata.
+    pub fn uses_metadata(&self) -> bool {
+        match self {
+            ElementPredicate::Compare { field, .. } => *field != MetaField::Id,
+            ElementPredicate::User(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Whether the predicate can only be decided once the element's geometry is built.
+    pub fn uses_geometry(&self) -> bool {
+        matches!(
+            self,
+            ElementPredicate::Within(_)
+                | ElementPredicate::Intersects(_)
+                | ElementPredicate::Area { .. }
+                | ElementPredicate::Length { .. }
+        )
+    }
+}
+
+/// OSM element type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElementKind {
+    Node,
+    Way,
+    Relation,
+}
+
+impl std::str::FromStr for ElementKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "node" => Ok(ElementKind::Node),
+            "way" => Ok(ElementKind::Way),
+            "relation" => Ok(ElementKind::Relation),
+            other => Err(format!(
+                "Unknown element type '{}'; expected node, way or relation",
+                other
+            )),
+        }
+    }
+}
+
+/// Numeric element fields of [`ElementPredicate::Compare`]. Timestamps compare as Unix
+/// milliseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetaField {
+    Id,
+    Version,
+    Changeset,
+    Uid,
+    Timestamp,
+}
+
+/// A key pattern of [`FilterAst::KeyMatch`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum KeyPattern {
+    Regex(RegexPattern),
+    Glob(GlobPattern),
+}
+
+impl KeyPattern {
+    pub fn is_match(&self, key: &str) -> bool {
+        match self {
+            KeyPattern::Regex(pattern) => pattern.is_match(key),
+            KeyPattern::Glob(pattern) => pattern.is_match(key),
+        }
+    }
+}
+
+/// A shell-style glob matched against the whole value: `*` matches any run of characters,
+/// `?` one character, and `[abc]`, `[a-z]` or `[!a-z]` one character of a class. A backslash
+/// makes the next character literal.
+///
+/// Unquoted text without `*` or `?` is only a glob if it has a closed class that isn't the
+/// whole text, so OSM values like `[disused]` or `[` match literally.
+///
+/// Compiled once when the filter is parsed; patterns compare equal by their source text.
+#[derive(Debug, Clone)]
+pub struct GlobPattern {
+    glob: String,
+    regex: Regex,
+}
+
+impl GlobPattern {
+    /// Whether unquoted text uses glob syntax and should be compiled as a pattern.
+    pub fn is_glob(text: &str) -> bool {
+        if text.contains(['*', '?']) {
+            return true;
+        }
+        let Some(open) = text.find('[') else {
+            return false;
+        };
+        match text[open + 1..].find(']') {
+            Some(close) => !(open == 0 && open + 1 + close == text.len() - 1),
+            None => false,
+        }
+    }
+
+    pub fn new(glob: &str) -> Result<Self, String> {
+        let mut regex = String::from(r"\A(?s:");
+        let mut chars = glob.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '*' => regex.push_str(".*"),
+                '?' => regex.push('.'),
+                '[' => {
+                    let mut class = String::from("[");
+                    if chars.next_if(|&c| c == '!' || c == '^').is_some() {
+                        class.push('^');
+                    }
+                    // A `]` right after the opening bracket is a member, as in shells
+                    if chars.next_if_eq(&']').is_some() {
+                        class.push_str(r"\]");
+                    }
+                    let mut closed = false;
+                    for c in chars.by_ref() {
+                        match c {
+                            ']' => {
+                                closed = true;
+                                break;
+                            }
+                            '-' => class.push('-'),
+                            c => class.push_str(&escape_char(c)),
+                        }
+                    }
+                    if !closed {
+                        return Err(format!("Invalid glob \"{}\": unclosed '['", glob));
+                    }
+                    class.push(']');
+                    regex.push_str(&class);
+                }
+                '\\' => regex.push_str(&escape_char(chars.next().unwrap_or('\\'))),
+                c => regex.push_str(&escape_char(c)),
+            }
+        }
+        regex.push_str(r")\z");
+
+        let regex = Regex::new(&regex).map_err(|e| format!("Invalid glob \"{}\": {}", glob, e))?;
+        Ok(GlobPattern {
+            glob: glob.to_string(),
+            regex,
+        })
+    }
+
+    pub fn is_match(&self, value: &str) -> bool {
+        self.regex.is_match(value)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.glob
+    }
+}
+
+impl PartialEq for GlobPattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.glob == other.glob
+    }
+}
+
+fn escape_char(c: char) -> String {
+    regex::escape(c.encode_utf8(&mut [0; 4]))
+}
+
+/// The form values are compared in by `^=`: lowercase, trimmed, with runs of whitespace
+/// collapsed to one space, so `" Yes"`, `"YES"` and `"yes"` are equal.
+///
+/// Borrows when the value is already normalized, which is the common case for OSM data.
+pub fn normalize_value(value: &str) -> Cow<'_, str> {
+    let mut previous_space = true;
+    let is_normal = value.chars().all(|c| {
+        let ok = if c.is_whitespace() {
+            c == ' ' && !previous_space
+        } else {
+            !c.is_uppercase()
+        };
+        previous_space = c.is_whitespace();
+        ok
+    }) && !value.ends_with(' ');
+    if is_normal {
+        return Cow::Borrowed(value);
+    }
+
+    let mut normalized = String::with_capacity(value.len());
+    for word in value.split_whitespace() {
+        if !normalized.is_empty() {
+            normalized.push(' ');
+        }
+        normalized.extend(word.chars().flat_map(char::to_lowercase));
+    }
+    Cow::Owned(normalized)
 }
 
 /// Numeric comparison operator.
@@ -67,6 +365,140 @@ impl fmt::Display for CompareOp {
 }
 
 impl FilterAst {
+    /// Tag keys referenced anywhere in the expression, without duplicates.
+    pub fn keys(&self) -> Vec<&str> {
+        let mut keys = Vec::new();
+        self.collect_keys(&mut keys);
+        keys
+    }
+
+    /// Work out which elements can match without looking at any of them.
+    pub fn analyze(&self) -> FilterAnalysis {
+        FilterAnalysis {
+            matches_empty: self.matches_empty(),
+            required_keys: self.required_keys(),
+            uses_element: self.any_element_predicate(&|_| true),
+            uses_metadata: self.any_element_predicate(&ElementPredicate::uses_metadata),
+            uses_geometry: self.any_element_predicate(&ElementPredicate::uses_geometry),
+            keys: self.keys().into_iter().map(str::to_string).collect(),
+            id_bounds: self.id_bounds(),
+        }
+    }
+
+    fn id_bounds(&self) -> RangeInclusive<i64> {
+        const NONE: RangeInclusive<i64> = RangeInclusive::new(1, 0);
+        match self {
+            FilterAst::Element(ElementPredicate::Compare {
+                field: MetaField::Id,
+                op,
+                value,
+            }) => match op {
+                CompareOp::Eq => *value..=*value,
+                CompareOp::Lt => value.checked_sub(1).map_or(NONE, |max| i64::MIN..=max),
+                CompareOp::Le => i64::MIN..=*value,
+                CompareOp::Gt => value.checked_add(1).map_or(NONE, |min| min..=i64::MAX),
+                CompareOp::Ge => *value..=i64::MAX,
+                CompareOp::Ne => i64::MIN..=i64::MAX,
+            },
+            FilterAst::False => NONE,
+            FilterAst::And(exprs) => exprs.iter().fold(i64::MIN..=i64::MAX, |bounds, expr| {
+                let other = expr.id_bounds();
+                *bounds.start().max(other.start())..=*bounds.end().min(other.end())
+            }),
+            // Covers every branch that can match
+            FilterAst::Or(exprs) => exprs
+                .iter()
+                .map(FilterAst::id_bounds)
+                .filter(|bounds| !bounds.is_empty())
+                .reduce(|a, b| *a.start().min(b.start())..=*a.end().max(b.end()))
+                .unwrap_or(NONE),
+            _ => i64::MIN..=i64::MAX,
+        }
+    }
+
+    fn any_element_predicate(&self, f: &dyn Fn(&ElementPredicate) -> bool) -> bool {
+        match self {
+            FilterAst::Element(predicate) => f(predicate),
+            FilterAst::And(exprs) | FilterAst::Or(exprs) => {
+                exprs.iter().any(|e| e.any_element_predicate(f))
+            }
+            FilterAst::Not(inner) => inner.any_element_predicate(f),
+            _ => false,
+        }
+    }
+
+    fn matches_empty(&self) -> bool {
+        match self {
+            FilterAst::True => true,
+            FilterAst::False => false,
+            FilterAst::TagExists { negated, .. } => *negated,
+            FilterAst::TagMatch { .. }
+            | FilterAst::KeyMatch(_)
+            | FilterAst::NumericCompare { .. } => false,
+            // Depends on the element; only meaningful without element predicates
+            FilterAst::Element(_) => true,
+            FilterAst::And(exprs) => exprs.iter().all(FilterAst::matches_empty),
+            FilterAst::Or(exprs) => exprs.iter().any(FilterAst::matches_empty),
+            FilterAst::Not(inner) => !inner.matches_empty(),
+        }
+    }
+
+    fn required_keys(&self) -> Option<Vec<String>> {
+        match self {
+            FilterAst::TagExists {
+                key,
+                negated: false,
+            }
+            | FilterAst::TagMatch { key, .. }
+            | FilterAst::NumericCompare { key, .. } => Some(vec![key.clone()]),
+            // Nothing matches, so no key is needed to reject
+            FilterAst::False => Some(Vec::new()),
+            // Any operand's keys are enough; the smallest set rejects the most elements
+            FilterAst::And(exprs) => exprs
+                .iter()
+                .filter_map(FilterAst::required_keys)
+                .min_by_key(Vec::len),
+            // Every branch must require keys, and any of them may be the one present
+            FilterAst::Or(exprs) => {
+                let mut keys: Vec<String> = Vec::new();
+                for expr in exprs {
+                    for key in expr.required_keys()? {
+                        if !keys.contains(&key) {
+                            keys.push(key);
+                        }
+                    }
+                }
+                Some(keys)
+            }
+            // Key patterns can be met by keys that aren't known up front
+            FilterAst::TagExists { negated: true, .. }
+            | FilterAst::KeyMatch(_)
+            | FilterAst::Element(_)
+            | FilterAst::Not(_)
+            | FilterAst::True => None,
+        }
+    }
+
+    fn collect_keys<'a>(&'a self, keys: &mut Vec<&'a str>) {
+        match self {
+            FilterAst::TagExists { key, .. }
+            | FilterAst::TagMatch { key, .. }
+            | FilterAst::NumericCompare { key, .. } => {
+                if !keys.contains(&key.as_str()) {
+                    keys.push(key);
+                }
+            }
+            FilterAst::And(exprs) | FilterAst::Or(exprs) => {
+                for expr in exprs {
+                    expr.collect_keys(keys);
+                }
+            }
+            FilterAst::Not(inner) => inner.collect_keys(keys),
+            FilterAst::KeyMatch(_) | FilterAst::Element(_) | FilterAst::True | FilterAst::False => {
+            }
+        }
+    }
+
     /// Simplify the AST by flattening nested And/Or.
     pub fn simplify(self) -> Self {
         match self {
@@ -106,3 +538,95 @@ impl FilterAst {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::normalize_value;
+    use crate::dsl::parse_filter;
+    use std::borrow::Cow;
+
+    fn analyze(expr: &str) -> (bool, Option<Vec<String>>) {
+        let analysis = parse_filter(expr).unwrap().analyze();
+        (analysis.matches_empty, analysis.required_keys)
+    }
+
+    fn keys(keys: &[&str]) -> Option<Vec<String>> {
+        Some(keys.iter().map(|k| k.to_string()).collect())
+    }
+
+    #[test]
+    fn positive_terms_require_their_key() {
+        assert_eq!(analyze("highway"), (false, keys(&["highway"])));
+        assert_eq!(analyze("highway=primary"), (false, keys(&["highway"])));
+        assert_eq!(analyze("lanes>=2"), (false, keys(&["lanes"])));
+    }
+
+    #[test]
+    fn and_uses_smallest_required_set() {
+        assert_eq!(
+            analyze("(highway | railway) & name"),
+            (false, keys(&["name"]))
+        );
+        assert_eq!(analyze("!building & shop"), (false, keys(&["shop"])));
+    }
+
+    #[test]
+    fn or_unions_keys_of_all_branches() {
+        assert_eq!(
+            analyze("highway=primary | railway=rail | highway=secondary"),
+            (false, keys(&["highway", "railway"]))
+        );
+        assert_eq!(analyze("highway | !name"), (true, None));
+    }
+
+    #[test]
+    fn key_patterns_require_no_known_key() {
+        assert_eq!(analyze(r#"~"^name:""#), (false, None));
+        assert_eq!(analyze(r#"ref~"^A" & ~"^name""#), (false, keys(&["ref"])));
+        assert_eq!(analyze(r#"highway | ~"^railway""#), (false, None));
+        assert_eq!(analyze(r#"ref!~"^A""#), (true, None));
+    }
+
+    #[test]
+    fn element_predicates_require_no_key() {
+        assert_eq!(analyze("@closed & building"), (false, keys(&["building"])));
+        assert_eq!(analyze("@type=node | amenity"), (true, None));
+
+        let analysis = parse_filter("@type=way & building").unwrap().analyze();
+        assert!(analysis.uses_element && !analysis.uses_metadata);
+        let analysis = parse_filter("building | !(@version>=2)").unwrap().analyze();
+        assert!(analysis.uses_element && analysis.uses_metadata);
+        assert!(!parse_filter("building").unwrap().analyze().uses_element);
+        let analysis = parse_filter("building & @area>100").unwrap().analyze();
+        assert!(analysis.uses_element && analysis.uses_geometry && !analysis.uses_metadata);
+    }
+
+    #[test]
+    fn id_comparisons_bound_the_matching_ids() {
+        let bounds = |expr: &str| parse_filter(expr).unwrap().analyze().id_bounds;
+        assert_eq!(bounds("building"), i64::MIN..=i64::MAX);
+        assert_eq!(bounds("@id=42"), 42..=42);
+        assert_eq!(bounds("@id>100 & @id<=200 & building"), 101..=200);
+        assert_eq!(bounds("@id<10 | @id>=20 & @id<30"), i64::MIN..=29);
+        assert_eq!(bounds("@id<10 | building"), i64::MIN..=i64::MAX);
+        assert_eq!(bounds("!(@id>100)"), i64::MIN..=i64::MAX);
+        assert!(bounds("@id>100 & @id<50").is_empty());
+    }
+
+    #[test]
+    fn negations_may_match_untagged_elements() {
+        assert_eq!(analyze("!name"), (true, None));
+        assert_eq!(analyze("!(highway=primary)"), (true, None));
+        assert_eq!(analyze("!(!name)"), (false, None));
+        assert_eq!(analyze(""), (true, None));
+    }
+
+    #[test]
+    fn normalize_value_lowercases_and_collapses_whitespace() {
+        assert_eq!(normalize_value("  Yes "), "yes");
+        assert_eq!(normalize_value("Main\t  STREET"), "main street");
+        assert_eq!(normalize_value("ÄRA"), "ära");
+        assert_eq!(normalize_value(""), "");
+        assert!(matches!(normalize_value("main street"), Cow::Borrowed(_)));
+    }
+}
@@ -0,0 +1,100 @@
+//! Parse errors of the filter DSL.
+
+use std::fmt;
+
+/// A filter expression that failed to parse: what went wrong, where, and possibly how to fix it.
+///
+/// Displays like a compiler diagnostic, with a caret under the offending part:
+///
+/// ```text
+/// Expected value, got Or
+///   --> filters.yaml:7
+///    |
+///    | highway=|primary
+///    |         ^
+///    = hint: `|` separates values; leave out the extra one
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub message: String,
+    /// The expression as written.
+    pub expression: String,
+    /// Byte offset of the problem in `expression`.
+    pub offset: usize,
+    pub hint: Option<String>,
+    /// Where the expression comes from, like `filters.yaml:7`, when it was read from a file.
+    pub location: Option<String>,
+}
+
+impl ParseError {
+    /// Line and column of the offset, both counted from 1; columns in characters.
+    pub fn line_column(&self) -> (usize, usize) {
+        let before = &self.expression[..self.offset];
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        (
+            before.matches('\n').count() + 1,
+            before[line_start..].chars().count() + 1,
+        )
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self.message)?;
+        if let Some(location) = &self.location {
+            writeln!(f, "  --> {}", location)?;
+        }
+        // Multi-line expressions show the line with the problem
+        let (line, column) = self.line_column();
+        let text = self.expression.lines().nth(line - 1).unwrap_or("");
+        writeln!(f, "   |")?;
+        writeln!(f, "   | {}", text)?;
+        write!(f, "   | {}^", " ".repeat(column - 1))?;
+        if let Some(hint) = &self.hint {
+            write!(f, "\n   = hint: {}", hint)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_points_at_the_offset() {
+        let error = ParseError {
+            message: "Expected value, got Or".into(),
+            expression: "highway=|primary".into(),
+            offset: 8,
+            hint: Some("leave out the extra `|`".into()),
+            location: Some("filters.yaml:7".into()),
+        };
+        assert_eq!(error.line_column(), (1, 9));
+        assert_eq!(
+            error.to_string(),
+            "Expected value, got Or\n  --> filters.yaml:7\n   |\n   | highway=|primary\n   |         ^\n   = hint: leave out the extra `|`"
+        );
+    }
+
+    #[test]
+    fn display_shows_the_line_of_a_multiline_expression() {
+        let error = ParseError {
+            message: "Unexpected token: RParen".into(),
+            expression: "highway\n& name=Straße)".into(),
+            offset: 22,
+            hint: None,
+            location: None,
+        };
+        assert_eq!(error.line_column(), (2, 14));
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "Unexpected token: RParen\n   |\n   | & name=Straße)\n   | {}^",
+                " ".repeat(13)
+            )
+        );
+    }
+}
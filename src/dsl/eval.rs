@@ -1,100 +1,214 @@
 //! Evaluator for the filter DSL AST.
 
-use super::ast::{CompareOp, FilterAst, TagValue};
+use super::ast::{
+    CompareOp, ElementKind, ElementPredicate, FilterAst, MetaField, TagValue, normalize_value,
+};
+use super::spatial;
+use super::units::parse_quantity;
+use crate::metadata::MetadataFields;
+use geo_types::Geometry;
 use std::collections::HashMap;
+use std::hash::BuildHasher;
 
-/// Evaluate a filter AST against a set of tags.
-pub fn evaluate_filter(ast: &FilterAst, tags: &HashMap<String, String>) -> bool {
+/// Read-only access to an element's tags.
+///
+/// Implemented for owned tag maps and for [`InternedTags`](super::InternedTags), which reads
+/// a block's string table directly so non-matching elements never allocate.
+pub trait TagLookup {
+    fn get(&self, key: &str) -> Option<&str>;
+
+    fn contains_key(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Whether any key satisfies `predicate`, for key patterns such as `~"^name:"`.
+    fn any_key(&self, predicate: &mut dyn FnMut(&str) -> bool) -> bool;
+}
+
+impl<S: BuildHasher> TagLookup for HashMap<String, String, S> {
+    fn get(&self, key: &str) -> Option<&str> {
+        HashMap::get(self, key).map(String::as_str)
+    }
+
+    fn contains_key(&self, key: &str) -> bool {
+        HashMap::contains_key(self, key)
+    }
+
+    fn any_key(&self, predicate: &mut dyn FnMut(&str) -> bool) -> bool {
+        self.keys().any(|key| predicate(key))
+    }
+}
+
+/// The element being filtered, for predicates such as `@type` and `@version`.
+#[derive(Clone, Copy)]
+pub struct ElementContext<'a> {
+    pub kind: ElementKind,
+    pub id: i64,
+    /// Whether a way's first and last node are the same
+    pub closed: bool,
+    /// Only needs to be present when the filter [uses metadata](super::FilterAnalysis::uses_metadata)
+    pub metadata: Option<&'a MetadataFields>,
+    /// The element's shape for spatial predicates: a point for nodes, and for ways a polygon
+    /// when closed and a line otherwise. Spatial predicates are undecided while it is `None`.
+    pub geometry: Option<&'a Geometry<f64>>,
+}
+
+/// Evaluate a filter AST against a set of tags. Element predicates never match.
+pub fn evaluate_filter<T: TagLookup + ?Sized>(ast: &FilterAst, tags: &T) -> bool {
+    evaluate(ast, tags, None) == Some(true)
+}
+
+/// Evaluate a filter AST against an element and its tags. `None` means the result depends
+/// on spatial predicates and the element's geometry isn't known yet.
+pub fn evaluate_element_filter<T: TagLookup + ?Sized>(
+    ast: &FilterAst,
+    tags: &T,
+    element: &ElementContext,
+) -> Option<bool> {
+    evaluate(ast, tags, Some(element))
+}
+
+/// Three-valued evaluation, so that a filter whose spatial predicates are still undecided can
+/// reject an element on its tags alone, e.g. `building & @area>100` for a road.
+fn evaluate<T: TagLookup + ?Sized>(
+    ast: &FilterAst,
+    tags: &T,
+    element: Option<&ElementContext>,
+) -> Option<bool> {
     match ast {
-        FilterAst::True => true,
+        FilterAst::True => Some(true),
+        FilterAst::False => Some(false),
 
         FilterAst::TagExists { key, negated } => {
             let exists = tags.contains_key(key);
-            if *negated { !exists } else { exists }
+            Some(if *negated { !exists } else { exists })
         }
 
-        FilterAst::TagMatch { key, values } => match tags.get(key) {
+        FilterAst::TagMatch {
+            key,
+            values,
+            normalize,
+            list,
+        } => Some(match tags.get(key) {
             None => false,
-            Some(actual) => values.iter().any(|v| match_value(v, actual)),
+            Some(actual) => items(actual, *list).any(|item| {
+                if *normalize {
+                    let item = normalize_value(item);
+                    values.iter().any(|v| match_value(v, key, &item))
+                } else {
+                    values.iter().any(|v| match_value(v, key, item))
+                }
+            }),
+        }),
+
+        FilterAst::KeyMatch(pattern) => Some(tags.any_key(&mut |key| pattern.is_match(key))),
+
+        FilterAst::NumericCompare {
+            key,
+            op,
+            value,
+            list,
+        } => Some(match tags.get(key) {
+            None => false,
+            // Try to parse the tag value as a number
+            Some(actual) => items(actual, *list)
+                .filter_map(|item| parse_quantity(key, item))
+                .any(|actual_num| compare(*op, actual_num, *value)),
+        }),
+
+        FilterAst::Element(predicate) => match element {
+            Some(element) => match_element(predicate, element),
+            None => Some(false),
         },
 
-        FilterAst::NumericCompare { key, op, value } => {
-            match tags.get(key) {
-                None => false,
-                Some(actual) => {
-                    // Try to parse the tag value as a number
-                    match parse_numeric(actual) {
-                        None => false,
-                        Some(actual_num) => compare(*op, actual_num, *value),
-                    }
+        FilterAst::And(exprs) => {
+            let mut result = Some(true);
+            for expr in exprs {
+                match evaluate(expr, tags, element) {
+                    Some(false) => return Some(false),
+                    None => result = None,
+                    Some(true) => {}
                 }
             }
+            result
         }
 
-        FilterAst::And(exprs) => exprs.iter().all(|e| evaluate_filter(e, tags)),
-
-        FilterAst::Or(exprs) => exprs.iter().any(|e| evaluate_filter(e, tags)),
-
-        FilterAst::Not(inner) => !evaluate_filter(inner, tags),
-    }
-}
+        FilterAst::Or(exprs) => {
+            let mut result = Some(false);
+            for expr in exprs {
+                match evaluate(expr, tags, element) {
+                    Some(true) => return Some(true),
+                    None => result = None,
+                    Some(false) => {}
+                }
+            }
+            result
+        }
 
-/// Match a TagValue against an actual value.
-fn match_value(pattern: &TagValue, actual: &str) -> bool {
-    match pattern {
-        TagValue::Any => true,
-        TagValue::Exact(expected) => actual == expected,
-        TagValue::Glob(pattern) => glob_match(pattern, actual),
+        FilterAst::Not(inner) => evaluate(inner, tags, element).map(|matched| !matched),
     }
 }
 
-/// Simple glob matching (supports * at start, end, or both).
-fn glob_match(pattern: &str, actual: &str) -> bool {
-    if pattern == "*" {
-        return true;
-    }
-
-    let starts_star = pattern.starts_with('*');
-    let ends_star = pattern.ends_with('*');
-
-    match (starts_star, ends_star) {
-        (true, true) => {
-            // *foo* - contains
-            let inner = &pattern[1..pattern.len() - 1];
-            actual.contains(inner)
+/// `None` for spatial predicates while the geometry is unknown.
+fn match_element(predicate: &ElementPredicate, element: &ElementContext) -> Option<bool> {
+    let matched = match predicate {
+        ElementPredicate::Type(kinds) => kinds.contains(&element.kind),
+        ElementPredicate::Closed => element.closed,
+        ElementPredicate::Compare { field, op, value } => {
+            let actual = match field {
+                MetaField::Id => Some(element.id),
+                MetaField::Version => element.metadata.and_then(|m| m.version),
+                MetaField::Changeset => element.metadata.and_then(|m| m.changeset),
+                MetaField::Uid => element.metadata.and_then(|m| m.uid),
+                MetaField::Timestamp => element.metadata.and_then(|m| m.timestamp_millis),
+            };
+            actual.is_some_and(|actual| compare_int(*op, actual, *value))
         }
-        (true, false) => {
-            // *foo - ends with
-            let suffix = &pattern[1..];
-            actual.ends_with(suffix)
+        ElementPredicate::User(values) => element
+            .metadata
+            .and_then(|m| m.user.as_deref())
+            .is_some_and(|user| values.iter().any(|v| match_value(v, "@user", user))),
+        ElementPredicate::Within(region) => region.contains(element.geometry?),
+        ElementPredicate::Intersects(region) => region.intersects(element.geometry?),
+        ElementPredicate::Area { op, value } => {
+            compare(*op, spatial::area(element.geometry?), *value)
         }
-        (false, true) => {
-            // foo* - starts with
-            let prefix = &pattern[..pattern.len() - 1];
-            actual.starts_with(prefix)
+        ElementPredicate::Length { op, value } => {
+            compare(*op, spatial::length(element.geometry?), *value)
         }
-        (false, false) => {
-            // No wildcards - exact match
-            actual == pattern
-        }
-    }
+    };
+    Some(matched)
 }
 
-/// Parse a numeric value from a string.
-/// Handles common OSM patterns like "50 mph", "30", "5.5".
-fn parse_numeric(s: &str) -> Option<f64> {
-    // Try direct parse first
-    if let Ok(n) = s.parse::<f64>() {
-        return Some(n);
-    }
+/// The `;`-separated items of a list value, trimmed, or the whole value when it isn't matched
+/// as a list.
+fn items(value: &str, list: bool) -> impl Iterator<Item = &str> {
+    value
+        .split(move |c| list && c == ';')
+        .map(move |item| if list { item.trim() } else { item })
+}
 
-    // Try to extract leading number (e.g., "50 mph" -> 50)
-    let numeric_part: String = s
-        .chars()
-        .take_while(|c| c.is_ascii_digit() || *c == '.' || *c == '-')
-        .collect();
+/// Match a TagValue against an actual value of `key`, which decides how ranges read units.
+fn match_value(pattern: &TagValue, key: &str, actual: &str) -> bool {
+    match pattern {
+        TagValue::Any => true,
+        TagValue::Exact(expected) => actual == expected,
+        TagValue::Glob(pattern) => pattern.is_match(actual),
+        TagValue::Regex(pattern) => pattern.is_match(actual),
+        TagValue::Range { min, max } => parse_quantity(key, actual)
+            .is_some_and(|n| min.is_none_or(|min| n >= min) && max.is_none_or(|max| n <= max)),
+    }
+}
 
-    numeric_part.parse::<f64>().ok()
+fn compare_int(op: CompareOp, left: i64, right: i64) -> bool {
+    match op {
+        CompareOp::Eq => left == right,
+        CompareOp::Ne => left != right,
+        CompareOp::Lt => left < right,
+        CompareOp::Le => left <= right,
+        CompareOp::Gt => left > right,
+        CompareOp::Ge => left >= right,
+    }
 }
 
 /// Apply a comparison operator.
@@ -113,6 +227,7 @@ fn compare(op: CompareOp, left: f64, right: f64) -> bool {
 mod tests {
     use super::*;
     use crate::dsl::parse_filter;
+    use crate::metadata::parse_timestamp_millis;
 
     fn tags(pairs: &[(&str, &str)]) -> HashMap<String, String> {
         pairs
@@ -150,6 +265,26 @@ mod tests {
         assert!(!evaluate_filter(&ast, &tags(&[("highway", "tertiary")])));
     }
 
+    #[test]
+    fn test_normalized_match() {
+        let ast = parse_filter("oneway^=yes|TRUE").unwrap();
+        for value in ["yes", "Yes", " YES ", "true", "True"] {
+            assert!(
+                evaluate_filter(&ast, &tags(&[("oneway", value)])),
+                "{value:?}"
+            );
+        }
+        assert!(!evaluate_filter(&ast, &tags(&[("oneway", "no")])));
+        assert!(!evaluate_filter(&ast, &tags(&[("oneway", "y es")])));
+
+        let ast = parse_filter(r#"name^="main  street" | name^=*PLATZ"#).unwrap();
+        assert!(evaluate_filter(&ast, &tags(&[("name", "Main\tStreet ")])));
+        assert!(evaluate_filter(&ast, &tags(&[("name", "Marktplatz")])));
+        // `=` stays exact
+        let ast = parse_filter("oneway=yes").unwrap();
+        assert!(!evaluate_filter(&ast, &tags(&[("oneway", "Yes")])));
+    }
+
     #[test]
     fn test_numeric_comparison() {
         let ast = parse_filter("lanes>=2").unwrap();
@@ -166,6 +301,81 @@ mod tests {
         assert!(!evaluate_filter(&ast, &tags(&[("maxspeed", "30")])));
     }
 
+    #[test]
+    fn test_units_are_converted() {
+        let ast = parse_filter("maxspeed<50").unwrap();
+        assert!(!evaluate_filter(&ast, &tags(&[("maxspeed", "40 mph")])));
+        assert!(evaluate_filter(&ast, &tags(&[("maxspeed", "30 mph")])));
+        assert!(!evaluate_filter(
+            &ast,
+            &tags(&[("maxspeed", "30 furlongs")])
+        ));
+        // Lengths aren't speeds
+        assert!(!evaluate_filter(&ast, &tags(&[("maxspeed", "40 m")])));
+
+        // Keys without units compare the leading number, as before
+        let ast = parse_filter("lanes>=2").unwrap();
+        assert!(evaluate_filter(&ast, &tags(&[("lanes", "2;3")])));
+        assert!(evaluate_filter(&ast, &tags(&[("lanes", "3 lanes")])));
+
+        let ast = parse_filter("maxheight>=3").unwrap();
+        assert!(evaluate_filter(&ast, &tags(&[("maxheight", "10'6\"")])));
+        assert!(!evaluate_filter(&ast, &tags(&[("maxheight", "9 ft")])));
+        assert!(evaluate_filter(&ast, &tags(&[("maxheight", "3,2")])));
+    }
+
+    #[test]
+    fn test_ranges() {
+        let ast = parse_filter("lanes=2..4").unwrap();
+        assert!(evaluate_filter(&ast, &tags(&[("lanes", "2")])));
+        assert!(evaluate_filter(&ast, &tags(&[("lanes", "4")])));
+        assert!(!evaluate_filter(&ast, &tags(&[("lanes", "5")])));
+        assert!(!evaluate_filter(&ast, &tags(&[("lanes", "two")])));
+
+        let ast = parse_filter("maxweight=..3.5|none").unwrap();
+        assert!(evaluate_filter(&ast, &tags(&[("maxweight", "7000 lbs")])));
+        assert!(!evaluate_filter(&ast, &tags(&[("maxweight", "7.5")])));
+        assert!(evaluate_filter(&ast, &tags(&[("maxweight", "none")])));
+    }
+
+    #[test]
+    fn test_list_matching() {
+        let ast = parse_filter("cuisine[]=pizza").unwrap();
+        assert!(evaluate_filter(
+            &ast,
+            &tags(&[("cuisine", "burger; pizza")])
+        ));
+        assert!(evaluate_filter(&ast, &tags(&[("cuisine", "pizza")])));
+        assert!(!evaluate_filter(
+            &ast,
+            &tags(&[("cuisine", "pizzeria;kebab")])
+        ));
+        // Without the suffix the whole value is compared
+        let ast = parse_filter("cuisine=pizza").unwrap();
+        assert!(!evaluate_filter(
+            &ast,
+            &tags(&[("cuisine", "burger;pizza")])
+        ));
+
+        let ast = parse_filter("cuisine[]^=Pizza").unwrap();
+        assert!(evaluate_filter(&ast, &tags(&[("cuisine", "Burger;PIZZA")])));
+
+        let ast = parse_filter("lanes[]>=3").unwrap();
+        assert!(evaluate_filter(&ast, &tags(&[("lanes", "2;3")])));
+        assert!(!evaluate_filter(&ast, &tags(&[("lanes", "1;2")])));
+        assert!(!evaluate_filter(
+            &parse_filter("lanes>=3").unwrap(),
+            &tags(&[("lanes", "2;3")])
+        ));
+
+        let ast = parse_filter("!cuisine[]=pizza").unwrap();
+        assert!(evaluate_filter(&ast, &tags(&[("cuisine", "burger")])));
+        assert!(!evaluate_filter(
+            &ast,
+            &tags(&[("cuisine", "burger;pizza")])
+        ));
+    }
+
     #[test]
     fn test_and() {
         let ast = parse_filter("highway=primary & lanes>=2").unwrap();
@@ -191,6 +401,169 @@ mod tests {
         assert!(!evaluate_filter(&ast, &tags(&[("highway", "tertiary")])));
     }
 
+    #[test]
+    fn test_regex_match() {
+        let ast = parse_filter(r#"ref~"^A[0-9]+$""#).unwrap();
+        assert!(evaluate_filter(&ast, &tags(&[("ref", "A1")])));
+        assert!(evaluate_filter(&ast, &tags(&[("ref", "A42")])));
+        assert!(!evaluate_filter(&ast, &tags(&[("ref", "A1a")])));
+        assert!(!evaluate_filter(&ast, &tags(&[("name", "A1")])));
+
+        // Unanchored patterns match anywhere in the value
+        let ast = parse_filter(r#"name~"[Ss]treet""#).unwrap();
+        assert!(evaluate_filter(&ast, &tags(&[("name", "Main Street")])));
+    }
+
+    #[test]
+    fn test_negated_regex_match() {
+        let ast = parse_filter(r#"ref!~"^A""#).unwrap();
+        assert!(evaluate_filter(&ast, &tags(&[("ref", "B7")])));
+        assert!(!evaluate_filter(&ast, &tags(&[("ref", "A7")])));
+        // Like `!ref`, a missing tag doesn't match the pattern
+        assert!(evaluate_filter(&ast, &tags(&[("name", "x")])));
+    }
+
+    #[test]
+    fn test_key_regex() {
+        let ast = parse_filter(r#"~"^name:""#).unwrap();
+        assert!(evaluate_filter(&ast, &tags(&[("name:en", "Foo")])));
+        assert!(!evaluate_filter(&ast, &tags(&[("name", "Foo")])));
+
+        let ast = parse_filter(r#"highway & !~"^name""#).unwrap();
+        assert!(evaluate_filter(&ast, &tags(&[("highway", "service")])));
+        assert!(!evaluate_filter(
+            &ast,
+            &tags(&[("highway", "service"), ("name:de", "Weg")])
+        ));
+    }
+
+    fn element(kind: ElementKind, metadata: Option<&MetadataFields>) -> ElementContext<'_> {
+        ElementContext {
+            kind,
+            id: 42,
+            closed: false,
+            metadata,
+            geometry: None,
+        }
+    }
+
+    fn element_matches(
+        ast: &FilterAst,
+        tags: &HashMap<String, String>,
+        element: &ElementContext,
+    ) -> bool {
+        evaluate_element_filter(ast, tags, element) == Some(true)
+    }
+
+    fn metadata(user: &str, version: i64, timestamp: &str) -> MetadataFields {
+        MetadataFields {
+            id: 42,
+            visible: Some(true),
+            version: Some(version),
+            changeset: Some(7),
+            timestamp: Some(timestamp.to_string()),
+            timestamp_millis: parse_timestamp_millis(timestamp),
+            uid: Some(3),
+            user: Some(user.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_element_type_and_closed() {
+        let building = tags(&[("building", "yes")]);
+        let ast = parse_filter("@type=way & @closed & building").unwrap();
+        let closed_way = ElementContext {
+            closed: true,
+            ..element(ElementKind::Way, None)
+        };
+        assert!(element_matches(&ast, &building, &closed_way));
+        assert!(!element_matches(
+            &ast,
+            &building,
+            &element(ElementKind::Way, None)
+        ));
+        assert!(!element_matches(
+            &ast,
+            &building,
+            &element(ElementKind::Node, None)
+        ));
+        // Without an element, element predicates never match
+        assert!(!evaluate_filter(&ast, &building));
+
+        let ast = parse_filter("@id>=42 & @id<43").unwrap();
+        assert!(element_matches(
+            &ast,
+            &building,
+            &element(ElementKind::Relation, None)
+        ));
+    }
+
+    #[test]
+    fn test_metadata_predicates() {
+        let tags = tags(&[("amenity", "cafe")]);
+        let recent = metadata("alice", 3, "2024-03-01T12:00:00Z");
+        let old = metadata("bob", 1, "2019-05-01T00:00:00Z");
+        let recent = element(ElementKind::Node, Some(&recent));
+        let old = element(ElementKind::Node, Some(&old));
+
+        let ast = parse_filter("@timestamp>=2024-01-01 & @user=alice").unwrap();
+        assert!(element_matches(&ast, &tags, &recent));
+        assert!(!element_matches(&ast, &tags, &old));
+
+        let ast = parse_filter(r#"@version>=3 | @user~"^b""#).unwrap();
+        assert!(element_matches(&ast, &tags, &recent));
+        assert!(element_matches(&ast, &tags, &old));
+
+        let ast = parse_filter("@changeset=7 & @uid!=4").unwrap();
+        assert!(element_matches(&ast, &tags, &old));
+
+        // Missing metadata fails the comparison rather than guessing
+        let ast = parse_filter("@version<100").unwrap();
+        assert!(!element_matches(
+            &ast,
+            &tags,
+            &element(ElementKind::Node, None)
+        ));
+    }
+
+    #[test]
+    fn test_spatial_predicates_wait_for_the_geometry() {
+        let building = tags(&[("building", "yes")]);
+        let road = tags(&[("highway", "service")]);
+        let way = element(ElementKind::Way, None);
+
+        let ast = parse_filter("building & @area>1000").unwrap();
+        assert_eq!(evaluate_element_filter(&ast, &building, &way), None);
+        // Decided by the tags alone
+        assert_eq!(evaluate_element_filter(&ast, &road, &way), Some(false));
+        let ast = parse_filter("highway | !(@length>10)").unwrap();
+        assert_eq!(evaluate_element_filter(&ast, &road, &way), Some(true));
+        assert_eq!(evaluate_element_filter(&ast, &building, &way), None);
+
+        // About 111 m x 111 m
+        let square = Geometry::Polygon(geo::Rect::new((0.0, 0.0), (0.001, 0.001)).to_polygon());
+        let with_geometry = ElementContext {
+            geometry: Some(&square),
+            ..way
+        };
+        for (expr, expected) in [
+            ("building & @area>1000", true),
+            ("building & @area>20000", false),
+            ("@length>=400 & @length<500", true),
+            ("@within(bbox:-1,-1,1,1)", true),
+            ("@within(bbox:0.0005,0,1,1)", false),
+            ("@intersects(bbox:0.0005,0,1,1)", true),
+            ("!@intersects(bbox:5,5,6,6)", true),
+        ] {
+            let ast = parse_filter(expr).unwrap();
+            assert_eq!(
+                evaluate_element_filter(&ast, &building, &with_geometry),
+                Some(expected),
+                "{expr}"
+            );
+        }
+    }
+
     #[test]
     fn test_glob() {
         let ast = parse_filter("highway=*_link").unwrap();
@@ -201,4 +574,56 @@ mod tests {
         assert!(evaluate_filter(&ast, &tags(&[("highway", "trunk_link")])));
         assert!(!evaluate_filter(&ast, &tags(&[("highway", "motorway")])));
     }
+
+    #[test]
+    fn test_glob_inner_stars_and_classes() {
+        let ast = parse_filter("highway=*_link_*").unwrap();
+        assert!(evaluate_filter(
+            &ast,
+            &tags(&[("highway", "motorway_link_x")])
+        ));
+        assert!(!evaluate_filter(
+            &ast,
+            &tags(&[("highway", "motorway_link")])
+        ));
+
+        let ast = parse_filter("name=a*b").unwrap();
+        assert!(evaluate_filter(&ast, &tags(&[("name", "ab")])));
+        assert!(evaluate_filter(&ast, &tags(&[("name", "a to b")])));
+        assert!(!evaluate_filter(&ast, &tags(&[("name", "a to c")])));
+
+        let ast = parse_filter("ref=A?[0-9]").unwrap();
+        assert!(evaluate_filter(&ast, &tags(&[("ref", "A17")])));
+        assert!(evaluate_filter(&ast, &tags(&[("ref", "Ax1")])));
+        assert!(!evaluate_filter(&ast, &tags(&[("ref", "A1")])));
+        assert!(!evaluate_filter(&ast, &tags(&[("ref", "A1x")])));
+
+        let ast = parse_filter("ref=[^AB]*").unwrap();
+        assert!(evaluate_filter(&ast, &tags(&[("ref", "M5")])));
+        assert!(!evaluate_filter(&ast, &tags(&[("ref", "A5")])));
+
+        // A value that is one bracketed word isn't a class
+        let ast = parse_filter("name=[disused]").unwrap();
+        assert!(evaluate_filter(&ast, &tags(&[("name", "[disused]")])));
+        assert!(!evaluate_filter(&ast, &tags(&[("name", "d")])));
+
+        // Regex metacharacters are literal
+        let ast = parse_filter("ref=1.*").unwrap();
+        assert!(evaluate_filter(&ast, &tags(&[("ref", "1.5")])));
+        assert!(!evaluate_filter(&ast, &tags(&[("ref", "15")])));
+    }
+
+    #[test]
+    fn test_key_glob() {
+        let ast = parse_filter("name:*").unwrap();
+        assert!(evaluate_filter(&ast, &tags(&[("name:fr", "Cologne")])));
+        assert!(!evaluate_filter(&ast, &tags(&[("name", "Köln")])));
+
+        let ast = parse_filter("building & !addr:*").unwrap();
+        assert!(evaluate_filter(&ast, &tags(&[("building", "yes")])));
+        assert!(!evaluate_filter(
+            &ast,
+            &tags(&[("building", "yes"), ("addr:street", "Main Street")])
+        ));
+    }
 }
@@ -0,0 +1,343 @@
+//! Filter evaluation against a PBF block's string table.
+//!
+//! Element tags in a block are pairs of indices into the block's string table. Resolving the
+//! filter's keys to indices once per block lets elements be rejected by comparing integers,
+//! without building an owned tag map.
+
+use super::ast::{FilterAnalysis, FilterAst};
+use super::eval::{ElementContext, TagLookup, evaluate_element_filter};
+use geo_types::Geometry;
+use std::cell::OnceCell;
+use std::collections::HashMap;
+
+/// A filter bound to one block's string table.
+pub struct BlockFilter<'a> {
+    filter: &'a FilterAst,
+    strings: &'a [Vec<u8>],
+    /// Filter keys with their string-table index, `None` if the block never uses the string
+    keys: Vec<(&'a str, Option<u32>)>,
+    /// Index of the whole string table, built on the first lookup of a key the filter doesn't use
+    string_index: OnceCell<HashMap<&'a [u8], u32>>,
+    matches_empty: bool,
+    uses_element: bool,
+    uses_metadata: bool,
+    uses_geometry: bool,
+    /// Indices of the filter's required keys present in this block (see [`FilterAnalysis`](super::FilterAnalysis))
+    required: Option<Vec<u32>>,
+}
+
+impl<'a> BlockFilter<'a> {
+    /// Bind `filter` to a block, given its [`analyze`](FilterAst::analyze) result computed
+    /// once per run.
+    pub fn new(
+        filter: &'a FilterAst,
+        analysis: &'a FilterAnalysis,
+        strings: &'a [Vec<u8>],
+    ) -> Self {
+        let mut keys: Vec<(&str, Option<u32>)> = analysis
+            .keys
+            .iter()
+            .map(|key| (key.as_str(), None))
+            .collect();
+
+        if !keys.is_empty() {
+            // The string table is deduplicated, so each key appears at most once
+            for (index, string) in strings.iter().enumerate() {
+                if let Some(slot) = keys
+                    .iter_mut()
+                    .find(|(key, slot)| slot.is_none() && key.as_bytes() == string.as_slice())
+                {
+                    slot.1 = Some(index as u32);
+                }
+            }
+        }
+
+        let required = analysis.required_keys.as_ref().map(|required| {
+            keys.iter()
+                .filter(|(key, _)| required.iter().any(|r| r == key))
+                .filter_map(|(_, index)| *index)
+                .collect()
+        });
+
+        Self {
+            filter,
+            strings,
+            keys,
+            string_index: OnceCell::new(),
+            matches_empty: analysis.matches_empty,
+            uses_element: analysis.uses_element,
+            uses_metadata: analysis.uses_metadata,
+            uses_geometry: analysis.uses_geometry,
+            required,
+        }
+    }
+
+    /// Whether any element of this block can match. False when the filter needs a key
+    /// that the block's string table doesn't contain, e.g. blocks of untagged nodes.
+    pub fn may_match(&self) -> bool {
+        match &self.required {
+            Some(required) => !required.is_empty(),
+            None => true,
+        }
+    }
+
+    /// Whether the filter has element predicates such as `@type` or `@closed`.
+    pub fn uses_element(&self) -> bool {
+        self.uses_element
+    }
+
+    /// Whether [`ElementContext::metadata`] must be set for the filter to see it.
+    pub fn uses_metadata(&self) -> bool {
+        self.uses_metadata
+    }
+
+    /// Check an element that passed [`matches`](Self::matches) again with its geometry, built
+    /// by `shape` only if the filter has spatial predicates.
+    pub fn matches_geometry(
+        &self,
+        pairs: &[(u32, u32)],
+        element: &ElementContext,
+        shape: impl FnOnce() -> Geometry<f64>,
+    ) -> bool {
+        if !self.uses_geometry {
+            return true;
+        }
+        let geometry = shape();
+        let element = ElementContext {
+            geometry: Some(&geometry),
+            ..*element
+        };
+        self.matches(pairs, &element)
+    }
+
+    /// View an element's raw `(key, value)` string-table indices as tags.
+    pub fn tags<'t>(&'t self, pairs: &'t [(u32, u32)]) -> InternedTags<'t, 'a> {
+        InternedTags { block: self, pairs }
+    }
+
+    /// Evaluate the filter against an element and its raw tag indices. Without the element's
+    /// geometry, spatial predicates are undecided and the element is kept if it may match.
+    pub fn matches(&self, pairs: &[(u32, u32)], element: &ElementContext) -> bool {
+        if pairs.is_empty() && !self.uses_element {
+            return self.matches_empty;
+        }
+        if let Some(required) = &self.required
+            && !pairs.iter().any(|(k, _)| required.contains(k))
+        {
+            return false;
+        }
+        evaluate_element_filter(self.filter, &self.tags(pairs), element) != Some(false)
+    }
+
+    fn string(&self, index: u32) -> Option<&str> {
+        let bytes = self.strings.get(index as usize)?;
+        std::str::from_utf8(bytes).ok()
+    }
+
+    fn key_index(&self, key: &str) -> Option<u32> {
+        match self.keys.iter().find(|(k, _)| *k == key) {
+            Some((_, index)) => *index,
+            // Not a filter key; look it up in the whole table
+            None => self
+                .string_index
+                .get_or_init(|| {
+                    let mut index = HashMap::with_capacity(self.strings.len());
+                    for (i, string) in self.strings.iter().enumerate() {
+                        index.entry(string.as_slice()).or_insert(i as u32);
+                    }
+                    index
+                })
+                .get(key.as_bytes())
+                .copied(),
+        }
+    }
+}
+
+/// Tags of a single element, borrowed from the block's string table.
+pub struct InternedTags<'t, 'a> {
+    block: &'t BlockFilter<'a>,
+    pairs: &'t [(u32, u32)],
+}
+
+impl TagLookup for InternedTags<'_, '_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        let key_index = self.block.key_index(key)?;
+        let (_, value_index) = self.pairs.iter().find(|(k, _)| *k == key_index)?;
+        self.block.string(*value_index)
+    }
+
+    fn contains_key(&self, key: &str) -> bool {
+        match self.block.key_index(key) {
+            Some(key_index) => self.pairs.iter().any(|(k, _)| *k == key_index),
+            None => false,
+        }
+    }
+
+    fn any_key(&self, predicate: &mut dyn FnMut(&str) -> bool) -> bool {
+        self.pairs
+            .iter()
+            .filter_map(|(k, _)| self.block.string(*k))
+            .any(predicate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dsl::{ElementKind, evaluate_filter, parse_filter};
+    use std::collections::HashMap;
+
+    const NODE: ElementContext<'static> = ElementContext {
+        kind: ElementKind::Node,
+        id: 1,
+        closed: false,
+        metadata: None,
+        geometry: None,
+    };
+
+    /// A block string table; index 0 is the empty string, as in real PBF blocks.
+    fn string_table(strings: &[&str]) -> Vec<Vec<u8>> {
+        std::iter::once("")
+            .chain(strings.iter().copied())
+            .map(|s| s.as_bytes().to_vec())
+            .collect()
+    }
+
+    fn owned(strings: &[Vec<u8>], pairs: &[(u32, u32)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| {
+                let key = String::from_utf8(strings[*k as usize].clone()).unwrap();
+                let value = String::from_utf8(strings[*v as usize].clone()).unwrap();
+                (key, value)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn matches_agree_with_owned_tag_maps() {
+        let strings = string_table(&["highway", "primary", "lanes", "3", "name", "Main St"]);
+        let elements: Vec<Vec<(u32, u32)>> =
+            vec![vec![(1, 2), (3, 4)], vec![(1, 2)], vec![(5, 6)], vec![]];
+
+        for expr in [
+            "highway=primary & lanes>=2",
+            "highway",
+            "!name",
+            "name=Main*",
+            "highway=secondary | name",
+            "!(highway=primary)",
+            r#"name~"St$""#,
+            r#"~"^lan""#,
+            r#"highway!~"^sec""#,
+            "name=M?in*[Tt]",
+            "lan*",
+            "highway & !na?e",
+            "name^=main*",
+            "lanes=2..4",
+            "name[]~St",
+        ] {
+            let ast = parse_filter(expr).unwrap();
+            let analysis = ast.analyze();
+            let block = BlockFilter::new(&ast, &analysis, &strings);
+            for pairs in &elements {
+                assert_eq!(
+                    block.matches(pairs, &NODE),
+                    evaluate_filter(&ast, &owned(&strings, pairs)),
+                    "{expr} on {pairs:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn keys_missing_from_block_never_match() {
+        let strings = string_table(&["amenity", "cafe"]);
+        let ast = parse_filter("shop").unwrap();
+        let analysis = ast.analyze();
+        let block = BlockFilter::new(&ast, &analysis, &strings);
+
+        assert!(!block.matches(&[(1, 2)], &NODE));
+        assert!(!block.tags(&[(1, 2)]).contains_key("shop"));
+    }
+
+    #[test]
+    fn key_and_value_sharing_a_string_resolve_separately() {
+        // "yes" is stored once and used as a value by two different keys
+        let strings = string_table(&["building", "yes", "yes_key"]);
+        let ast = parse_filter("building=yes").unwrap();
+        let analysis = ast.analyze();
+        let block = BlockFilter::new(&ast, &analysis, &strings);
+        let tags = block.tags(&[(1, 2), (3, 2)]);
+
+        assert_eq!(tags.get("building"), Some("yes"));
+        assert_eq!(tags.get("yes_key"), Some("yes"));
+        assert_eq!(tags.get("yes"), None);
+        assert!(block.matches(&[(1, 2)], &NODE));
+    }
+
+    #[test]
+    fn blocks_without_required_keys_cannot_match() {
+        // Only user names and the empty string, as in a block of untagged nodes
+        let strings = string_table(&["mapper"]);
+        let ast = parse_filter("highway | railway=rail").unwrap();
+        assert!(!BlockFilter::new(&ast, &ast.analyze(), &strings).may_match());
+
+        let strings = string_table(&["mapper", "railway", "rail"]);
+        assert!(BlockFilter::new(&ast, &ast.analyze(), &strings).may_match());
+
+        let ast = parse_filter("!highway").unwrap();
+        assert!(BlockFilter::new(&ast, &ast.analyze(), &string_table(&[])).may_match());
+    }
+
+    #[test]
+    fn untagged_elements_use_the_empty_match_result() {
+        let strings = string_table(&["highway", "primary"]);
+
+        let ast = parse_filter("highway").unwrap();
+        assert!(!BlockFilter::new(&ast, &ast.analyze(), &strings).matches(&[], &NODE));
+
+        let ast = parse_filter("!highway").unwrap();
+        assert!(BlockFilter::new(&ast, &ast.analyze(), &strings).matches(&[], &NODE));
+    }
+
+    #[test]
+    fn untagged_elements_are_evaluated_for_element_predicates() {
+        let strings = string_table(&["building", "yes"]);
+        let closed_way = ElementContext {
+            kind: ElementKind::Way,
+            closed: true,
+            ..NODE
+        };
+
+        let ast = parse_filter("@type=way & !@closed").unwrap();
+        let analysis = ast.analyze();
+        let block = BlockFilter::new(&ast, &analysis, &strings);
+        assert!(!block.matches(&[], &NODE));
+        assert!(!block.matches(&[], &closed_way));
+        let open_way = ElementContext {
+            closed: false,
+            ..closed_way
+        };
+        assert!(block.matches(&[], &open_way));
+
+        let ast = parse_filter("@closed & building").unwrap();
+        let analysis = ast.analyze();
+        let block = BlockFilter::new(&ast, &analysis, &strings);
+        assert!(block.matches(&[(1, 2)], &closed_way));
+        assert!(!block.matches(&[], &closed_way));
+        assert!(!block.matches(&[(1, 2)], &NODE));
+    }
+
+    #[test]
+    fn invalid_utf8_values_are_treated_as_missing() {
+        let mut strings = string_table(&["name"]);
+        strings.push(vec![0xff, 0xfe]);
+        let ast = parse_filter("name=*").unwrap();
+        let analysis = ast.analyze();
+        let block = BlockFilter::new(&ast, &analysis, &strings);
+
+        assert_eq!(block.tags(&[(1, 2)]).get("name"), None);
+        assert!(block.tags(&[(1, 2)]).contains_key("name"));
+    }
+}
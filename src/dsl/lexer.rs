@@ -1,6 +1,7 @@
 //! Lexer/tokenizer for the filter DSL.
 
-use winnow::ascii::space0;
+use super::error::ParseError;
+use winnow::ascii::multispace0;
 use winnow::combinator::alt;
 use winnow::prelude::*;
 use winnow::token::take_while;
@@ -9,16 +10,20 @@ use winnow::token::take_while;
 #[derive(Debug, Clone, PartialEq)]
 pub enum Token {
     // Identifiers and values
-    Ident(String), // tag key or value
-    Number(f64),   // numeric literal
+    Ident(String),  // tag key or value
+    Number(String), // numeric literal, as written
+    Str(String),    // 'quoted' or "quoted" literal
 
     // Operators
-    Eq, // =
-    Ne, // !=
-    Lt, // <
-    Le, // <=
-    Gt, // >
-    Ge, // >=
+    Eq,       // =
+    Ne,       // !=
+    Lt,       // <
+    Le,       // <=
+    Gt,       // >
+    Ge,       // >=
+    Match,    // ~
+    NotMatch, // !~
+    NormEq,   // ^=
 
     // Boolean operators
     And, // &
@@ -37,44 +42,88 @@ pub enum Token {
 // Manually define PResult for resilience against winnow version changes
 type PResult<T> = Result<T, winnow::error::ErrMode<winnow::error::ContextError>>;
 
-/// Lex an identifier (tag key or value).
-/// Allowed: alphanumeric, underscore, colon, dash, and wildcard *
-fn lex_ident(input: &mut &str) -> PResult<Token> {
-    let first = take_while(1.., |c: char| {
-        c.is_alphabetic() || c == '_' || c == ':' || c == '*' || c == '-'
-    })
-    .parse_next(input)?;
-    let rest = take_while(0.., |c: char| {
-        c.is_alphanumeric() || c == '_' || c == ':' || c == '-' || c == '*'
-    })
-    .parse_next(input)?;
-
-    let s = format!("{}{}", first, rest);
-    if s == "*" {
+/// Characters that end a bare word: whitespace, operators, parentheses and quotes.
+fn is_word_char(c: char) -> bool {
+    !c.is_whitespace()
+        && !matches!(
+            c,
+            '&' | '|' | '(' | ')' | '!' | '=' | '<' | '>' | '~' | '"' | '\''
+        )
+}
+
+/// Lex a bare word (tag key or value), e.g. `highway`, `12a`, `A1.1` or `Mo-Fr;Sa`.
+/// A lone `*` is the wildcard, and a plain decimal like `2` or `-1.5` is a number.
+/// A word ends before `^=`, so `oneway^=yes` needs no spaces.
+fn lex_word(input: &mut &str) -> PResult<Token> {
+    let start: &str = input;
+    let mut word = take_while(1.., is_word_char).parse_next(input)?;
+    // `^` is a word character, but not as the start of `^=`
+    if input.starts_with('=')
+        && let Some(stem) = word.strip_suffix('^')
+        && !stem.is_empty()
+    {
+        word = stem;
+        *input = &start[stem.len()..];
+    }
+    if word == "*" {
         Ok(Token::Star)
+    } else if is_number(word) {
+        Ok(Token::Number(word.to_string()))
     } else {
-        Ok(Token::Ident(s))
+        Ok(Token::Ident(word.to_string()))
     }
 }
 
-/// Lex a number (integer or float).
-fn lex_number(input: &mut &str) -> PResult<Token> {
-    let neg = winnow::combinator::opt('-').parse_next(input)?;
-    let num_str = take_while(1.., |c: char| c.is_ascii_digit() || c == '.').parse_next(input)?;
-    let full = if neg.is_some() {
-        format!("-{}", num_str)
-    } else {
-        num_str.to_string()
-    };
-    let n: f64 = full
-        .parse()
-        .map_err(|_| winnow::error::ErrMode::Backtrack(winnow::error::ContextError::default()))?;
-    Ok(Token::Number(n))
+/// Whether a word is a plain decimal number. Exponents, `inf` and `nan` stay words so that
+/// values like `1e5` aren't rewritten.
+fn is_number(word: &str) -> bool {
+    let digits = word.strip_prefix('-').unwrap_or(word);
+    digits.chars().any(|c| c.is_ascii_digit())
+        && digits.chars().all(|c| c.is_ascii_digit() || c == '.')
+        && word.parse::<f64>().is_ok()
+}
+
+/// Lex a single- or double-quoted string. `\\`, `\"`, `\'`, `\n` and `\t` are escapes;
+/// other backslashes are kept as written, so regex escapes like `\d` need no doubling.
+fn lex_string(input: &mut &str) -> PResult<Token> {
+    let quote = alt(('"', '\'')).parse_next(input)?;
+    let text: &str = input;
+    let mut value = String::new();
+    let mut chars = text.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        if c == quote {
+            *input = &text[i + 1..];
+            return Ok(Token::Str(value));
+        }
+        if c != '\\' {
+            value.push(c);
+            continue;
+        }
+        match chars.peek().map(|&(_, next)| next) {
+            Some(next @ ('\\' | '"' | '\'')) => {
+                chars.next();
+                value.push(next);
+            }
+            Some('n') => {
+                chars.next();
+                value.push('\n');
+            }
+            Some('t') => {
+                chars.next();
+                value.push('\t');
+            }
+            _ => value.push('\\'),
+        }
+    }
+    // Unterminated string
+    Err(winnow::error::ErrMode::Cut(
+        winnow::error::ContextError::default(),
+    ))
 }
 
 /// Lex a single token.
 fn lex_token(input: &mut &str) -> PResult<Token> {
-    space0.parse_next(input)?;
+    multispace0.parse_next(input)?;
 
     if input.is_empty() {
         return Ok(Token::Eof);
@@ -83,10 +132,13 @@ fn lex_token(input: &mut &str) -> PResult<Token> {
     alt((
         // Multi-char operators first
         "!=".value(Token::Ne),
+        "!~".value(Token::NotMatch),
         "<=".value(Token::Le),
         ">=".value(Token::Ge),
+        "^=".value(Token::NormEq),
         // Single-char operators
         "=".value(Token::Eq),
+        "~".value(Token::Match),
         "<".value(Token::Lt),
         ">".value(Token::Gt),
         "&".value(Token::And),
@@ -94,28 +146,48 @@ fn lex_token(input: &mut &str) -> PResult<Token> {
         "!".value(Token::Not),
         "(".value(Token::LParen),
         ")".value(Token::RParen),
-        // Number (before ident to catch negative numbers)
-        lex_number,
-        // Identifier (includes star if part of string)
-        lex_ident,
+        lex_string,
+        // Keys, values and numbers
+        lex_word,
     ))
     .parse_next(input)
 }
 
-/// Tokenize the entire input.
-pub fn tokenize(input: &str) -> Result<Vec<Token>, String> {
+/// Tokenize the entire input into tokens and the byte offsets where they start.
+pub fn tokenize(input: &str) -> Result<Vec<(Token, usize)>, ParseError> {
     let mut remaining = input;
     let mut tokens = Vec::new();
 
     loop {
+        let before = remaining;
         match lex_token(&mut remaining) {
             Ok(Token::Eof) => break,
-            Ok(tok) => tokens.push(tok),
-            Err(e) => return Err(format!("Lexer error at '{}': {:?}", remaining, e)),
+            Ok(tok) => {
+                let skipped = before.len() - before.trim_start().len();
+                tokens.push((tok, input.len() - before.len() + skipped));
+            }
+            Err(_) => {
+                let offset = input.len() - before.trim_start().len();
+                let quoted = input[offset..].starts_with(['"', '\'']);
+                return Err(ParseError {
+                    message: if quoted {
+                        "Unterminated string".to_string()
+                    } else {
+                        format!(
+                            "Unexpected character {:?}",
+                            input[offset..].chars().next().unwrap_or(' ')
+                        )
+                    },
+                    expression: input.to_string(),
+                    offset,
+                    hint: quoted.then(|| "close the quote, or escape it with `\\`".to_string()),
+                    location: None,
+                });
+            }
         }
     }
 
-    tokens.push(Token::Eof);
+    tokens.push((Token::Eof, input.len()));
     Ok(tokens)
 }
 
@@ -123,9 +195,17 @@ pub fn tokenize(input: &str) -> Result<Vec<Token>, String> {
 mod tests {
     use super::*;
 
+    fn lex(input: &str) -> Vec<Token> {
+        tokenize(input)
+            .unwrap()
+            .into_iter()
+            .map(|(token, _)| token)
+            .collect()
+    }
+
     #[test]
     fn test_simple_tokens() {
-        let tokens = tokenize("highway=primary").unwrap();
+        let tokens = lex("highway=primary");
         assert_eq!(
             tokens,
             vec![
@@ -139,13 +219,13 @@ mod tests {
 
     #[test]
     fn test_numeric_comparison() {
-        let tokens = tokenize("lanes>=2").unwrap();
+        let tokens = lex("lanes>=2");
         assert_eq!(
             tokens,
             vec![
                 Token::Ident("lanes".into()),
                 Token::Ge,
-                Token::Number(2.0),
+                Token::Number("2".into()),
                 Token::Eof,
             ]
         );
@@ -153,7 +233,7 @@ mod tests {
 
     #[test]
     fn test_complex_expression() {
-        let tokens = tokenize("highway=primary & lanes>=2").unwrap();
+        let tokens = lex("highway=primary & lanes>=2");
         assert_eq!(
             tokens,
             vec![
@@ -163,7 +243,122 @@ mod tests {
                 Token::And,
                 Token::Ident("lanes".into()),
                 Token::Ge,
-                Token::Number(2.0),
+                Token::Number("2".into()),
+                Token::Eof,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_regex_operators_and_strings() {
+        let tokens = lex(r#"ref~"^A\d+$" & name!~"x\"y""#);
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Ident("ref".into()),
+                Token::Match,
+                Token::Str(r"^A\d+$".into()),
+                Token::And,
+                Token::Ident("name".into()),
+                Token::NotMatch,
+                Token::Str("x\"y".into()),
+                Token::Eof,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_quoted_strings_and_escapes() {
+        let tokens = lex(r#"name="Main Street" | name='O\'Brien\\s' | x="a\tb""#);
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Ident("name".into()),
+                Token::Eq,
+                Token::Str("Main Street".into()),
+                Token::Or,
+                Token::Ident("name".into()),
+                Token::Eq,
+                Token::Str("O'Brien\\s".into()),
+                Token::Or,
+                Token::Ident("x".into()),
+                Token::Eq,
+                Token::Str("a\tb".into()),
+                Token::Eof,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_words_starting_with_digits() {
+        let tokens = lex("addr:housenumber=12a & ref=A1.1 & opening_hours=Mo-Fr;Sa/Su & x=007");
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Ident("addr:housenumber".into()),
+                Token::Eq,
+                Token::Ident("12a".into()),
+                Token::And,
+                Token::Ident("ref".into()),
+                Token::Eq,
+                Token::Ident("A1.1".into()),
+                Token::And,
+                Token::Ident("opening_hours".into()),
+                Token::Eq,
+                Token::Ident("Mo-Fr;Sa/Su".into()),
+                Token::And,
+                Token::Ident("x".into()),
+                Token::Eq,
+                Token::Number("007".into()),
+                Token::Eof,
+            ]
+        );
+        assert_eq!(
+            lex("ele>-1.5 & ref=1e5")[2..],
+            [
+                Token::Number("-1.5".into()),
+                Token::And,
+                Token::Ident("ref".into()),
+                Token::Eq,
+                Token::Ident("1e5".into()),
+                Token::Eof,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unterminated_string_is_an_error() {
+        let err = tokenize(r#"ref~"^A"#).unwrap_err();
+        assert_eq!(
+            (err.message.as_str(), err.offset),
+            ("Unterminated string", 4)
+        );
+        assert!(tokenize("name='x").is_err());
+    }
+
+    #[test]
+    fn test_token_offsets() {
+        let offsets: Vec<usize> = tokenize("  name = 'Straße' |x")
+            .unwrap()
+            .into_iter()
+            .map(|(_, offset)| offset)
+            .collect();
+        assert_eq!(offsets, vec![2, 7, 9, 19, 20, 21]);
+    }
+
+    #[test]
+    fn test_normalized_eq() {
+        let tokens = lex("oneway^=yes & ref=^A");
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Ident("oneway".into()),
+                Token::NormEq,
+                Token::Ident("yes".into()),
+                Token::And,
+                Token::Ident("ref".into()),
+                Token::Eq,
+                Token::Ident("^A".into()),
                 Token::Eof,
             ]
         );
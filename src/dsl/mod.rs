@@ -6,17 +6,46 @@
 //!   tag=value               - exact match
 //!   tag=val1|val2|val3      - match any value
 //!   tag=*                   - any value (same as existence)
-//!   tag>=n, tag>n, etc.     - numeric comparison
+//!   tag^=value              - match ignoring case and extra whitespace
+//!   tag=*_link, tag=A?[0-9] - glob: `*`, `?` and `[a-z]`/`[!a-z]` classes, whole value
+//!   key:*                   - any key matching the glob exists, e.g. `name:*`
+//!   tag="any value"         - quoted value ('single' or "double"), matched literally
+//!   tag>=n, tag>n, etc.     - numeric comparison; `30 mph`, `10 ft`, `2,5` convert to
+//!                             km/h, metres and tonnes, and so may `n` (`maxspeed<30mph`)
+//!   tag=2..4, tag=..50      - inclusive numeric range, also in value lists
+//!   tag[]=value, tag[]>=n   - any `;`-separated item of the value matches
+//!   tag~"regex"             - value matches the regex (unanchored)
+//!   tag!~"regex"            - value doesn't match, or tag missing
+//!   ~"regex"                - any key matches the regex
+//!   @type=node|way          - element type (node, way, relation)
+//!   @closed                 - way whose first and last node are the same
+//!   @id>n, @version>=n      - element id and metadata (@changeset, @uid) comparisons
+//!   @user=name, @user~"re"  - user name, like a tag value
+//!   @timestamp>=2024-01-01  - last edit; a date (midnight UTC) or an RFC 3339 timestamp
+//!   @within(bbox:w,s,e,n)   - geometry inside a bbox or `file:area.geojson` polygons
+//!   @intersects(file:f)     - geometry touches the region
+//!   @area>n, @length<n      - geodesic area (m²) and length or perimeter (m)
 //!   expr1 & expr2           - AND
 //!   expr1 | expr2           - OR (note: lower precedence than &)
 //!   !expr                   - NOT
 //!   (expr)                  - grouping
+//!
+//! Unquoted keys and values may contain anything but whitespace, quotes, parentheses and
+//! operator characters, so `addr:housenumber=12a` and `ref=A1.1` need no quotes.
 
 mod ast;
+mod error;
+mod eval;
+mod interned;
 mod lexer;
+mod optimize;
 mod parser;
-mod eval;
+mod spatial;
+mod units;
 
 pub use ast::*;
+pub use error::ParseError;
+pub use eval::{ElementContext, TagLookup, evaluate_element_filter, evaluate_filter};
+pub use interned::{BlockFilter, InternedTags};
 pub use parser::parse_filter;
-pub use eval::evaluate_filter;
+pub use spatial::Region;
@@ -0,0 +1,443 @@
+//! Rewrites filters into equivalent ones that are cheaper to evaluate.
+//!
+//! Every rewrite keeps the three-valued result of [`evaluate_element_filter`], so spatial
+//! predicates that are still undecided before geometry construction stay undecided.
+//!
+//! [`evaluate_element_filter`]: super::evaluate_element_filter
+
+use super::ast::{ElementPredicate, FilterAst, KeyPattern, TagValue};
+
+impl FilterAst {
+    /// Optimize the filter: flatten and deduplicate And/Or, merge `k=a | k=b` into `k=a|b`,
+    /// fold constants and contradictions like `k=a & k=b` or `k & !k`, and order the operands
+    /// of And/Or so that cheap, decisive ones run first.
+    pub fn optimize(self) -> Self {
+        match self {
+            FilterAst::And(exprs) => optimize_and(exprs),
+            FilterAst::Or(exprs) => optimize_or(exprs),
+            FilterAst::Not(inner) => match inner.optimize() {
+                FilterAst::True => FilterAst::False,
+                FilterAst::False => FilterAst::True,
+                FilterAst::Not(inner) => *inner,
+                FilterAst::TagExists { key, negated } => FilterAst::TagExists {
+                    key,
+                    negated: !negated,
+                },
+                inner => FilterAst::Not(Box::new(inner)),
+            },
+            FilterAst::TagMatch {
+                key,
+                values,
+                normalize,
+                list,
+            } => {
+                // `k=*` is `k`, also for lists: any value has at least one item
+                if values.contains(&TagValue::Any) {
+                    return FilterAst::TagExists {
+                        key,
+                        negated: false,
+                    };
+                }
+                FilterAst::TagMatch {
+                    key,
+                    values: dedup(values),
+                    normalize,
+                    list,
+                }
+            }
+            other => other,
+        }
+    }
+}
+
+fn optimize_and(exprs: Vec<FilterAst>) -> FilterAst {
+    let mut operands = Vec::new();
+    for expr in exprs {
+        match expr.optimize() {
+            FilterAst::And(inner) => operands.extend(inner),
+            FilterAst::True => {}
+            FilterAst::False => return FilterAst::False,
+            other => operands.push(other),
+        }
+    }
+    let mut operands = dedup(operands);
+
+    // `k=a|b & k=b|c` is `k=b`, and `k=a & k=b` matches nothing
+    let mut i = 0;
+    while i < operands.len() {
+        let mut j = i + 1;
+        while j < operands.len() {
+            if let Some(values) = intersect(&operands[i], &operands[j]) {
+                if values.is_empty() {
+                    return FilterAst::False;
+                }
+                if let FilterAst::TagMatch { values: merged, .. } = &mut operands[i] {
+                    *merged = values;
+                }
+                operands.remove(j);
+            } else {
+                j += 1;
+            }
+        }
+        i += 1;
+    }
+
+    // A key can't be both present and missing
+    if operands.iter().any(|expr| {
+        present_key(expr)
+            .is_some_and(|key| operands.iter().any(|other| missing_key(other) == Some(key)))
+    }) || has_complement(&operands)
+    {
+        return FilterAst::False;
+    }
+
+    // `k & k=a` is `k=a`
+    let implied: Vec<String> = operands
+        .iter()
+        .filter(|expr| !matches!(expr, FilterAst::TagExists { .. }))
+        .filter_map(present_key)
+        .map(str::to_string)
+        .collect();
+    operands.retain(|expr| {
+        !matches!(expr, FilterAst::TagExists { key, negated: false } if implied.contains(key))
+    });
+
+    // Operands that are cheap and likely to reject come first
+    operands.sort_by(|a, b| {
+        let rank = |expr: &FilterAst| cost(expr) / (1.0 - probability(expr)).max(f64::EPSILON);
+        rank(a).total_cmp(&rank(b))
+    });
+    match operands.len() {
+        0 => FilterAst::True,
+        1 => operands.pop().unwrap(),
+        _ => FilterAst::And(operands),
+    }
+}
+
+fn optimize_or(exprs: Vec<FilterAst>) -> FilterAst {
+    let mut operands: Vec<FilterAst> = Vec::new();
+    for expr in exprs {
+        match expr.optimize() {
+            FilterAst::Or(inner) => operands.extend(inner),
+            FilterAst::False => {}
+            FilterAst::True => return FilterAst::True,
+            other => operands.push(other),
+        }
+    }
+    let mut operands = dedup(operands);
+
+    // `k=a | k=b` is `k=a|b`
+    let mut i = 0;
+    while i < operands.len() {
+        let mut merged = false;
+        let mut j = i + 1;
+        while j < operands.len() {
+            if same_match(&operands[i], &operands[j]) {
+                if let (
+                    FilterAst::TagMatch { values: more, .. },
+                    FilterAst::TagMatch { values, .. },
+                ) = (operands.remove(j), &mut operands[i])
+                {
+                    values.extend(more);
+                }
+                merged = true;
+            } else {
+                j += 1;
+            }
+        }
+        if merged {
+            // Drops repeated values, and turns a merged `k=*` into `k`
+            operands[i] = std::mem::replace(&mut operands[i], FilterAst::True).optimize();
+        }
+        i += 1;
+    }
+    let mut operands = dedup(operands);
+
+    let existing: Vec<String> = operands
+        .iter()
+        .filter(|expr| matches!(expr, FilterAst::TagExists { .. }))
+        .filter_map(present_key)
+        .map(str::to_string)
+        .collect();
+
+    // Either `k` or `!k` holds
+    if operands
+        .iter()
+        .filter_map(missing_key)
+        .any(|key| existing.iter().any(|k| k == key))
+        || has_complement(&operands)
+    {
+        return FilterAst::True;
+    }
+
+    // `k | k=a` is `k`
+    operands.retain(|expr| {
+        matches!(expr, FilterAst::TagExists { .. })
+            || present_key(expr).is_none_or(|key| !existing.iter().any(|k| k == key))
+    });
+
+    // Operands that are cheap and likely to match come first
+    operands.sort_by(|a, b| {
+        let rank = |expr: &FilterAst| cost(expr) / probability(expr).max(f64::EPSILON);
+        rank(a).total_cmp(&rank(b))
+    });
+    match operands.len() {
+        0 => FilterAst::False,
+        1 => operands.pop().unwrap(),
+        _ => FilterAst::Or(operands),
+    }
+}
+
+/// Remove repeated items, keeping the first of each.
+fn dedup<T: PartialEq>(items: Vec<T>) -> Vec<T> {
+    let mut unique = Vec::with_capacity(items.len());
+    for item in items {
+        if !unique.contains(&item) {
+            unique.push(item);
+        }
+    }
+    unique
+}
+
+/// The key an expression can only match with.
+fn present_key(expr: &FilterAst) -> Option<&str> {
+    match expr {
+        FilterAst::TagExists {
+            key,
+            negated: false,
+        }
+        | FilterAst::TagMatch { key, .. }
+        | FilterAst::NumericCompare { key, .. } => Some(key),
+        _ => None,
+    }
+}
+
+/// The key an expression requires to be missing.
+fn missing_key(expr: &FilterAst) -> Option<&str> {
+    match expr {
+        FilterAst::TagExists { key, negated: true } => Some(key),
+        _ => None,
+    }
+}
+
+/// Whether an operand and its negation are both present. Only decided expressions count:
+/// with an undecided spatial predicate, `x & !x` is undecided too.
+fn has_complement(operands: &[FilterAst]) -> bool {
+    operands.iter().any(|expr| {
+        matches!(expr, FilterAst::Not(inner)
+            if !inner.analyze().uses_geometry && operands.contains(inner.as_ref()))
+    })
+}
+
+/// Whether two expressions are value matches on the same key, in the same mode.
+fn same_match(a: &FilterAst, b: &FilterAst) -> bool {
+    match (a, b) {
+        (
+            FilterAst::TagMatch {
+                key,
+                normalize,
+                list,
+                ..
+            },
+            FilterAst::TagMatch {
+                key: other_key,
+                normalize: other_normalize,
+                list: other_list,
+                ..
+            },
+        ) => key == other_key && normalize == other_normalize && list == other_list,
+        _ => false,
+    }
+}
+
+/// The values both matches accept, when they are exact values of the same single-valued key.
+fn intersect(a: &FilterAst, b: &FilterAst) -> Option<Vec<TagValue>> {
+    let exact = |values: &[TagValue]| values.iter().all(|v| matches!(v, TagValue::Exact(_)));
+    match (a, b) {
+        (
+            FilterAst::TagMatch {
+                values,
+                list: false,
+                ..
+            },
+            FilterAst::TagMatch {
+                values: other,
+                list: false,
+                ..
+            },
+        ) if same_match(a, b) && exact(values) && exact(other) => Some(
+            values
+                .iter()
+                .filter(|v| other.contains(v))
+                .cloned()
+                .collect(),
+        ),
+        _ => None,
+    }
+}
+
+/// Rough relative cost of evaluating an expression once.
+fn cost(expr: &FilterAst) -> f64 {
+    let value_cost = |value: &TagValue| match value {
+        TagValue::Any | TagValue::Exact(_) => 1.0,
+        TagValue::Range { .. } => 2.0,
+        TagValue::Glob(_) | TagValue::Regex(_) => 5.0,
+    };
+    match expr {
+        FilterAst::True | FilterAst::False => 0.0,
+        FilterAst::TagExists { .. } => 1.0,
+        FilterAst::TagMatch { values, list, .. } => {
+            let values: f64 = values.iter().map(value_cost).sum();
+            1.0 + if *list { 2.0 * values } else { values }
+        }
+        FilterAst::NumericCompare { .. } => 3.0,
+        // Scans every key of the element
+        FilterAst::KeyMatch(KeyPattern::Glob(_)) => 10.0,
+        FilterAst::KeyMatch(KeyPattern::Regex(_)) => 20.0,
+        FilterAst::Element(predicate) => match predicate {
+            ElementPredicate::Type(_) | ElementPredicate::Closed => 0.5,
+            ElementPredicate::Compare { .. } => 1.0,
+            ElementPredicate::User(values) => 1.0 + values.iter().map(value_cost).sum::<f64>(),
+            // Geometry math, once the geometry is built
+            ElementPredicate::Area { .. } | ElementPredicate::Length { .. } => 50.0,
+            ElementPredicate::Within(_) | ElementPredicate::Intersects(_) => 100.0,
+        },
+        FilterAst::And(exprs) | FilterAst::Or(exprs) => exprs.iter().map(cost).sum(),
+        FilterAst::Not(inner) => cost(inner),
+    }
+}
+
+/// Rough guess at the share of elements an expression matches. Most keys are rare, so tag
+/// tests are assumed to be selective.
+fn probability(expr: &FilterAst) -> f64 {
+    match expr {
+        FilterAst::True => 1.0,
+        FilterAst::False => 0.0,
+        FilterAst::TagExists { negated, .. } => {
+            if *negated {
+                0.9
+            } else {
+                0.1
+            }
+        }
+        FilterAst::TagMatch { values, .. } => (0.02 * values.len() as f64).min(0.1),
+        FilterAst::NumericCompare { .. } => 0.05,
+        FilterAst::KeyMatch(_) => 0.1,
+        FilterAst::Element(_) => 0.5,
+        FilterAst::And(exprs) => exprs.iter().map(probability).product(),
+        FilterAst::Or(exprs) => 1.0 - exprs.iter().map(|e| 1.0 - probability(e)).product::<f64>(),
+        FilterAst::Not(inner) => 1.0 - probability(inner),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::dsl::{FilterAst, evaluate_filter, parse_filter};
+    use std::collections::HashMap;
+
+    fn optimized(expr: &str) -> FilterAst {
+        parse_filter(expr).unwrap().optimize()
+    }
+
+    #[test]
+    fn merges_value_matches_on_one_key() {
+        assert_eq!(
+            optimized("highway=primary | highway=secondary | highway=primary"),
+            parse_filter("highway=primary|secondary").unwrap()
+        );
+        assert_eq!(
+            optimized("highway=primary|secondary & highway=secondary|tertiary"),
+            parse_filter("highway=secondary").unwrap()
+        );
+        // Different modes stay apart
+        assert!(matches!(
+            optimized("name=A | name^=b"),
+            FilterAst::Or(exprs) if exprs.len() == 2
+        ));
+    }
+
+    #[test]
+    fn folds_constants_and_contradictions() {
+        assert_eq!(
+            optimized("highway=primary & highway=secondary"),
+            FilterAst::False
+        );
+        assert_eq!(optimized("highway & !highway"), FilterAst::False);
+        assert_eq!(optimized("!name & name=x"), FilterAst::False);
+        assert_eq!(optimized("name | !name"), FilterAst::True);
+        assert_eq!(optimized("(a & !a) | b"), parse_filter("b").unwrap());
+        assert_eq!(optimized("!!building"), parse_filter("building").unwrap());
+        assert_eq!(optimized("shop=*"), parse_filter("shop").unwrap());
+        // Lists may hold both values
+        assert!(matches!(
+            optimized("cuisine[]=pizza & cuisine[]=burger"),
+            FilterAst::And(_)
+        ));
+        // Undecided before the geometry is built, so not a contradiction
+        assert!(matches!(
+            optimized("@area>10 & !@area>10"),
+            FilterAst::And(_)
+        ));
+    }
+
+    #[test]
+    fn removes_implied_operands() {
+        assert_eq!(
+            optimized("highway & highway=primary & highway"),
+            parse_filter("highway=primary").unwrap()
+        );
+        assert_eq!(
+            optimized("shop | shop=bakery | lanes>2 | lanes"),
+            parse_filter("shop | lanes").unwrap()
+        );
+    }
+
+    #[test]
+    fn orders_cheap_operands_first() {
+        assert_eq!(
+            optimized(r#"name~"Straße$" & @within(bbox:0,0,1,1) & highway"#),
+            FilterAst::And(vec![
+                parse_filter("highway").unwrap(),
+                parse_filter(r#"name~"Straße$""#).unwrap(),
+                parse_filter("@within(bbox:0,0,1,1)").unwrap(),
+            ])
+        );
+    }
+
+    #[test]
+    fn optimized_filters_agree_with_the_originals() {
+        let elements: Vec<HashMap<String, String>> = [
+            vec![],
+            vec![("highway", "primary")],
+            vec![("highway", "secondary"), ("name", "Main St")],
+            vec![("shop", "bakery"), ("lanes", "3")],
+            vec![("cuisine", "pizza;burger")],
+        ]
+        .iter()
+        .map(|tags| {
+            tags.iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        })
+        .collect();
+
+        for expr in [
+            "highway=primary | highway=secondary & name",
+            "!(highway=primary & !name) | shop=bakery",
+            "(highway | shop) & !(highway=primary | lanes>=3)",
+            "cuisine[]=pizza & cuisine[]=burger | name=x & name=y",
+            "highway=*_link | highway=primary | !lanes",
+            "shop & shop=bakery & !name | highway & !highway",
+        ] {
+            let ast = parse_filter(expr).unwrap();
+            let optimized = ast.clone().optimize();
+            for tags in &elements {
+                assert_eq!(
+                    evaluate_filter(&optimized, tags),
+                    evaluate_filter(&ast, tags),
+                    "{expr} on {tags:?}"
+                );
+            }
+        }
+    }
+}
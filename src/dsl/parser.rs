@@ -6,24 +6,65 @@
 //! or_expr    = and_expr ("|" and_expr)*
 //! and_expr   = unary_expr ("&" unary_expr)*
 //! unary_expr = "!" unary_expr | primary
-//! primary    = "(" filter ")" | tag_expr
-//! tag_expr   = IDENT (compare_op value_list)?
+//! primary    = "(" filter ")" | key_regex | tag_expr
+//! key_regex  = ("~" | "!~") STRING
+//! tag_expr   = key (compare_op value_list | "^=" value_list | match_op pattern)?
+//!              (a glob key only alone, a list key never alone)
+//! key        = IDENT | NUMBER | STRING           (IDENT ending in `[]` is a list key)
 //! compare_op = "=" | "!=" | "<" | "<=" | ">" | ">="
+//! match_op   = "~" | "!~"
 //! value_list = value ("|" value)*
-//! value      = IDENT | NUMBER | "*"
+//! value      = IDENT | NUMBER | STRING | "*"     (IDENT with `*`, `?` or `[` is a glob,
+//!                                                IDENT like `2..4` a numeric range)
+//! pattern    = STRING | IDENT | NUMBER
+//!
+//! IDENT is any run of characters other than whitespace, quotes, parentheses and operator
+//! characters, so `12a`, `A1.1` and `Mo-Fr;Sa` are plain values. STRING is single- or
+//! double-quoted; quoted values are always matched literally. `^=` matches values ignoring
+//! case and extra whitespace. A list key like `cuisine[]` matches if any `;`-separated item
+//! of the value does.
 
-use super::ast::{CompareOp, FilterAst, TagValue};
+use super::ast::{
+    CompareOp, ElementKind, ElementPredicate, FilterAst, GlobPattern, KeyPattern, MetaField,
+    RegexPattern, TagValue, normalize_value,
+};
+use super::error::ParseError;
 use super::lexer::{Token, tokenize};
+use super::spatial::Region;
+use super::units::parse_threshold;
+use crate::metadata::parse_timestamp_millis;
+use time::{Date, Month};
 
 /// Parser state.
+///
+/// Errors are plain messages about the last consumed token; [`parse_filter`] turns them into a
+/// [`ParseError`] pointing at that token.
 struct Parser {
     tokens: Vec<Token>,
+    offsets: Vec<usize>,
     pos: usize,
 }
 
 impl Parser {
-    fn new(tokens: Vec<Token>) -> Self {
-        Parser { tokens, pos: 0 }
+    fn new(tokens: Vec<(Token, usize)>) -> Self {
+        let (tokens, offsets) = tokens.into_iter().unzip();
+        Parser {
+            tokens,
+            offsets,
+            pos: 0,
+        }
+    }
+
+    /// A parse error at the last consumed token.
+    fn error(&self, input: &str, message: String) -> ParseError {
+        let index = self.pos.saturating_sub(1).min(self.tokens.len() - 1);
+        ParseError {
+            hint: hint(&self.tokens, index, &message),
+            message,
+            expression: input.to_string(),
+            offset: self.offsets[index],
+            location: None,
+        }
     }
 
     fn peek(&self) -> &Token {
@@ -84,22 +125,31 @@ impl Parser {
         if matches!(self.peek(), Token::Not) {
             self.advance(); // consume !
 
-            // Check if next is an identifier (negated existence) or expression
-            if let Token::Ident(key) = self.peek().clone() {
+            // Check if next is a key (negated existence) or expression
+            if let Token::Ident(key) | Token::Number(key) | Token::Str(key) = self.peek().clone() {
                 // Could be !tag (negated existence) or !(expr)
                 // Peek ahead to see if it's followed by an operator
                 let next_pos = self.pos + 1;
                 let next_tok = self.tokens.get(next_pos);
 
                 match next_tok {
-                    Some(Token::Eq) | Some(Token::Ne) | Some(Token::Lt) | Some(Token::Le)
-                    | Some(Token::Gt) | Some(Token::Ge) => {
+                    Some(Token::Eq)
+                    | Some(Token::Ne)
+                    | Some(Token::Lt)
+                    | Some(Token::Le)
+                    | Some(Token::Gt)
+                    | Some(Token::Ge)
+                    | Some(Token::Match)
+                    | Some(Token::NotMatch)
+                    | Some(Token::NormEq) => {
                         // It's a tag expression, wrap in NOT
                         let inner = self.parse_primary()?;
                         return Ok(FilterAst::Not(Box::new(inner)));
                     }
                     Some(Token::And) | Some(Token::Or) | Some(Token::RParen) | Some(Token::Eof)
-                    | None => {
+                    | None
+                        if !is_key_glob(self.peek()) && !is_element_key(self.peek()) =>
+                    {
                         // Simple negated existence: !tag
                         self.advance(); // consume ident
                         return Ok(FilterAst::TagExists { key, negated: true });
@@ -119,7 +169,7 @@ impl Parser {
         }
     }
 
-    /// Parse primary expression: "(" filter ")" | tag_expr
+    /// Parse primary expression: "(" filter ")" | key_regex | tag_expr
     fn parse_primary(&mut self) -> Result<FilterAst, String> {
         match self.peek().clone() {
             Token::LParen => {
@@ -128,31 +178,99 @@ impl Parser {
                 self.expect(Token::RParen)?;
                 Ok(inner)
             }
-            Token::Ident(_) => self.parse_tag_expr(),
+            Token::Ident(_) | Token::Number(_) | Token::Str(_) => self.parse_tag_expr(),
+            Token::Match => {
+                self.advance(); // consume ~
+                Ok(FilterAst::KeyMatch(KeyPattern::Regex(
+                    self.parse_pattern()?,
+                )))
+            }
+            Token::NotMatch => {
+                self.advance(); // consume !~
+                let pattern = KeyPattern::Regex(self.parse_pattern()?);
+                Ok(FilterAst::Not(Box::new(FilterAst::KeyMatch(pattern))))
+            }
             Token::Eof => Ok(FilterAst::True),
-            other => Err(format!("Unexpected token: {:?}", other)),
+            other => {
+                self.advance(); // point the error at it
+                Err(format!("Unexpected token: {:?}", other))
+            }
         }
     }
 
-    /// Parse tag expression: IDENT (compare_op value_list)?
+    /// Parse tag expression: key (compare_op value_list | match_op pattern)?
     fn parse_tag_expr(&mut self) -> Result<FilterAst, String> {
-        let key = match self.advance() {
-            Token::Ident(k) => k,
-            other => return Err(format!("Expected identifier, got {:?}", other)),
+        let list = is_list_key(self.peek());
+        let key_glob = !list && is_key_glob(self.peek());
+        let element_key = is_element_key(self.peek());
+        let mut key = match self.advance() {
+            Token::Ident(k) | Token::Number(k) | Token::Str(k) => k,
+            other => return Err(format!("Expected tag key, got {:?}", other)),
         };
 
-        // Check for comparison operator
-        let op = match self.peek() {
-            Token::Eq => Some(CompareOp::Eq),
-            Token::Ne => Some(CompareOp::Ne),
-            Token::Lt => Some(CompareOp::Lt),
-            Token::Le => Some(CompareOp::Le),
-            Token::Gt => Some(CompareOp::Gt),
-            Token::Ge => Some(CompareOp::Ge),
-            _ => None,
-        };
+        if element_key {
+            return self.parse_element_predicate(&key);
+        }
+
+        // `name:*`: any tag whose key matches the glob exists
+        if key_glob {
+            if is_operator(self.peek()) {
+                return Err(format!(
+                    "Key glob {:?} can only test for existence; quote the key to match it literally",
+                    key
+                ));
+            }
+            return Ok(FilterAst::KeyMatch(KeyPattern::Glob(GlobPattern::new(
+                &key,
+            )?)));
+        }
+
+        // `cuisine[]=pizza`: match the `;`-separated items of the value
+        if list {
+            if !is_operator(self.peek()) {
+                return Err(format!(
+                    "List key {:?} needs an operator, e.g. {}=value",
+                    key, key
+                ));
+            }
+            key.truncate(key.len() - 2);
+        }
+
+        // Regex match; `!~` also matches elements without the tag, like `!key`
+        if matches!(self.peek(), Token::Match | Token::NotMatch) {
+            let negated = matches!(self.advance(), Token::NotMatch);
+            let values = vec![TagValue::Regex(self.parse_pattern()?)];
+            let matched = FilterAst::TagMatch {
+                key,
+                values,
+                normalize: false,
+                list,
+            };
+            return Ok(if negated {
+                FilterAst::Not(Box::new(matched))
+            } else {
+                matched
+            });
+        }
 
-        match op {
+        // `^=` ignores case and surrounding or repeated whitespace on both sides
+        if matches!(self.peek(), Token::NormEq) {
+            self.advance(); // consume ^=
+            let values = self
+                .parse_value_list(&key)?
+                .into_iter()
+                .map(normalize_pattern)
+                .collect::<Result<_, _>>()?;
+            return Ok(FilterAst::TagMatch {
+                key,
+                values,
+                normalize: true,
+                list,
+            });
+        }
+
+        // Check for comparison operator
+        match compare_op(self.peek()) {
             None => {
                 // Simple existence check
                 Ok(FilterAst::TagExists {
@@ -162,89 +280,448 @@ impl Parser {
             }
             Some(CompareOp::Eq) => {
                 self.advance(); // consume =
-                let values = self.parse_value_list()?;
-                Ok(FilterAst::TagMatch { key, values })
+                let values = self.parse_value_list(&key)?;
+                Ok(FilterAst::TagMatch {
+                    key,
+                    values,
+                    normalize: false,
+                    list,
+                })
             }
             Some(op) => {
                 self.advance(); // consume operator
-                // For non-equality comparisons, expect a number
+                // For non-equality comparisons, expect a number, possibly with a unit
                 match self.advance() {
-                    Token::Number(n) => Ok(FilterAst::NumericCompare { key, op, value: n }),
+                    Token::Number(n) | Token::Ident(n) | Token::Str(n) => {
+                        let value = parse_threshold(&key, &n).ok_or_else(|| {
+                            format!("Expected number after {:?}, got {:?}", op, n)
+                        })?;
+                        Ok(FilterAst::NumericCompare {
+                            key,
+                            op,
+                            value,
+                            list,
+                        })
+                    }
                     other => Err(format!("Expected number after {:?}, got {:?}", op, other)),
                 }
             }
         }
     }
 
+    /// Parse an element predicate after its `@name`: `@type=KINDS`, `@closed`, `@user` with a
+    /// value list or regex, `@within(REGION)`, `@intersects(REGION)`, or
+    /// `@FIELD compare_op VALUE` for metadata fields, `@area` and `@length`.
+    fn parse_element_predicate(&mut self, name: &str) -> Result<FilterAst, String> {
+        let negate = |predicate: ElementPredicate, negated: bool| {
+            let ast = FilterAst::Element(predicate);
+            if negated {
+                FilterAst::Not(Box::new(ast))
+            } else {
+                ast
+            }
+        };
+
+        match name {
+            "@closed" => {
+                if is_operator(self.peek()) {
+                    return Err("@closed takes no value; use !@closed for open ways".to_string());
+                }
+                Ok(FilterAst::Element(ElementPredicate::Closed))
+            }
+            "@type" => {
+                let negated = match self.advance() {
+                    Token::Eq => false,
+                    Token::Ne => true,
+                    other => return Err(format!("Expected = or != after @type, got {:?}", other)),
+                };
+                let mut kinds = vec![self.parse_element_kind()?];
+                // Only element types continue the list, so `@type=node | amenity` is an OR
+                while matches!(self.peek(), Token::Or)
+                    && matches!(
+                        self.tokens.get(self.pos + 1),
+                        Some(Token::Ident(kind)) if kind.parse::<ElementKind>().is_ok()
+                    )
+                {
+                    self.advance(); // consume |
+                    kinds.push(self.parse_element_kind()?);
+                }
+                Ok(negate(ElementPredicate::Type(kinds), negated))
+            }
+            "@within" | "@intersects" => {
+                self.expect(Token::LParen)?;
+                let region = match self.advance() {
+                    Token::Ident(source) | Token::Str(source) => Region::parse(&source)?,
+                    other => {
+                        return Err(format!(
+                            "Expected bbox:... or file:... after {}(, got {:?}",
+                            name, other
+                        ));
+                    }
+                };
+                self.expect(Token::RParen)?;
+                Ok(FilterAst::Element(if name == "@within" {
+                    ElementPredicate::Within(region)
+                } else {
+                    ElementPredicate::Intersects(region)
+                }))
+            }
+            "@area" | "@length" => {
+                let op = compare_op(&self.advance())
+                    .ok_or_else(|| format!("Expected comparison after {}", name))?;
+                let value = match self.advance() {
+                    Token::Number(n) => n.parse().map_err(|_| format!("Invalid number {:?}", n))?,
+                    other => {
+                        return Err(format!(
+                            "Expected number after {}{}, got {:?}",
+                            name, op, other
+                        ));
+                    }
+                };
+                Ok(FilterAst::Element(if name == "@area" {
+                    ElementPredicate::Area { op, value }
+                } else {
+                    ElementPredicate::Length { op, value }
+                }))
+            }
+            "@user" => match self.advance() {
+                Token::Eq => Ok(negate(
+                    ElementPredicate::User(self.parse_value_list("@user")?),
+                    false,
+                )),
+                Token::Ne => Ok(negate(
+                    ElementPredicate::User(self.parse_value_list("@user")?),
+                    true,
+                )),
+                token @ (Token::Match | Token::NotMatch) => {
+                    let values = vec![TagValue::Regex(self.parse_pattern()?)];
+                    Ok(negate(
+                        ElementPredicate::User(values),
+                        token == Token::NotMatch,
+                    ))
+                }
+                other => Err(format!(
+                    "Expected =, !=, ~ or !~ after @user, got {:?}",
+                    other
+                )),
+            },
+            _ => {
+                let field = match name {
+                    "@id" => MetaField::Id,
+                    "@version" => MetaField::Version,
+                    "@changeset" => MetaField::Changeset,
+                    "@uid" => MetaField::Uid,
+                    "@timestamp" => MetaField::Timestamp,
+                    other => {
+                        return Err(format!(
+                            "Unknown element predicate {}; expected @type, @closed, @id, \
+                             @version, @changeset, @uid, @user, @timestamp, @within, \
+                             @intersects, @area or @length",
+                            other
+                        ));
+                    }
+                };
+                let op = compare_op(self.peek())
+                    .ok_or_else(|| format!("Expected comparison after {}", name))?;
+                self.advance(); // consume operator
+                let value = match (field, self.advance()) {
+                    (MetaField::Timestamp, Token::Ident(s) | Token::Str(s) | Token::Number(s)) => {
+                        parse_date_millis(&s)?
+                    }
+                    (_, Token::Number(n)) => n
+                        .parse()
+                        .map_err(|_| format!("Expected integer after {}{}, got {}", name, op, n))?,
+                    (_, other) => {
+                        return Err(format!(
+                            "Expected integer after {}{}, got {:?}",
+                            name, op, other
+                        ));
+                    }
+                };
+                Ok(FilterAst::Element(ElementPredicate::Compare {
+                    field,
+                    op,
+                    value,
+                }))
+            }
+        }
+    }
+
+    fn parse_element_kind(&mut self) -> Result<ElementKind, String> {
+        match self.advance() {
+            Token::Ident(kind) | Token::Str(kind) => kind.parse(),
+            other => Err(format!("Expected element type, got {:?}", other)),
+        }
+    }
+
     /// Parse value list: value ("|" value)*
-    fn parse_value_list(&mut self) -> Result<Vec<TagValue>, String> {
-        let mut values = vec![self.parse_value()?];
+    fn parse_value_list(&mut self, key: &str) -> Result<Vec<TagValue>, String> {
+        let mut values = vec![self.parse_value(key)?];
 
         while matches!(self.peek(), Token::Or) {
+            // Anything but a plain value after "|" starts a new expression, and so does an
+            // element predicate like `@closed`
+            let next = self.tokens.get(self.pos + 1);
+            if !matches!(
+                next,
+                Some(Token::Ident(_))
+                    | Some(Token::Number(_))
+                    | Some(Token::Str(_))
+                    | Some(Token::Star)
+            ) || next.is_some_and(is_element_key)
+            {
+                break;
+            }
+
             // Ambiguity: "highway=primary | lanes=2"
             // The "|" could be ORing the whole expression or just the value.
             // Lookahead: if the next token is an identifier and the token after THAT is an operator,
             // then it's a new expression, so stop parsing the value list.
-            if let Some(Token::Ident(_)) = self.tokens.get(self.pos + 1) {
+            if let Some(Token::Ident(_) | Token::Number(_) | Token::Str(_)) =
+                self.tokens.get(self.pos + 1)
+            {
                 let next_next = self.tokens.get(self.pos + 2);
-                if matches!(
-                    next_next,
-                    Some(Token::Eq)
-                        | Some(Token::Ne)
-                        | Some(Token::Lt)
-                        | Some(Token::Le)
-                        | Some(Token::Gt)
-                        | Some(Token::Ge)
-                ) {
+                if next_next.is_some_and(is_operator) {
                     break; // Stop at boolean OR
                 }
             }
 
             self.advance(); // consume |
-            values.push(self.parse_value()?);
+            values.push(self.parse_value(key)?);
         }
 
         Ok(values)
     }
 
-    /// Parse a single value: IDENT | NUMBER | "*"
-    fn parse_value(&mut self) -> Result<TagValue, String> {
+    /// Parse a single value of `key`: IDENT | NUMBER | STRING | "*"
+    fn parse_value(&mut self, key: &str) -> Result<TagValue, String> {
         match self.advance() {
             Token::Star => Ok(TagValue::Any),
             Token::Ident(s) => {
+                if let Some(range) = parse_range(key, &s) {
+                    return range;
+                }
                 // Check for glob pattern
-                if s.contains('*') {
-                    Ok(TagValue::Glob(s))
+                if GlobPattern::is_glob(&s) {
+                    Ok(TagValue::Glob(GlobPattern::new(&s)?))
                 } else {
                     Ok(TagValue::Exact(s))
                 }
             }
-            Token::Number(n) => Ok(TagValue::Exact(n.to_string())),
+            // Numbers and quoted strings match as written, e.g. `ref=007`
+            Token::Number(s) | Token::Str(s) => Ok(TagValue::Exact(s)),
             other => Err(format!("Expected value, got {:?}", other)),
         }
     }
+
+    /// Parse and compile a regex: STRING | IDENT | NUMBER
+    fn parse_pattern(&mut self) -> Result<RegexPattern, String> {
+        match self.advance() {
+            Token::Str(pattern) | Token::Ident(pattern) | Token::Number(pattern) => {
+                RegexPattern::new(&pattern)
+            }
+            other => Err(format!("Expected quoted regex after ~, got {:?}", other)),
+        }
+    }
+}
+
+/// Whether a token is a comparison or match operator, i.e. follows a key in a tag expression.
+fn is_operator(token: &Token) -> bool {
+    matches!(
+        token,
+        Token::Eq
+            | Token::Ne
+            | Token::Lt
+            | Token::Le
+            | Token::Gt
+            | Token::Ge
+            | Token::Match
+            | Token::NotMatch
+            | Token::NormEq
+    )
+}
+
+/// Parse `min..max`, `min..` or `..max` as a numeric range of `key`. Words with `..` whose
+/// ends aren't numbers are left as plain values; a range whose bounds are reversed is an error.
+fn parse_range(key: &str, word: &str) -> Option<Result<TagValue, String>> {
+    let (min, max) = word.split_once("..")?;
+    let bound = |text: &str| match text {
+        "" => Some(None),
+        _ => parse_threshold(key, text).map(Some),
+    };
+    let (min, max) = (bound(min)?, bound(max)?);
+    Some(match (min, max) {
+        (None, None) => return None,
+        (Some(min), Some(max)) if min > max => Err(format!(
+            "Empty range {:?}; the lower bound comes first",
+            word
+        )),
+        _ => Ok(TagValue::Range { min, max }),
+    })
+}
+
+/// Normalize a `^=` value once at parse time, so matching only normalizes the tag value.
+fn normalize_pattern(value: TagValue) -> Result<TagValue, String> {
+    Ok(match value {
+        TagValue::Exact(text) => TagValue::Exact(normalize_value(&text).into_owned()),
+        TagValue::Glob(glob) => TagValue::Glob(GlobPattern::new(&normalize_value(glob.as_str()))?),
+        other => other,
+    })
+}
+
+fn compare_op(token: &Token) -> Option<CompareOp> {
+    match token {
+        Token::Eq => Some(CompareOp::Eq),
+        Token::Ne => Some(CompareOp::Ne),
+        Token::Lt => Some(CompareOp::Lt),
+        Token::Le => Some(CompareOp::Le),
+        Token::Gt => Some(CompareOp::Gt),
+        Token::Ge => Some(CompareOp::Ge),
+        _ => None,
+    }
+}
+
+/// Whether a token is a key with the list suffix, such as `cuisine[]`.
+fn is_list_key(token: &Token) -> bool {
+    matches!(token, Token::Ident(key) if key.len() > 2 && key.ends_with("[]"))
+}
+
+/// Whether a token names an element predicate, such as `@type`.
+fn is_element_key(token: &Token) -> bool {
+    matches!(token, Token::Ident(key) if key.starts_with('@'))
+}
+
+/// Parse a `@timestamp` operand, either a date (`2024-01-01`, midnight UTC) or an RFC 3339
+/// timestamp, to Unix milliseconds.
+fn parse_date_millis(text: &str) -> Result<i64, String> {
+    if let Some(millis) = parse_timestamp_millis(text) {
+        return Ok(millis);
+    }
+    let invalid = || {
+        format!(
+            "Invalid timestamp {:?}; expected a date like 2024-01-01 or an RFC 3339 timestamp",
+            text
+        )
+    };
+    let mut parts = text.splitn(3, '-').map(str::parse::<i32>);
+    let (Some(Ok(year)), Some(Ok(month)), Some(Ok(day))) =
+        (parts.next(), parts.next(), parts.next())
+    else {
+        return Err(invalid());
+    };
+    let month = u8::try_from(month)
+        .ok()
+        .and_then(|m| Month::try_from(m).ok())
+        .ok_or_else(invalid)?;
+    let day = u8::try_from(day).map_err(|_| invalid())?;
+    let date = Date::from_calendar_date(year, month, day).map_err(|_| invalid())?;
+    Ok(date.midnight().assume_utc().unix_timestamp() * 1000)
+}
+
+/// Whether a token is an unquoted key with glob syntax, such as `name:*`.
+fn is_key_glob(token: &Token) -> bool {
+    matches!(token, Token::Ident(key) if GlobPattern::is_glob(key))
 }
 
 /// Parse a filter DSL string into an AST.
-pub fn parse_filter(input: &str) -> Result<FilterAst, String> {
-    let input = input.trim();
-    if input.is_empty() {
+pub fn parse_filter(input: &str) -> Result<FilterAst, ParseError> {
+    if input.trim().is_empty() {
         return Ok(FilterAst::True);
     }
 
     let tokens = tokenize(input)?;
     let mut parser = Parser::new(tokens);
-    let ast = parser.parse_filter()?;
+    parser
+        .parse_filter()
+        .and_then(|ast| {
+            // Ensure we consumed all tokens
+            match parser.advance() {
+                Token::Eof => Ok(ast),
+                other => Err(format!("Unexpected token after expression: {:?}", other)),
+            }
+        })
+        .map_err(|message| parser.error(input, message))
+}
 
-    // Ensure we consumed all tokens
-    if !matches!(parser.peek(), Token::Eof) {
-        return Err(format!(
-            "Unexpected token after expression: {:?}",
-            parser.peek()
-        ));
+/// A suggestion for an error at `tokens[index]`, from the tokens around it.
+fn hint(tokens: &[Token], index: usize, message: &str) -> Option<String> {
+    let is_value = |token: &Token| {
+        matches!(
+            token,
+            Token::Ident(_) | Token::Number(_) | Token::Str(_) | Token::Star
+        )
+    };
+    let previous = index.checked_sub(1).map(|i| &tokens[i]);
+    let hint = match (previous, &tokens[index]) {
+        (Some(Token::And), Token::And) => "use a single `&` for AND",
+        (Some(Token::Or), Token::Or) => "use a single `|`, both between expressions and values",
+        (Some(Token::Eq | Token::NormEq), Token::Or) => "a value is missing before `|`",
+        (Some(Token::Eq), Token::Eq) => "use a single `=` to compare",
+        (Some(Token::Eq | Token::NormEq), Token::LParen) => {
+            "list values without parentheses, separated by `|`, as in `key=a|b`"
+        }
+        (Some(Token::Ne), token) if is_value(token) => {
+            "`!=` compares numbers; use `!key=value` to exclude a value"
+        }
+        (Some(Token::Lt | Token::Le | Token::Gt | Token::Ge), token) if is_value(token) => {
+            "comparisons take a number, optionally with a unit like `30mph`"
+        }
+        (Some(previous), token) if is_value(previous) && is_value(token) => {
+            "did you mean `|` inside a value list? Quote values that contain spaces"
+        }
+        (_, Token::Eof) if message.contains("RParen") => "a `(` is missing its `)`",
+        (_, Token::RParen) => "this `)` has no matching `(`",
+        (_, Token::Ident(name)) if name.starts_with('@') => {
+            return closest(name, ELEMENT_PREDICATES)
+                .map(|suggestion| format!("did you mean `{}`?", suggestion));
+        }
+        _ => return None,
+    };
+    Some(hint.to_string())
+}
+
+const ELEMENT_PREDICATES: &[&str] = &[
+    "@type",
+    "@closed",
+    "@id",
+    "@version",
+    "@changeset",
+    "@uid",
+    "@user",
+    "@timestamp",
+    "@within",
+    "@intersects",
+    "@area",
+    "@length",
+];
+
+/// The candidate within two edits of `word`, if it isn't one already.
+fn closest<'a>(word: &str, candidates: &[&'a str]) -> Option<&'a str> {
+    if candidates.contains(&word) {
+        return None;
     }
+    candidates
+        .iter()
+        .map(|candidate| (edit_distance(word, candidate), *candidate))
+        .filter(|(distance, _)| *distance <= 2)
+        .min()
+        .map(|(_, candidate)| candidate)
+}
 
-    Ok(ast)
+/// Levenshtein distance in characters.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != *cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
 }
 
 #[cfg(test)]
@@ -283,6 +760,8 @@ mod tests {
             FilterAst::TagMatch {
                 key: "highway".into(),
                 values: vec![TagValue::Exact("primary".into())],
+                normalize: false,
+                list: false,
             }
         );
     }
@@ -299,6 +778,8 @@ mod tests {
                     TagValue::Exact("secondary".into()),
                     TagValue::Exact("tertiary".into()),
                 ],
+                normalize: false,
+                list: false,
             }
         );
     }
@@ -311,6 +792,8 @@ mod tests {
             FilterAst::TagMatch {
                 key: "shop".into(),
                 values: vec![TagValue::Any],
+                normalize: false,
+                list: false,
             }
         );
     }
@@ -324,6 +807,7 @@ mod tests {
                 key: "lanes".into(),
                 op: CompareOp::Ge,
                 value: 2.0,
+                list: false,
             }
         );
     }
@@ -368,4 +852,452 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_regex_match_compiles_pattern() {
+        let ast = parse_filter(r#"ref~"^A[0-9]+$""#).unwrap();
+        assert_eq!(
+            ast,
+            FilterAst::TagMatch {
+                key: "ref".into(),
+                values: vec![TagValue::Regex(RegexPattern::new("^A[0-9]+$").unwrap())],
+                normalize: false,
+                list: false,
+            }
+        );
+    }
+
+    #[test]
+    fn test_negated_regex_and_key_regex() {
+        let ast = parse_filter(r#"ref!~"^A""#).unwrap();
+        assert!(matches!(ast, FilterAst::Not(_)));
+
+        let ast = parse_filter(r#"~"^name:""#).unwrap();
+        assert_eq!(
+            ast,
+            FilterAst::KeyMatch(KeyPattern::Regex(RegexPattern::new("^name:").unwrap()))
+        );
+
+        let ast = parse_filter(r#"highway=primary | ~"^name:""#).unwrap();
+        assert!(matches!(&ast, FilterAst::Or(exprs) if exprs.len() == 2));
+    }
+
+    #[test]
+    fn test_quoted_and_digit_values_are_literal() {
+        let exact = |key: &str, value: &str| FilterAst::TagMatch {
+            key: key.into(),
+            values: vec![TagValue::Exact(value.into())],
+            normalize: false,
+            list: false,
+        };
+        assert_eq!(
+            parse_filter(r#"name="Main Street""#).unwrap(),
+            exact("name", "Main Street")
+        );
+        assert_eq!(
+            parse_filter("addr:housenumber=12a").unwrap(),
+            exact("addr:housenumber", "12a")
+        );
+        assert_eq!(parse_filter("ref=A1.1").unwrap(), exact("ref", "A1.1"));
+        assert_eq!(parse_filter("ref=007").unwrap(), exact("ref", "007"));
+        assert_eq!(
+            parse_filter("destination=Nord;Süd/Ost").unwrap(),
+            exact("destination", "Nord;Süd/Ost")
+        );
+        // A quoted `*` is a literal star, not a wildcard
+        assert_eq!(parse_filter("name='*'").unwrap(), exact("name", "*"));
+        assert_eq!(
+            parse_filter(r#""name:en"="It's (here)""#).unwrap(),
+            exact("name:en", "It's (here)")
+        );
+    }
+
+    #[test]
+    fn test_quoted_values_in_lists_and_negations() {
+        let ast = parse_filter(r#"name="A B"|'C D' | !"x y""#).unwrap();
+        assert_eq!(
+            ast,
+            FilterAst::Or(vec![
+                FilterAst::TagMatch {
+                    key: "name".into(),
+                    values: vec![TagValue::Exact("A B".into()), TagValue::Exact("C D".into()),],
+                    normalize: false,
+                    list: false,
+                },
+                FilterAst::TagExists {
+                    key: "x y".into(),
+                    negated: true,
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn test_globs_compile_at_parse_time() {
+        let ast = parse_filter("highway=*_link|motorway").unwrap();
+        assert_eq!(
+            ast,
+            FilterAst::TagMatch {
+                key: "highway".into(),
+                values: vec![
+                    TagValue::Glob(GlobPattern::new("*_link").unwrap()),
+                    TagValue::Exact("motorway".into()),
+                ],
+                normalize: false,
+                list: false,
+            }
+        );
+        assert!(
+            parse_filter("ref=A?[0-9")
+                .unwrap_err()
+                .message
+                .contains("unclosed")
+        );
+    }
+
+    #[test]
+    fn test_brackets_without_a_class_are_literal() {
+        for value in ["[disused]", "A[0-9", "]", "["] {
+            assert_eq!(
+                parse_filter(&format!("name={value}")).unwrap(),
+                FilterAst::TagMatch {
+                    key: "name".into(),
+                    values: vec![TagValue::Exact(value.into())],
+                    normalize: false,
+                    list: false,
+                },
+                "{value}"
+            );
+        }
+        for glob in ["A[0-9]", "[ps]rimary", "[a-z][0-9]", "[disused]*"] {
+            assert!(GlobPattern::is_glob(glob), "{glob}");
+        }
+    }
+
+    #[test]
+    fn test_ranges_and_unit_thresholds() {
+        assert_eq!(
+            parse_filter("lanes=2..4|..1|6..").unwrap(),
+            FilterAst::TagMatch {
+                key: "lanes".into(),
+                values: vec![
+                    TagValue::Range {
+                        min: Some(2.0),
+                        max: Some(4.0),
+                    },
+                    TagValue::Range {
+                        min: None,
+                        max: Some(1.0),
+                    },
+                    TagValue::Range {
+                        min: Some(6.0),
+                        max: None,
+                    },
+                ],
+                normalize: false,
+                list: false,
+            }
+        );
+        // Not numbers, so a plain value
+        assert_eq!(
+            parse_filter("name=a..b").unwrap(),
+            FilterAst::TagMatch {
+                key: "name".into(),
+                values: vec![TagValue::Exact("a..b".into())],
+                normalize: false,
+                list: false,
+            }
+        );
+        assert!(
+            parse_filter("lanes=4..2")
+                .unwrap_err()
+                .message
+                .contains("Empty range")
+        );
+
+        let threshold = |expr: &str| match parse_filter(expr).unwrap() {
+            FilterAst::NumericCompare { value, .. } => value,
+            other => panic!("{other:?}"),
+        };
+        assert_eq!(threshold("width>=2,5"), 2.5);
+        assert!((threshold("maxspeed<30mph") - 48.28032).abs() < 1e-9);
+        assert_eq!(threshold(r#"height<"10 m""#), 10.0);
+        assert!(parse_filter("lanes>many").is_err());
+    }
+
+    #[test]
+    fn test_list_keys() {
+        assert_eq!(
+            parse_filter("cuisine[]=pizza|burger").unwrap(),
+            FilterAst::TagMatch {
+                key: "cuisine".into(),
+                values: vec![
+                    TagValue::Exact("pizza".into()),
+                    TagValue::Exact("burger".into()),
+                ],
+                normalize: false,
+                list: true,
+            }
+        );
+        assert_eq!(
+            parse_filter("!lanes[]>=3").unwrap(),
+            FilterAst::Not(Box::new(FilterAst::NumericCompare {
+                key: "lanes".into(),
+                op: CompareOp::Ge,
+                value: 3.0,
+                list: true,
+            }))
+        );
+        // Quoted keys are literal
+        assert!(matches!(
+            parse_filter(r#""a[]"=b"#).unwrap(),
+            FilterAst::TagMatch { list: false, .. }
+        ));
+        assert!(
+            parse_filter("cuisine[]")
+                .unwrap_err()
+                .message
+                .contains("needs an operator")
+        );
+    }
+
+    #[test]
+    fn test_normalized_match_normalizes_patterns() {
+        assert_eq!(
+            parse_filter(r#"oneway^=Yes|" TRUE "|*_Link | !name"#).unwrap(),
+            FilterAst::Or(vec![
+                FilterAst::TagMatch {
+                    key: "oneway".into(),
+                    values: vec![
+                        TagValue::Exact("yes".into()),
+                        TagValue::Exact("true".into()),
+                        TagValue::Glob(GlobPattern::new("*_link").unwrap()),
+                    ],
+                    normalize: true,
+                    list: false,
+                },
+                FilterAst::TagExists {
+                    key: "name".into(),
+                    negated: true,
+                },
+            ])
+        );
+        assert!(matches!(
+            parse_filter("!oneway^=yes").unwrap(),
+            FilterAst::Not(_)
+        ));
+    }
+
+    #[test]
+    fn test_key_globs() {
+        let name_glob =
+            || FilterAst::KeyMatch(KeyPattern::Glob(GlobPattern::new("name:*").unwrap()));
+        assert_eq!(parse_filter("name:*").unwrap(), name_glob());
+        assert_eq!(
+            parse_filter("!name:*").unwrap(),
+            FilterAst::Not(Box::new(name_glob()))
+        );
+        assert_eq!(
+            parse_filter("highway & !name:*").unwrap(),
+            FilterAst::And(vec![
+                FilterAst::TagExists {
+                    key: "highway".into(),
+                    negated: false,
+                },
+                FilterAst::Not(Box::new(name_glob())),
+            ])
+        );
+        assert!(parse_filter("name:*=Berlin").is_err());
+        // Quoted keys are literal
+        assert_eq!(
+            parse_filter("'name:*'").unwrap(),
+            FilterAst::TagExists {
+                key: "name:*".into(),
+                negated: false,
+            }
+        );
+    }
+
+    #[test]
+    fn test_element_predicates() {
+        let element = FilterAst::Element;
+        assert_eq!(
+            parse_filter("@type=node|way").unwrap(),
+            element(ElementPredicate::Type(vec![
+                ElementKind::Node,
+                ElementKind::Way
+            ]))
+        );
+        assert_eq!(
+            parse_filter("@type!=relation").unwrap(),
+            FilterAst::Not(Box::new(element(ElementPredicate::Type(vec![
+                ElementKind::Relation
+            ]))))
+        );
+        assert_eq!(
+            parse_filter("!@closed").unwrap(),
+            FilterAst::Not(Box::new(element(ElementPredicate::Closed)))
+        );
+        assert_eq!(
+            parse_filter("@version>=3").unwrap(),
+            element(ElementPredicate::Compare {
+                field: MetaField::Version,
+                op: CompareOp::Ge,
+                value: 3,
+            })
+        );
+        assert_eq!(
+            parse_filter("@user=alice|'bob smith'").unwrap(),
+            element(ElementPredicate::User(vec![
+                TagValue::Exact("alice".into()),
+                TagValue::Exact("bob smith".into()),
+            ]))
+        );
+        assert!(matches!(
+            parse_filter("@closed & building=yes").unwrap(),
+            FilterAst::And(exprs) if exprs.len() == 2
+        ));
+    }
+
+    #[test]
+    fn test_element_predicates_end_value_lists() {
+        assert_eq!(
+            parse_filter("building=yes | @closed").unwrap(),
+            FilterAst::Or(vec![
+                FilterAst::TagMatch {
+                    key: "building".into(),
+                    values: vec![TagValue::Exact("yes".into())],
+                    normalize: false,
+                    list: false,
+                },
+                FilterAst::Element(ElementPredicate::Closed),
+            ])
+        );
+        assert!(matches!(
+            parse_filter("highway=primary|secondary | @within(bbox:13.0,52.3,13.8,52.7)").unwrap(),
+            FilterAst::Or(exprs) if matches!(
+                &exprs[..],
+                [FilterAst::TagMatch { values, .. }, FilterAst::Element(ElementPredicate::Within(_))]
+                    if values.len() == 2
+            )
+        ));
+        assert!(matches!(
+            parse_filter("@user=alice | @version>=2").unwrap(),
+            FilterAst::Or(exprs) if exprs.len() == 2
+        ));
+    }
+
+    #[test]
+    fn test_timestamp_operands() {
+        let millis = |expr: &str| match parse_filter(expr).unwrap() {
+            FilterAst::Element(ElementPredicate::Compare { value, .. }) => value,
+            other => panic!("{other:?}"),
+        };
+        assert_eq!(millis("@timestamp>=2024-01-01"), 1_704_067_200_000);
+        assert_eq!(millis("@timestamp<2024-01-01T00:00:01Z"), 1_704_067_201_000);
+        assert!(parse_filter("@timestamp>=2024-13-01").is_err());
+        assert!(parse_filter("@timestamp>=yesterday").is_err());
+    }
+
+    #[test]
+    fn test_spatial_predicates() {
+        assert_eq!(
+            parse_filter("building & @area>=1000.5").unwrap(),
+            FilterAst::And(vec![
+                FilterAst::TagExists {
+                    key: "building".into(),
+                    negated: false,
+                },
+                FilterAst::Element(ElementPredicate::Area {
+                    op: CompareOp::Ge,
+                    value: 1000.5,
+                }),
+            ])
+        );
+        assert_eq!(
+            parse_filter("@within(bbox:13.0,52.3,13.8,52.7)").unwrap(),
+            FilterAst::Element(ElementPredicate::Within(
+                Region::parse("bbox:13.0,52.3,13.8,52.7").unwrap()
+            ))
+        );
+        assert!(matches!(
+            parse_filter("!@intersects('bbox:-1,-1,1,1') | @length<50").unwrap(),
+            FilterAst::Or(exprs) if exprs.len() == 2
+        ));
+        assert!(
+            parse_filter("@within(bbox:1,2,3)")
+                .unwrap_err()
+                .message
+                .contains("Invalid bbox")
+        );
+        assert!(parse_filter("@within bbox:1,2,3,4").is_err());
+        assert!(parse_filter("@area>big").is_err());
+    }
+
+    #[test]
+    fn test_invalid_element_predicates() {
+        assert!(
+            parse_filter("@type=area")
+                .unwrap_err()
+                .message
+                .contains("Unknown element type")
+        );
+        assert!(
+            parse_filter("@colour=red")
+                .unwrap_err()
+                .message
+                .contains("Unknown element predicate")
+        );
+        assert!(parse_filter("@version>=three").is_err());
+        assert!(parse_filter("@closed=yes").is_err());
+        // A quoted key is a plain tag
+        assert_eq!(
+            parse_filter("'@type'=node").unwrap(),
+            FilterAst::TagMatch {
+                key: "@type".into(),
+                values: vec![TagValue::Exact("node".into())],
+                normalize: false,
+                list: false,
+            }
+        );
+    }
+
+    #[test]
+    fn test_invalid_regex_is_a_parse_error() {
+        let err = parse_filter(r#"ref~"(""#).unwrap_err();
+        assert!(err.message.contains("Invalid regex"), "{err}");
+    }
+
+    #[test]
+    fn test_errors_point_at_the_offending_token() {
+        let error = |expr: &str| {
+            let err = parse_filter(expr).unwrap_err();
+            (err.offset, err.hint.unwrap_or_default())
+        };
+        assert_eq!(
+            error("highway=|primary"),
+            (8, "a value is missing before `|`".into())
+        );
+        let (offset, hint) = error("highway=primary secondary");
+        assert_eq!(offset, 16);
+        assert!(hint.contains("did you mean `|` inside a value list?"));
+        assert_eq!(error("a && b"), (3, "use a single `&` for AND".into()));
+        assert_eq!(error("(highway"), (8, "a `(` is missing its `)`".into()));
+        assert_eq!(
+            error("highway)"),
+            (7, "this `)` has no matching `(`".into())
+        );
+        assert_eq!(error("@tpye=way"), (0, "did you mean `@type`?".into()));
+        assert!(error("highway!=primary").1.contains("`!key=value`"));
+        assert!(error("lanes > many").1.contains("take a number"));
+        // No guessing without a close match
+        assert_eq!(error("@colour=red").1, "");
+    }
+
+    #[test]
+    fn test_multiline_expressions() {
+        let err = parse_filter("highway=primary\n  & name=A B").unwrap_err();
+        assert_eq!(err.line_column(), (2, 12));
+        assert!(parse_filter("highway=primary\n| railway").is_ok());
+    }
 }
@@ -0,0 +1,199 @@
+//! Geometry tests of spatial filter predicates (`@within`, `@intersects`, `@area`, `@length`).
+//!
+//! Geometries are WGS84 longitude/latitude; areas are in square metres and lengths in metres,
+//! measured on the ellipsoid.
+
+use geo::{
+    BoundingRect, Contains, Geodesic, GeodesicArea, Intersects, Length, MultiPolygon, Polygon, Rect,
+};
+use geo_types::Geometry;
+use geojson::GeoJson;
+use std::path::Path;
+use std::sync::Arc;
+
+/// An area given to `@within` or `@intersects`: `bbox:minlon,minlat,maxlon,maxlat` or
+/// `file:path.geojson`.
+///
+/// Loaded once when the filter is parsed; regions compare equal by their source text.
+#[derive(Debug, Clone)]
+pub struct Region {
+    source: String,
+    area: Arc<MultiPolygon<f64>>,
+    bounds: Rect<f64>,
+}
+
+impl Region {
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let area = if let Some(bbox) = source.strip_prefix("bbox:") {
+            MultiPolygon(vec![parse_bbox(bbox)?.to_polygon()])
+        } else if let Some(path) = source.strip_prefix("file:") {
+            load_polygons(Path::new(path))?
+        } else {
+            return Err(format!(
+                "Invalid region \"{}\"; expected bbox:minlon,minlat,maxlon,maxlat or file:path.geojson",
+                source
+            ));
+        };
+        let bounds = area
+            .bounding_rect()
+            .ok_or_else(|| format!("Region \"{}\" is empty", source))?;
+
+        Ok(Region {
+            source: source.to_string(),
+            area: Arc::new(area),
+            bounds,
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    /// Whether the geometry lies inside the region.
+    pub fn contains(&self, geometry: &Geometry<f64>) -> bool {
+        self.may_intersect(geometry) && self.area.contains(geometry)
+    }
+
+    /// Whether the geometry and the region share any point.
+    pub fn intersects(&self, geometry: &Geometry<f64>) -> bool {
+        self.may_intersect(geometry) && geometry.intersects(self.area.as_ref())
+    }
+
+    /// Cheap bounding box test before the exact one.
+    fn may_intersect(&self, geometry: &Geometry<f64>) -> bool {
+        geometry
+            .bounding_rect()
+            .is_some_and(|rect| rect.intersects(&self.bounds))
+    }
+}
+
+impl PartialEq for Region {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+    }
+}
+
+fn parse_bbox(bbox: &str) -> Result<Rect<f64>, String> {
+    let invalid = || {
+        format!(
+            "Invalid bbox \"{}\"; expected minlon,minlat,maxlon,maxlat",
+            bbox
+        )
+    };
+    let values: Vec<f64> = bbox
+        .split(',')
+        .map(|v| v.trim().parse::<f64>())
+        .collect::<Result<_, _>>()
+        .map_err(|_| invalid())?;
+    let [min_lon, min_lat, max_lon, max_lat] = values[..] else {
+        return Err(invalid());
+    };
+    if min_lon > max_lon || min_lat > max_lat {
+        return Err(invalid());
+    }
+    Ok(Rect::new((min_lon, min_lat), (max_lon, max_lat)))
+}
+
+/// The polygons of a GeoJSON file; other geometries are ignored.
+fn load_polygons(path: &Path) -> Result<MultiPolygon<f64>, String> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read region file {:?}: {}", path, e))?;
+    let geojson: GeoJson = text
+        .parse()
+        .map_err(|e| format!("Invalid GeoJSON in region file {:?}: {}", path, e))?;
+    let collection = geo_types::GeometryCollection::<f64>::try_from(&geojson)
+        .map_err(|e| format!("Invalid GeoJSON in region file {:?}: {}", path, e))?;
+
+    let mut polygons: Vec<Polygon<f64>> = Vec::new();
+    for geometry in collection {
+        match geometry {
+            Geometry::Polygon(polygon) => polygons.push(polygon),
+            Geometry::MultiPolygon(multi) => polygons.extend(multi),
+            _ => {}
+        }
+    }
+    if polygons.is_empty() {
+        return Err(format!("Region file {:?} contains no polygons", path));
+    }
+    Ok(MultiPolygon(polygons))
+}
+
+/// Geodesic area in square metres; zero for points and lines.
+pub fn area(geometry: &Geometry<f64>) -> f64 {
+    geometry.geodesic_area_unsigned()
+}
+
+/// Geodesic length of lines in metres, or the perimeter of polygons; zero for points.
+pub fn length(geometry: &Geometry<f64>) -> f64 {
+    match geometry {
+        Geometry::Line(line) => Geodesic.length(line),
+        Geometry::LineString(line_string) => Geodesic.length(line_string),
+        Geometry::MultiLineString(lines) => Geodesic.length(lines),
+        Geometry::GeometryCollection(collection) => collection.iter().map(length).sum(),
+        other => other.geodesic_perimeter(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use geo_types::{LineString, Point};
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn bbox_regions_contain_and_intersect() {
+        let region = Region::parse("bbox:0,0,1,1").unwrap();
+        let inside = Geometry::Point(Point::new(0.5, 0.5));
+        let outside = Geometry::Point(Point::new(2.0, 0.5));
+        let crossing = Geometry::LineString(LineString::from(vec![(0.5, 0.5), (2.0, 0.5)]));
+
+        assert!(region.contains(&inside));
+        assert!(!region.contains(&outside));
+        assert!(!region.contains(&crossing));
+        assert!(region.intersects(&crossing));
+        assert!(!region.intersects(&outside));
+    }
+
+    #[test]
+    fn invalid_regions_are_errors() {
+        assert!(Region::parse("bbox:0,0,1").is_err());
+        assert!(Region::parse("bbox:1,0,0,1").is_err());
+        assert!(Region::parse("circle:0,0,1").is_err());
+        assert!(Region::parse("file:/nonexistent/area.geojson").is_err());
+    }
+
+    #[test]
+    fn file_regions_use_the_polygons_of_a_geojson_file() {
+        let mut file = NamedTempFile::new().unwrap();
+        write!(
+            file,
+            r#"{{"type": "FeatureCollection", "features": [
+                {{"type": "Feature", "properties": {{}},
+                  "geometry": {{"type": "Point", "coordinates": [5, 5]}}}},
+                {{"type": "Feature", "properties": {{}},
+                  "geometry": {{"type": "Polygon",
+                                "coordinates": [[[0, 0], [2, 0], [2, 2], [0, 2], [0, 0]]]}}}}
+            ]}}"#
+        )
+        .unwrap();
+        let region = Region::parse(&format!("file:{}", file.path().display())).unwrap();
+
+        assert!(region.contains(&Geometry::Point(Point::new(1.0, 1.0))));
+        // The point feature isn't part of the region
+        assert!(!region.intersects(&Geometry::Point(Point::new(5.0, 5.0))));
+    }
+
+    #[test]
+    fn measures_are_geodesic() {
+        // About 111 km per degree at the equator
+        let line = Geometry::LineString(LineString::from(vec![(0.0, 0.0), (1.0, 0.0)]));
+        assert!((length(&line) - 111_319.5).abs() < 1.0);
+        assert_eq!(area(&line), 0.0);
+
+        let square = Geometry::Polygon(Rect::new((0.0, 0.0), (0.001, 0.001)).to_polygon());
+        assert!((area(&square) - 12_308.0).abs() < 10.0, "{}", area(&square));
+        assert!(length(&square) > 400.0);
+        assert_eq!(length(&Geometry::Point(Point::new(0.0, 0.0))), 0.0);
+    }
+}
@@ -0,0 +1,210 @@
+//! Numeric tag values with units, as used by numeric comparisons and ranges.
+//!
+//! Values of keys with a known kind of quantity are converted to the unit OSM assumes when none
+//! is given, so `maxspeed<50` compares km/h whether the tag says `50` or `30 mph`: speeds in
+//! km/h, lengths in metres and weights in tonnes. Other keys compare their leading number.
+
+/// The kind of quantity a key holds, which decides the units its values may have.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Speed,
+    Length,
+    Weight,
+}
+
+impl Kind {
+    /// The kind of a key like `maxspeed` or `maxheight:physical`, judged by the part before
+    /// the first `:`.
+    fn of(key: &str) -> Option<Kind> {
+        match key.split(':').next().unwrap_or(key) {
+            "maxspeed" | "minspeed" => Some(Kind::Speed),
+            "width" | "height" | "length" | "maxwidth" | "maxheight" | "maxlength" => {
+                Some(Kind::Length)
+            }
+            "maxweight" => Some(Kind::Weight),
+            _ => None,
+        }
+    }
+}
+
+/// Unit suffixes with their kind and factor to the kind's default unit. Matched ignoring case.
+const UNITS: &[(&str, Kind, f64)] = &[
+    // Speed, to km/h
+    ("km/h", Kind::Speed, 1.0),
+    ("kmh", Kind::Speed, 1.0),
+    ("kph", Kind::Speed, 1.0),
+    ("mph", Kind::Speed, 1.609_344),
+    ("knots", Kind::Speed, 1.852),
+    ("kn", Kind::Speed, 1.852),
+    // Length, to metres
+    ("m", Kind::Length, 1.0),
+    ("km", Kind::Length, 1000.0),
+    ("cm", Kind::Length, 0.01),
+    ("mm", Kind::Length, 0.001),
+    ("mi", Kind::Length, 1609.344),
+    ("nmi", Kind::Length, 1852.0),
+    ("ft", Kind::Length, FOOT),
+    ("in", Kind::Length, INCH),
+    // Weight, to tonnes
+    ("t", Kind::Weight, 1.0),
+    ("kg", Kind::Weight, 0.001),
+    ("lb", Kind::Weight, POUND),
+    ("lbs", Kind::Weight, POUND),
+];
+
+const FOOT: f64 = 0.3048;
+const INCH: f64 = 0.0254;
+const POUND: f64 = 0.000_453_592_37;
+
+/// Parse the value of tag `key` to a number. For keys with a kind of quantity, values like
+/// `50`, `2,5`, `30 mph`, `10 ft`, `3'6"` or `3.5t` are converted to the kind's default unit,
+/// and units of another kind or unknown ones give `None`. Other keys take the leading number,
+/// so `lanes` reads `2;3` as 2.
+pub fn parse_quantity(key: &str, value: &str) -> Option<f64> {
+    match Kind::of(key) {
+        Some(kind) => parse_with_unit(kind, value),
+        None => parse_number(leading_number(value.trim()).0),
+    }
+}
+
+/// Parse a threshold or range bound written in a filter for `key`. Like [`parse_quantity`],
+/// but keys without a kind of quantity take plain numbers only.
+pub fn parse_threshold(key: &str, text: &str) -> Option<f64> {
+    match Kind::of(key) {
+        Some(kind) => parse_with_unit(kind, text),
+        None => parse_number(text.trim()),
+    }
+}
+
+fn parse_with_unit(kind: Kind, value: &str) -> Option<f64> {
+    let value = value.trim();
+
+    // Feet and inches: `3'`, `3'6"`, `6"`
+    if kind == Kind::Length {
+        if let Some((feet, inches)) = value.split_once('\'') {
+            let feet = parse_number(feet.trim())? * FOOT;
+            let inches = inches.trim();
+            if inches.is_empty() {
+                return Some(feet);
+            }
+            return Some(feet + parse_number(inches.strip_suffix('"')?.trim())? * INCH);
+        }
+        if let Some(inches) = value.strip_suffix('"') {
+            return Some(parse_number(inches.trim())? * INCH);
+        }
+    }
+
+    let (number, unit) = leading_number(value);
+    let number = parse_number(number)?;
+    let unit = unit.trim();
+    if unit.is_empty() {
+        return Some(number);
+    }
+    UNITS
+        .iter()
+        .find(|(name, unit_kind, _)| *unit_kind == kind && name.eq_ignore_ascii_case(unit))
+        .map(|(_, _, factor)| number * factor)
+}
+
+/// Split text into its leading run of number characters and the rest.
+fn leading_number(text: &str) -> (&str, &str) {
+    let split = text
+        .find(|c: char| !(c.is_ascii_digit() || matches!(c, '.' | ',' | '-')))
+        .unwrap_or(text.len());
+    text.split_at(split)
+}
+
+/// A plain decimal number, with `.` or a single `,` as the decimal separator. A `,` followed
+/// by exactly three digits, as in `1,000`, may be a thousands separator and gives `None`.
+fn parse_number(text: &str) -> Option<f64> {
+    let digits = text.strip_prefix('-').unwrap_or(text);
+    if !digits.starts_with(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+    if let Some((_, fraction)) = text.split_once(',') {
+        if text.contains('.')
+            || fraction.contains(',')
+            || (fraction.len() == 3 && fraction.bytes().all(|b| b.is_ascii_digit()))
+        {
+            return None;
+        }
+        return text.replacen(',', ".", 1).parse().ok();
+    }
+    text.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn approx(key: &str, value: &str, expected: f64) {
+        let parsed =
+            parse_quantity(key, value).unwrap_or_else(|| panic!("{key}={value:?} didn't parse"));
+        assert!(
+            (parsed - expected).abs() < 1e-6,
+            "{key}={value:?}: {parsed}"
+        );
+    }
+
+    #[test]
+    fn plain_and_locale_numbers() {
+        approx("maxspeed", "30", 30.0);
+        approx("width", " 5.5 ", 5.5);
+        approx("lanes", "-1", -1.0);
+        approx("width", "2,5", 2.5);
+        approx("lanes", "2,50", 2.5);
+        assert_eq!(parse_quantity("width", "1,000.5"), None);
+        assert_eq!(parse_quantity("width", "none"), None);
+        assert_eq!(parse_quantity("width", ""), None);
+        assert_eq!(parse_quantity("width", "inf"), None);
+    }
+
+    #[test]
+    fn thousands_separators_are_ambiguous() {
+        assert_eq!(parse_quantity("population", "1,000"), None);
+        assert_eq!(parse_quantity("maxweight", "12,500 kg"), None);
+        assert_eq!(parse_threshold("population", "1,000"), None);
+    }
+
+    #[test]
+    fn units_convert_to_the_default_unit() {
+        approx("maxspeed", "30 mph", 48.28032);
+        approx("maxspeed:forward", "50 km/h", 50.0);
+        approx("maxspeed", "10 knots", 18.52);
+        approx("height", "10 ft", 3.048);
+        approx("maxwidth", "250cm", 2.5);
+        approx("maxweight", "3.5t", 3.5);
+        approx("maxweight", "2,5 T", 2.5);
+        approx("maxweight", "8000 lbs", 3.628_738_96);
+        assert_eq!(parse_quantity("maxspeed", "50 furlongs"), None);
+        assert_eq!(parse_quantity("maxspeed", "30;50"), None);
+    }
+
+    #[test]
+    fn units_must_fit_the_key() {
+        assert_eq!(parse_quantity("maxspeed", "40 m"), None);
+        assert_eq!(parse_quantity("maxspeed", "3'6\""), None);
+        assert_eq!(parse_quantity("maxheight", "3 t"), None);
+        assert_eq!(parse_quantity("maxweight", "30 mph"), None);
+    }
+
+    #[test]
+    fn other_keys_take_the_leading_number() {
+        approx("lanes", "2;3", 2.0);
+        approx("ele", "300 m", 300.0);
+        approx("capacity", "50 furlongs", 50.0);
+        assert_eq!(parse_quantity("lanes", "two"), None);
+        assert_eq!(parse_threshold("lanes", "3"), Some(3.0));
+        assert_eq!(parse_threshold("lanes", "3ft"), None);
+        assert_eq!(parse_threshold("maxheight", "12ft"), Some(12.0 * FOOT));
+    }
+
+    #[test]
+    fn feet_and_inches() {
+        approx("maxheight", "3'6\"", 1.0668);
+        approx("maxheight", "3' 6\"", 1.0668);
+        approx("height", "12'", 3.6576);
+        approx("width", "6\"", 0.1524);
+        assert_eq!(parse_quantity("maxheight", "3'6"), None);
+    }
+}
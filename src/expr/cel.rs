@@ -5,6 +5,8 @@ use cel::{Context, Program, Value};
 use std::collections::HashMap;
 use std::sync::Arc;
 
+use crate::metadata::MetadataFields;
+
 /// A compiled CEL program ready for evaluation.
 #[derive(Clone)]
 pub struct CelProgram {
@@ -20,10 +22,33 @@ impl std::fmt::Debug for CelProgram {
     }
 }
 
-/// Context for CEL evaluation (tags and metadata).
-pub struct CelContext<'a> {
-    pub tags: &'a HashMap<String, String>,
-    pub meta: &'a HashMap<String, String>,
+/// Variables for evaluating CEL programs against one feature.
+///
+/// Converting tags and metadata into CEL values is the expensive part of evaluation, so build
+/// this once per feature and reuse it for every CEL column.
+pub struct CelContext {
+    inner: Context<'static>,
+}
+
+impl CelContext {
+    /// `tags` becomes a map of strings. `meta` keeps its types (`meta.id` is an int,
+    /// `meta.visible` a bool, `meta.timestamp` a timestamp) and omits fields the element doesn't have, so `has(meta.user)`
+    /// works as expected.
+    pub fn new(tags: &HashMap<String, String>, meta: Option<&MetadataFields>) -> Result<Self> {
+        let mut inner = Context::default();
+
+        inner
+            .add_variable("tags", tags)
+            .map_err(|e| anyhow::anyhow!("CEL context error: {}", e))?;
+
+        match meta {
+            Some(meta) => inner.add_variable("meta", meta),
+            None => inner.add_variable("meta", HashMap::<String, String>::new()),
+        }
+        .map_err(|e| anyhow::anyhow!("CEL context error: {}", e))?;
+
+        Ok(Self { inner })
+    }
 }
 
 /// Compile a CEL expression string into a program.
@@ -39,25 +64,13 @@ pub fn compile_cel(source: &str) -> Result<CelProgram> {
 
 /// Evaluate a compiled CEL program with the given context.
 pub fn evaluate_cel(program: &CelProgram, ctx: &CelContext) -> Result<Value> {
-    let mut cel_ctx = Context::default();
-
-    // Add tags as a map variable
-    cel_ctx
-        .add_variable("tags", ctx.tags.clone())
-        .map_err(|e| anyhow::anyhow!("CEL context error: {}", e))?;
-
-    // Add meta as a map variable
-    cel_ctx
-        .add_variable("meta", ctx.meta.clone())
-        .map_err(|e| anyhow::anyhow!("CEL context error: {}", e))?;
-
     // CEL natively supports 'key in tags' and 'tags[key]'
     // or tags.get('key', 'default') if macros are enabled (not yet in this version)
     // For now, we use native map access.
 
     program
         .program
-        .execute(&cel_ctx)
+        .execute(&ctx.inner)
         .map_err(|e| anyhow::anyhow!("CEL execution error: {}", e))
 }
 
@@ -78,11 +91,21 @@ pub fn cel_value_to_string(value: &Value) -> Option<String> {
 mod tests {
     use super::*;
 
-    fn make_ctx<'a>(
-        tags: &'a HashMap<String, String>,
-        meta: &'a HashMap<String, String>,
-    ) -> CelContext<'a> {
-        CelContext { tags, meta }
+    fn make_ctx(tags: &HashMap<String, String>, meta: Option<&MetadataFields>) -> CelContext {
+        CelContext::new(tags, meta).unwrap()
+    }
+
+    fn metadata() -> MetadataFields {
+        MetadataFields {
+            id: 42,
+            visible: Some(true),
+            version: Some(3),
+            changeset: None,
+            timestamp: Some("2024-01-01T00:00:00Z".to_string()),
+            timestamp_millis: Some(1_704_067_200_000),
+            uid: None,
+            user: Some("mapper".to_string()),
+        }
     }
 
     #[test]
@@ -90,8 +113,7 @@ mod tests {
         let program = compile_cel("tags.name").unwrap();
 
         let tags: HashMap<String, String> = [("name".into(), "Foo".into())].into();
-        let meta = HashMap::new();
-        let ctx = make_ctx(&tags, &meta);
+        let ctx = make_ctx(&tags, None);
 
         let result = evaluate_cel(&program, &ctx).unwrap();
         assert_eq!(cel_value_to_string(&result), Some("Foo".into()));
@@ -102,8 +124,7 @@ mod tests {
         let program = compile_cel("tags.highway == 'primary'").unwrap();
 
         let tags: HashMap<String, String> = [("highway".into(), "primary".into())].into();
-        let meta = HashMap::new();
-        let ctx = make_ctx(&tags, &meta);
+        let ctx = make_ctx(&tags, None);
 
         let result = evaluate_cel(&program, &ctx).unwrap();
         assert!(matches!(result, Value::Bool(true)));
@@ -114,8 +135,7 @@ mod tests {
         let program = compile_cel("'name' in tags").unwrap();
 
         let tags: HashMap<String, String> = [("name".into(), "Foo".into())].into();
-        let meta = HashMap::new();
-        let ctx = make_ctx(&tags, &meta);
+        let ctx = make_ctx(&tags, None);
 
         let result = evaluate_cel(&program, &ctx).unwrap();
         assert!(matches!(result, Value::Bool(true)));
@@ -126,10 +146,66 @@ mod tests {
         let program = compile_cel("has(tags.name) ? tags.name : 'default'").unwrap();
 
         let tags: HashMap<String, String> = [("name".into(), "Foo".into())].into();
-        let meta = HashMap::new();
-        let ctx = make_ctx(&tags, &meta);
+        let ctx = make_ctx(&tags, None);
+
+        let result = evaluate_cel(&program, &ctx).unwrap();
+        assert_eq!(cel_value_to_string(&result), Some("Foo".into()));
+    }
+
+    #[test]
+    fn test_meta_fields_keep_their_types() {
+        let program =
+            compile_cel("meta.id + 1 == 43 && meta.version >= 3 && meta.visible").unwrap();
+
+        let tags = HashMap::new();
+        let meta = metadata();
+        let ctx = make_ctx(&tags, Some(&meta));
+
+        let result = evaluate_cel(&program, &ctx).unwrap();
+        assert!(matches!(result, Value::Bool(true)));
+    }
+
+    #[test]
+    fn test_meta_timestamp_is_a_timestamp() {
+        let program = compile_cel(
+            "meta.timestamp >= timestamp('2024-01-01T00:00:00Z') && \
+             meta.timestamp < timestamp('2024-01-02T00:00:00Z')",
+        )
+        .unwrap();
+
+        let tags = HashMap::new();
+        let meta = metadata();
+        let ctx = make_ctx(&tags, Some(&meta));
 
         let result = evaluate_cel(&program, &ctx).unwrap();
+        assert!(matches!(result, Value::Bool(true)));
+    }
+
+    #[test]
+    fn test_missing_meta_fields_are_absent() {
+        let program = compile_cel("has(meta.user) && !has(meta.changeset)").unwrap();
+
+        let tags = HashMap::new();
+        let meta = metadata();
+        let ctx = make_ctx(&tags, Some(&meta));
+
+        let result = evaluate_cel(&program, &ctx).unwrap();
+        assert!(matches!(result, Value::Bool(true)));
+    }
+
+    #[test]
+    fn test_context_is_reused_across_programs() {
+        let tags: HashMap<String, String> = [("name".into(), "Foo".into())].into();
+        let ctx = make_ctx(&tags, None);
+
+        let name = compile_cel("tags.name").unwrap();
+        let has_meta = compile_cel("has(meta.id)").unwrap();
+
+        let result = evaluate_cel(&name, &ctx).unwrap();
+        assert_eq!(cel_value_to_string(&result), Some("Foo".into()));
+        let result = evaluate_cel(&has_meta, &ctx).unwrap();
+        assert!(matches!(result, Value::Bool(false)));
+        let result = evaluate_cel(&name, &ctx).unwrap();
         assert_eq!(cel_value_to_string(&result), Some("Foo".into()));
     }
 }
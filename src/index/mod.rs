@@ -0,0 +1,444 @@
+//! Blob index: where each data blob of a PBF file starts and what it contains.
+//!
+//! Passes use the index to decode only the blobs holding the element types they need. It is
+//! built as a by-product of decoding and can be kept in a sidecar file between runs.
+
+use anyhow::{Context, Result};
+use osmpbf::{Element, PrimitiveBlock};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Bumped when the sidecar layout changes; older sidecars are ignored and rebuilt.
+const INDEX_VERSION: u32 = 2;
+
+/// Bytes hashed at each end of the input for its fingerprint.
+const FINGERPRINT_SAMPLE: u64 = 64 * 1024;
+
+/// Identifies the file an index was built for. Besides size and modification time, it hashes
+/// the start and end of the file, which hold the header blob and the end of the last data blob,
+/// so a rewritten file of the same size is noticed even if its mtime was preserved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InputFingerprint {
+    pub len: u64,
+    /// Modification time in nanoseconds since the Unix epoch, if the platform reports it
+    pub modified: Option<u64>,
+    pub sample_hash: u64,
+}
+
+impl InputFingerprint {
+    pub fn of(path: &Path) -> Result<Self> {
+        let mut file =
+            File::open(path).with_context(|| format!("Index: Failed to open {:?}", path))?;
+        let metadata = file.metadata()?;
+        let len = metadata.len();
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+            .map(|age| age.as_nanos() as u64);
+
+        let mut sample = Vec::new();
+        (&mut file)
+            .take(FINGERPRINT_SAMPLE)
+            .read_to_end(&mut sample)?;
+        if len > FINGERPRINT_SAMPLE {
+            // The tail, without re-reading bytes of the head
+            let tail = (len - FINGERPRINT_SAMPLE).max(FINGERPRINT_SAMPLE);
+            file.seek(SeekFrom::Start(tail))?;
+            file.read_to_end(&mut sample)?;
+        }
+
+        Ok(Self {
+            len,
+            modified,
+            sample_hash: fnv1a(&sample),
+        })
+    }
+}
+
+/// 64-bit FNV-1a; stable across builds, unlike the standard library's hasher.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Element types present in a blob.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct BlobKinds(u8);
+
+impl BlobKinds {
+    pub const NONE: BlobKinds = BlobKinds(0);
+    pub const NODES: BlobKinds = BlobKinds(1);
+    pub const WAYS: BlobKinds = BlobKinds(2);
+    pub const RELATIONS: BlobKinds = BlobKinds(4);
+    pub const ALL: BlobKinds = BlobKinds(7);
+
+    pub fn union(self, other: BlobKinds) -> BlobKinds {
+        BlobKinds(self.0 | other.0)
+    }
+
+    pub fn intersects(self, other: BlobKinds) -> bool {
+        self.0 & other.0 != 0
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+}
+
+/// One data blob: its file offset, element types and ID range.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BlobEntry {
+    pub offset: u64,
+    pub kinds: BlobKinds,
+    /// Smallest and largest element ID in the blob (0 for empty blobs)
+    pub min_id: i64,
+    pub max_id: i64,
+}
+
+impl BlobEntry {
+    pub fn from_block(offset: u64, block: &PrimitiveBlock) -> Self {
+        let mut kinds = BlobKinds::NONE;
+        let mut range: Option<(i64, i64)> = None;
+
+        for element in block.elements() {
+            let (kind, id) = match element {
+                Element::Node(node) => (BlobKinds::NODES, node.id()),
+                Element::DenseNode(node) => (BlobKinds::NODES, node.id()),
+                Element::Way(way) => (BlobKinds::WAYS, way.id()),
+                Element::Relation(relation) => (BlobKinds::RELATIONS, relation.id()),
+            };
+            kinds = kinds.union(kind);
+            range = Some(match range {
+                Some((min, max)) => (min.min(id), max.max(id)),
+                None => (id, id),
+            });
+        }
+
+        let (min_id, max_id) = range.unwrap_or((0, 0));
+        Self {
+            offset,
+            kinds,
+            min_id,
+            max_id,
+        }
+    }
+}
+
+/// Data blobs of one PBF file in file order. The header blob is not listed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BlobIndex {
+    version: u32,
+    /// The indexed file; a sidecar for a different or changed file is ignored
+    pub input: InputFingerprint,
+    pub entries: Vec<BlobEntry>,
+    /// Offset of the first blob not covered by `entries`, when a pass stopped early
+    pub unindexed_from: Option<u64>,
+}
+
+impl BlobIndex {
+    pub fn new(
+        input: InputFingerprint,
+        mut entries: Vec<BlobEntry>,
+        unindexed_from: Option<u64>,
+    ) -> Self {
+        entries.sort_by_key(|entry| entry.offset);
+        entries.dedup_by_key(|entry| entry.offset);
+        if let Some(end) = unindexed_from {
+            entries.retain(|entry| entry.offset < end);
+        }
+        Self {
+            version: INDEX_VERSION,
+            input,
+            entries,
+            unindexed_from,
+        }
+    }
+
+    /// Read a sidecar index. Returns `None` if it doesn't exist, can't be parsed, or was
+    /// built for a different or since modified file.
+    pub fn load(path: &Path, input: &InputFingerprint) -> Option<Self> {
+        let data = std::fs::read(path).ok()?;
+        let index: BlobIndex = match serde_json::from_slice(&data) {
+            Ok(index) => index,
+            Err(err) => {
+                tracing::warn!("Blob index {:?} is unreadable, rebuilding: {}", path, err);
+                return None;
+            }
+        };
+        if index.version != INDEX_VERSION || index.input != *input {
+            tracing::warn!("Blob index {:?} does not match the input, rebuilding", path);
+            return None;
+        }
+        Some(index)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let data = serde_json::to_vec(self)?;
+        std::fs::write(path, data)
+            .with_context(|| format!("Index: Failed to write blob index {:?}", path))
+    }
+
+    /// Whether every data blob of the file is listed.
+    pub fn is_complete(&self) -> bool {
+        self.unindexed_from.is_none()
+    }
+
+    /// Offsets of the indexed blobs containing any of `kinds` with IDs in `ids`, in file
+    /// order.
+    pub fn offsets_with(&self, kinds: BlobKinds, ids: &RangeInclusive<i64>) -> Vec<u64> {
+        self.entries
+            .iter()
+            .filter(|entry| entry.kinds.intersects(kinds))
+            .filter(|entry| entry.min_id <= *ids.end() && entry.max_id >= *ids.start())
+            .map(|entry| entry.offset)
+            .collect()
+    }
+
+    /// Combine an existing index with what a later pass recorded past its indexed part.
+    pub fn extend(previous: Option<BlobIndex>, recorded: IndexRecorder) -> BlobIndex {
+        match previous {
+            Some(previous) if previous.is_complete() => previous,
+            Some(previous) => {
+                let (input, mut entries, unindexed_from) = recorded.into_parts();
+                entries.extend(previous.entries);
+                BlobIndex::new(input, entries, unindexed_from)
+            }
+            None => {
+                let (input, entries, unindexed_from) = recorded.into_parts();
+                BlobIndex::new(input, entries, unindexed_from)
+            }
+        }
+    }
+}
+
+/// Collects index entries from the workers of a pass.
+pub struct IndexRecorder {
+    input: InputFingerprint,
+    /// Only blobs at or after this offset are recorded; earlier ones are already indexed
+    from: u64,
+    entries: Mutex<Vec<BlobEntry>>,
+    /// Offset of the earliest blob where the pass stopped reading, `u64::MAX` if it didn't
+    stopped_at: AtomicU64,
+}
+
+impl IndexRecorder {
+    pub fn new(input: InputFingerprint, previous: Option<&BlobIndex>) -> Self {
+        Self {
+            input,
+            from: previous.map_or(0, |index| index.unindexed_from.unwrap_or(u64::MAX)),
+            entries: Mutex::new(Vec::new()),
+            stopped_at: AtomicU64::new(u64::MAX),
+        }
+    }
+
+    /// Whether a blob at `offset` still needs an entry.
+    pub fn wants(&self, offset: u64) -> bool {
+        offset >= self.from
+    }
+
+    pub fn record(&self, entry: BlobEntry) {
+        self.entries.lock().unwrap().push(entry);
+    }
+
+    /// Mark that the pass stopped reading at the blob at `offset`; it and everything after
+    /// it stay unindexed.
+    pub fn stop_at(&self, offset: u64) {
+        self.stopped_at.fetch_min(offset, Ordering::Relaxed);
+    }
+
+    fn into_parts(self) -> (InputFingerprint, Vec<BlobEntry>, Option<u64>) {
+        let stopped_at = self.stopped_at.into_inner();
+        (
+            self.input,
+            self.entries.into_inner().unwrap(),
+            (stopped_at != u64::MAX).then_some(stopped_at),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::NamedTempFile;
+
+    const INPUT: InputFingerprint = InputFingerprint {
+        len: 1000,
+        modified: Some(1_700_000_000_000_000_000),
+        sample_hash: 42,
+    };
+
+    const ALL_IDS: RangeInclusive<i64> = i64::MIN..=i64::MAX;
+
+    fn entry(offset: u64, kinds: BlobKinds) -> BlobEntry {
+        BlobEntry {
+            offset,
+            kinds,
+            min_id: 1,
+            max_id: 10,
+        }
+    }
+
+    #[test]
+    fn offsets_are_filtered_by_kind_in_file_order() {
+        let index = BlobIndex::new(
+            INPUT,
+            vec![
+                entry(300, BlobKinds::WAYS),
+                entry(100, BlobKinds::NODES),
+                entry(200, BlobKinds::NODES.union(BlobKinds::WAYS)),
+                entry(400, BlobKinds::RELATIONS),
+            ],
+            None,
+        );
+
+        assert_eq!(
+            index.offsets_with(BlobKinds::NODES, &ALL_IDS),
+            vec![100, 200]
+        );
+        assert_eq!(
+            index.offsets_with(BlobKinds::WAYS, &ALL_IDS),
+            vec![200, 300]
+        );
+        assert_eq!(
+            index.offsets_with(BlobKinds::ALL, &ALL_IDS),
+            vec![100, 200, 300, 400]
+        );
+        assert!(index.offsets_with(BlobKinds::NONE, &ALL_IDS).is_empty());
+    }
+
+    #[test]
+    fn offsets_are_filtered_by_id_range() {
+        let with_ids = |offset, min_id, max_id| BlobEntry {
+            min_id,
+            max_id,
+            ..entry(offset, BlobKinds::WAYS)
+        };
+        let index = BlobIndex::new(
+            INPUT,
+            vec![
+                with_ids(100, 1, 99),
+                with_ids(200, 100, 199),
+                with_ids(300, 200, 299),
+            ],
+            None,
+        );
+
+        assert_eq!(
+            index.offsets_with(BlobKinds::WAYS, &(150..=250)),
+            vec![200, 300]
+        );
+        assert_eq!(index.offsets_with(BlobKinds::WAYS, &(99..=99)), vec![100]);
+        assert!(
+            index
+                .offsets_with(BlobKinds::WAYS, &(300..=i64::MAX))
+                .is_empty()
+        );
+        assert!(
+            index
+                .offsets_with(BlobKinds::WAYS, &RangeInclusive::new(1, 0))
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn stopped_pass_leaves_the_tail_unindexed() {
+        let recorder = IndexRecorder::new(INPUT, None);
+        recorder.record(entry(100, BlobKinds::NODES));
+        recorder.record(entry(300, BlobKinds::WAYS));
+        recorder.record(entry(200, BlobKinds::WAYS));
+        recorder.stop_at(300);
+        recorder.stop_at(200);
+
+        let index = BlobIndex::extend(None, recorder);
+        assert!(!index.is_complete());
+        assert_eq!(index.unindexed_from, Some(200));
+        assert_eq!(index.offsets_with(BlobKinds::ALL, &ALL_IDS), vec![100]);
+    }
+
+    #[test]
+    fn later_pass_completes_a_partial_index() {
+        let partial = BlobIndex::new(INPUT, vec![entry(100, BlobKinds::NODES)], Some(200));
+
+        let recorder = IndexRecorder::new(INPUT, Some(&partial));
+        assert!(!recorder.wants(100));
+        assert!(recorder.wants(200));
+        recorder.record(entry(200, BlobKinds::WAYS));
+        recorder.record(entry(300, BlobKinds::RELATIONS));
+
+        let index = BlobIndex::extend(Some(partial), recorder);
+        assert!(index.is_complete());
+        assert_eq!(
+            index.offsets_with(BlobKinds::ALL, &ALL_IDS),
+            vec![100, 200, 300]
+        );
+    }
+
+    #[test]
+    fn complete_index_is_kept_as_is() {
+        let complete = BlobIndex::new(INPUT, vec![entry(100, BlobKinds::NODES)], None);
+        let recorder = IndexRecorder::new(INPUT, Some(&complete));
+        assert!(!recorder.wants(100));
+
+        let index = BlobIndex::extend(Some(complete.clone()), recorder);
+        assert_eq!(index, complete);
+    }
+
+    #[test]
+    fn sidecar_roundtrip_checks_the_input() {
+        let file = NamedTempFile::new().unwrap();
+        let index = BlobIndex::new(INPUT, vec![entry(100, BlobKinds::NODES)], None);
+        index.save(file.path()).unwrap();
+
+        assert_eq!(BlobIndex::load(file.path(), &INPUT), Some(index));
+        for changed in [
+            InputFingerprint { len: 999, ..INPUT },
+            InputFingerprint {
+                modified: Some(1_800_000_000_000_000_000),
+                ..INPUT
+            },
+            InputFingerprint {
+                sample_hash: 43,
+                ..INPUT
+            },
+        ] {
+            assert_eq!(BlobIndex::load(file.path(), &changed), None);
+        }
+    }
+
+    #[test]
+    fn fingerprint_sees_rewrites_of_the_same_size() {
+        let file = NamedTempFile::new().unwrap();
+        let mut data = vec![7u8; 200_000];
+        std::fs::write(file.path(), &data).unwrap();
+        let before = InputFingerprint::of(file.path()).unwrap();
+        assert_eq!(before.len, 200_000);
+        assert_eq!(InputFingerprint::of(file.path()).unwrap(), before);
+
+        // Different header and last blob, same size
+        for offset in [0, data.len() - 1] {
+            data[offset] = 8;
+            std::fs::write(file.path(), &data).unwrap();
+            let after = InputFingerprint::of(file.path()).unwrap();
+            assert_ne!(after.sample_hash, before.sample_hash);
+            data[offset] = 7;
+        }
+    }
+
+    #[test]
+    fn unreadable_sidecar_is_ignored() {
+        let file = NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), b"not json").unwrap();
+        assert_eq!(BlobIndex::load(file.path(), &INPUT), None);
+
+        let missing = file.path().with_extension("missing");
+        assert_eq!(BlobIndex::load(&missing, &INPUT), None);
+    }
+}
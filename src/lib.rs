@@ -0,0 +1,18 @@
+//! cosmo converts OpenStreetMap PBF files into GeoParquet, GeoJSON and GeoJSONL.
+//!
+//! The `cosmo` binary is a thin CLI over this library. Embedders can drive the pipeline
+//! directly, e.g. with a custom node cache via `storage::NodeStore` and
+//! `app::process_pbf_with_node_store`.
+
+pub mod app;
+pub mod config;
+pub mod dsl;
+pub mod expr;
+pub mod index;
+pub mod mapping;
+pub mod metadata;
+pub mod pipeline;
+pub mod report;
+pub mod sinks;
+pub mod storage;
+mod utils;
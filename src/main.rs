@@ -1,20 +1,13 @@
-mod app;
-mod config;
-mod dsl;
-mod expr;
-mod mapping;
-mod metadata;
-mod pipeline;
-mod sinks;
-mod storage;
-mod utils;
-
 use anyhow::{Context, Result};
 use clap::Parser;
 use std::sync::Arc;
 
-use app::{Cli, OutputFormat, init_sink, needs_node_store_compiled, process_pbf, summarize_filters_compiled};
-use config::{FiltersConfig, RuntimeConfig};
+use cosmo::app::{
+    Cli, OutputFormat, init_sink, needs_node_store_compiled, output_format_label, process_pbf,
+    summarize_filters_compiled,
+};
+use cosmo::config::{FiltersConfig, RuntimeConfig};
+use cosmo::report::RunStats;
 
 // anyhow::Result allows us to use ? operator in main to emit errors
 fn main() -> Result<()> {
@@ -66,8 +59,7 @@ fn main() -> Result<()> {
     );
 
     // feedback to user about the filters
-    let (table_name, col_count, has_node, has_way, has_rel) =
-        summarize_filters_compiled(&compiled);
+    let (table_name, col_count, has_node, has_way, has_rel) = summarize_filters_compiled(&compiled);
     tracing::info!(
         "Table: '{}' with {} columns (nodes: {}, ways: {}, relations: {})",
         table_name,
@@ -91,6 +83,10 @@ fn main() -> Result<()> {
             .node_cache_max_nodes
             .unwrap_or(runtime_defaults.node_cache_max_nodes),
         all_tags: cli.all_tags,
+        ordered: cli.ordered,
+        blob_index: cli.blob_index.clone(),
+        memory_limit: cli.memory_limit,
+        progress: cli.progress,
     });
 
     // Detect format from extension if not provided
@@ -112,7 +108,7 @@ fn main() -> Result<()> {
     Initialize selected sink
     ************************
     */
-    let sink = init_sink(&format, &cli.output, &compiled)?;
+    let sink = init_sink(&format, &cli.output, &compiled, &runtime)?;
     let sink_handle = Arc::new(std::sync::Mutex::new(sink));
 
     /*
@@ -122,19 +118,36 @@ fn main() -> Result<()> {
     */
     let needs_nodes = needs_node_store_compiled(&compiled);
     let start = std::time::Instant::now();
-    let match_count = process_pbf(&cli, compiled, runtime, sink_handle.clone(), needs_nodes)?;
+    let stats = Arc::new(RunStats::new());
+    let match_count = process_pbf(
+        &cli,
+        compiled,
+        runtime,
+        sink_handle.clone(),
+        needs_nodes,
+        stats.clone(),
+    )?;
 
     /*
     ********************
     Clean up and metrics
     ********************
     */
-    {
+    let coercion_failures = {
         let mut sink = sink_handle.lock().unwrap();
         sink.finish().context("Pipeline: Failed to finalize sink")?;
-    }
+        sink.coercion_failures()
+    };
 
     let elapsed = start.elapsed();
+    if let Some(path) = &cli.report {
+        let mut report = stats.report(match_count, coercion_failures, elapsed);
+        report.input = Some(cli.input.clone());
+        report.output = Some(cli.output.clone());
+        report.format = Some(output_format_label(&format).to_string());
+        report.write(path)?;
+        tracing::info!("Report written to {:?}", path);
+    }
     tracing::info!(
         "Done! Written {} features in {:.2}s ({} features/s)",
         match_count,
@@ -1,6 +1,6 @@
 //! Mapping rule definitions and evaluation.
 
-use crate::dsl::{FilterAst, evaluate_filter, parse_filter};
+use crate::dsl::{FilterAst, ParseError, evaluate_filter, parse_filter};
 use anyhow::Result;
 use serde::Deserialize;
 use std::collections::HashMap;
@@ -38,17 +38,38 @@ pub struct MappingConfig {
 }
 
 impl Mapping {
-    /// Compile a mapping from config.
-    pub fn compile(name: String, config: &MappingConfig) -> Result<Self> {
+    /// Compile a mapping from config. `locate` can add where a rule that fails to parse is
+    /// written, as the config does for rules loaded from a file.
+    pub fn compile(
+        name: String,
+        config: &MappingConfig,
+        locate: impl Fn(ParseError) -> ParseError,
+    ) -> Result<Self> {
         let mut rules = Vec::with_capacity(config.rules.len());
 
         for (i, rule) in config.rules.iter().enumerate() {
             let filter = parse_filter(&rule.match_expr).map_err(|e| {
-                anyhow::anyhow!("Error parsing rule {} in mapping '{}': {}", i + 1, name, e)
+                anyhow::anyhow!(
+                    "Error parsing rule {} in mapping '{}': {}",
+                    i + 1,
+                    name,
+                    locate(e)
+                )
             })?;
+            // Mappings see only the tags of a feature
+            if filter.analyze().uses_element {
+                anyhow::bail!(
+                    "Error in rule {} of mapping '{}': element predicates like @type are only \
+                     supported in table filters",
+                    i + 1,
+                    name
+                );
+            }
 
             rules.push(CompiledRule {
-                filter,
+                // Rules run on every matching feature, so they get the same treatment as
+                // table filters
+                filter: filter.optimize(),
                 value: rule.value.clone(),
             });
         }
@@ -105,7 +126,7 @@ mod tests {
             default: None,
         };
 
-        Mapping::compile("poi_class".into(), &config).unwrap()
+        Mapping::compile("poi_class".into(), &config, |e| e).unwrap()
     }
 
     #[test]
@@ -152,4 +173,35 @@ mod tests {
             None
         );
     }
+
+    #[test]
+    fn test_element_predicates_are_rejected() {
+        let config = MappingConfig {
+            rules: vec![MappingRule {
+                match_expr: "building & @closed".into(),
+                value: "area".into(),
+            }],
+            default: None,
+        };
+
+        let err = Mapping::compile("kind".into(), &config, |e| e).unwrap_err();
+        assert!(err.to_string().contains("element predicates"), "{err}");
+    }
+
+    #[test]
+    fn test_rules_are_optimized() {
+        let config = MappingConfig {
+            rules: vec![MappingRule {
+                match_expr: "highway=primary | highway=secondary | highway=primary".into(),
+                value: "main".into(),
+            }],
+            default: None,
+        };
+
+        let mapping = Mapping::compile("kind".into(), &config, |e| e).unwrap();
+        assert_eq!(
+            mapping.rules[0].filter,
+            parse_filter("highway=primary|secondary").unwrap()
+        );
+    }
 }
@@ -1,14 +1,32 @@
+use chrono::DateTime;
 use osmpbf::{DenseNodeInfo, Info};
+use serde::{Serialize, Serializer};
 use time::OffsetDateTime;
 use time::format_description::well_known::Rfc3339;
 
+/// Element metadata. Serializes with missing fields omitted, which is how CEL sees `meta`.
+#[derive(Serialize)]
 pub struct MetadataFields {
     pub id: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub visible: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub version: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub changeset: Option<i64>,
+    /// RFC 3339, as written to `meta` columns
+    #[serde(skip)]
     pub timestamp: Option<String>,
+    /// `timestamp` as Unix milliseconds, for comparisons. CEL sees it as a timestamp.
+    #[serde(
+        rename = "timestamp",
+        serialize_with = "serialize_cel_timestamp",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub timestamp_millis: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub uid: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub user: Option<String>,
 }
 
@@ -19,6 +37,7 @@ pub fn build_metadata_from_info(id: i64, info: &Info) -> MetadataFields {
         version: info.version().map(i64::from),
         changeset: info.changeset(),
         timestamp: info.milli_timestamp().and_then(format_timestamp_millis),
+        timestamp_millis: info.milli_timestamp(),
         uid: info.uid().map(i64::from),
         user: info
             .user()
@@ -34,17 +53,35 @@ pub fn build_metadata_from_dense_info(id: i64, info: &DenseNodeInfo) -> Metadata
         version: Some(i64::from(info.version())),
         changeset: Some(info.changeset()),
         timestamp: format_timestamp_millis(info.milli_timestamp()),
+        timestamp_millis: Some(info.milli_timestamp()),
         uid: Some(i64::from(info.uid())),
         user: info.user().ok().map(|s| s.to_string()),
     }
 }
 
+fn serialize_cel_timestamp<S: Serializer>(
+    millis: &Option<i64>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match millis.and_then(DateTime::from_timestamp_millis) {
+        Some(dt) => cel::Timestamp(dt.fixed_offset()).serialize(serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
 pub fn format_timestamp_millis(millis: i64) -> Option<String> {
     let nanos = i128::from(millis) * 1_000_000;
     let dt = OffsetDateTime::from_unix_timestamp_nanos(nanos).ok()?;
     dt.format(&Rfc3339).ok()
 }
 
+/// Parse an RFC 3339 timestamp, as written by [`format_timestamp_millis`], back to Unix
+/// milliseconds.
+pub fn parse_timestamp_millis(timestamp: &str) -> Option<i64> {
+    let dt = OffsetDateTime::parse(timestamp, &Rfc3339).ok()?;
+    i64::try_from(dt.unix_timestamp_nanos() / 1_000_000).ok()
+}
+
 pub fn extract_meta_value(key: &str, metadata: Option<&MetadataFields>) -> Option<String> {
     let meta = metadata?;
     match key {
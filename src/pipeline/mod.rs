@@ -4,48 +4,106 @@ use geo_types::{Geometry, LineString, Point, Polygon};
 use osmpbf::{Element, PrimitiveBlock};
 use serde_json::{Map, Value};
 use std::collections::HashMap;
+use std::ops::RangeInclusive;
 use std::sync::Arc;
 
 use crate::config::{
     ClosedWayMode, ColumnSource, CompiledColumn, CompiledConfig, RuntimeConfig, WayGeometryMode,
 };
-use crate::dsl::evaluate_filter;
-use crate::expr::{cel_value_to_string, evaluate_cel};
+use crate::dsl::{BlockFilter, ElementContext, ElementKind};
+use crate::expr::{CelContext, cel_value_to_string, evaluate_cel};
+use crate::index::BlobKinds;
 use crate::mapping::evaluate_mapping;
 use crate::metadata::{
     MetadataFields, build_metadata_from_dense_info, build_metadata_from_info, extract_meta_value,
 };
+use crate::report::{BlockStats, RunStats};
 use crate::sinks::{ColumnValue, FeatureRow};
-use crate::storage::NodeStoreReader;
+use crate::storage::NodeStore;
 use crate::utils::build_tag_map;
 
 pub trait BlockProcessor: Send + Sync {
     fn process_block(&self, block: PrimitiveBlock) -> Result<Vec<FeatureRow>>;
+
+    /// Element types this processor looks at; blobs without any of them are skipped.
+    fn element_kinds(&self) -> BlobKinds {
+        BlobKinds::ALL
+    }
+
+    /// Element IDs that can produce features; indexed blobs outside the range are skipped.
+    fn element_ids(&self) -> RangeInclusive<i64> {
+        i64::MIN..=i64::MAX
+    }
 }
 
 pub struct StandardProcessor {
     pub config: Arc<CompiledConfig>,
     pub runtime: Arc<RuntimeConfig>,
-    pub node_store: Arc<NodeStoreReader>,
+    pub node_store: Arc<dyn NodeStore>,
+    pub stats: Arc<RunStats>,
 }
 
 impl BlockProcessor for StandardProcessor {
     fn process_block(&self, block: PrimitiveBlock) -> Result<Vec<FeatureRow>> {
-        process_block_collect(block, &self.config, &self.runtime, &self.node_store)
+        let mut stats = BlockStats::default();
+        let rows = process_block_collect(
+            block,
+            &self.config,
+            &self.runtime,
+            self.node_store.as_ref(),
+            &mut stats,
+        )?;
+        self.stats.merge(stats);
+        Ok(rows)
+    }
+
+    fn element_kinds(&self) -> BlobKinds {
+        let geometry = &self.config.table.geometry;
+        let mut kinds = BlobKinds::NONE;
+        if geometry.node {
+            kinds = kinds.union(BlobKinds::NODES);
+        }
+        if geometry.way.enabled() {
+            kinds = kinds.union(BlobKinds::WAYS);
+        }
+        if geometry.relation {
+            kinds = kinds.union(BlobKinds::RELATIONS);
+        }
+        kinds
+    }
+
+    fn element_ids(&self) -> RangeInclusive<i64> {
+        self.config.table.analysis.id_bounds.clone()
     }
 }
 
 pub struct NodesOnlyProcessor {
     pub config: Arc<CompiledConfig>,
     pub runtime: Arc<RuntimeConfig>,
+    pub stats: Arc<RunStats>,
 }
 
 impl BlockProcessor for NodesOnlyProcessor {
     fn process_block(&self, block: PrimitiveBlock) -> Result<Vec<FeatureRow>> {
-        process_block_nodes_only_collect(block, &self.config, &self.runtime)
+        let mut stats = BlockStats::default();
+        let rows =
+            process_block_nodes_only_collect(block, &self.config, &self.runtime, &mut stats)?;
+        self.stats.merge(stats);
+        Ok(rows)
+    }
+
+    fn element_kinds(&self) -> BlobKinds {
+        BlobKinds::NODES
+    }
+
+    fn element_ids(&self) -> RangeInclusive<i64> {
+        self.config.table.analysis.id_bounds.clone()
     }
 }
 
+/// Build the output row of a matching element. Failed CEL evaluations leave their column
+/// empty and are counted in `stats`.
+#[allow(clippy::too_many_arguments)]
 pub fn build_feature_row(
     geometry: Geometry<f64>,
     tags: &HashMap<String, String>,
@@ -54,8 +112,11 @@ pub fn build_feature_row(
     metadata: Option<MetadataFields>,
     refs: Option<Vec<i64>>,
     config: &CompiledConfig,
+    stats: &mut BlockStats,
 ) -> FeatureRow {
     let mut column_values: HashMap<String, ColumnValue> = HashMap::new();
+    // Built on first use and shared by all CEL columns of this feature
+    let mut cel_context: Option<Result<CelContext>> = None;
 
     for col in columns {
         let value = match &col.source {
@@ -86,53 +147,27 @@ pub fn build_feature_row(
                     None
                 }
             }
-            ColumnSource::Refs => {
-                refs.as_ref().map(|r| {
-                    ColumnValue::Json(serde_json::to_value(r).unwrap_or(Value::Null))
-                })
-            }
+            ColumnSource::Refs => refs
+                .as_ref()
+                .map(|r| ColumnValue::Json(serde_json::to_value(r).unwrap_or(Value::Null))),
             ColumnSource::Mapping(name) => config
                 .mappings
                 .get(name)
                 .and_then(|m| evaluate_mapping(m, tags))
                 .map(ColumnValue::String),
             ColumnSource::Cel(program) => {
-                match &metadata {
-                    Some(m) => {
-                        // TODO: Add metadata to CEL context properly if needed beyond tags
-                        let mut meta_map = HashMap::new();
-                        meta_map.insert("id".to_string(), m.id.to_string());
-                        if let Some(v) = &m.version {
-                            meta_map.insert("version".to_string(), v.to_string());
-                        }
-                        if let Some(t) = &m.timestamp {
-                            meta_map.insert("timestamp".to_string(), t.clone());
-                        }
-
-                        let ctx = crate::expr::CelContext {
-                            tags,
-                            meta: &meta_map,
-                        };
-                        match evaluate_cel(program, &ctx) {
-                            Ok(v) => cel_value_to_string(&v).map(ColumnValue::String),
-                            Err(e) => {
-                                tracing::debug!("CEL evaluation failed: {}", e);
-                                None
-                            }
-                        }
-                    }
-                    None => {
-                        let ctx = crate::expr::CelContext {
-                            tags,
-                            meta: &HashMap::new(),
-                        };
-                        match evaluate_cel(program, &ctx) {
-                            Ok(v) => cel_value_to_string(&v).map(ColumnValue::String),
-                            Err(e) => {
-                                tracing::debug!("CEL evaluation failed: {}", e);
-                                None
-                            }
-                        }
+                let ctx =
+                    cel_context.get_or_insert_with(|| CelContext::new(tags, metadata.as_ref()));
+                let result = match ctx {
+                    Ok(ctx) => evaluate_cel(program, ctx),
+                    Err(e) => Err(anyhow::anyhow!("{e}")),
+                };
+                match result {
+                    Ok(v) => cel_value_to_string(&v).map(ColumnValue::String),
+                    Err(e) => {
+                        tracing::debug!("CEL evaluation failed: {}", e);
+                        stats.add_cel_error(&col.name);
+                        None
                     }
                 }
             }
@@ -152,6 +187,7 @@ pub fn build_feature_row(
         extras.insert("tags".to_string(), Value::Object(tags_map));
     }
 
+    stats.add_feature(&geometry);
     FeatureRow {
         geometry,
         columns: column_values,
@@ -191,60 +227,106 @@ pub fn build_way_geometry(
     }
 }
 
+/// The shape spatial predicates see for a way: a polygon when it is closed, whatever the
+/// table's output geometry, and a line otherwise.
+fn way_shape(coords: &[(f64, f64)]) -> Geometry<f64> {
+    let line_string = LineString::from(coords.to_vec());
+    if coords.len() >= 4 && line_string.is_closed() {
+        Geometry::Polygon(Polygon::new(line_string, vec![]))
+    } else {
+        Geometry::LineString(line_string)
+    }
+}
+
 pub fn process_block_collect(
     block: PrimitiveBlock,
     config: &CompiledConfig,
     runtime: &RuntimeConfig,
-    node_store: &NodeStoreReader,
+    node_store: &dyn NodeStore,
+    stats: &mut BlockStats,
 ) -> Result<Vec<FeatureRow>> {
     let mut rows = Vec::new();
     let table = &config.table;
+    let block_filter = BlockFilter::new(&table.filter, &table.analysis, block.raw_stringtable());
+    if !block_filter.may_match() {
+        count_rejected_block(&block, config, stats);
+        return Ok(rows);
+    }
+    // Raw tag indices of the current element, reused across the block
+    let mut raw_tags: Vec<(u32, u32)> = Vec::new();
 
     for element in block.elements() {
         match element {
             Element::Node(node) => {
-                let tag_map = build_tag_map(node.tags());
-                if table.geometry.node && evaluate_filter(&table.filter, &tag_map) {
-                    let row = build_feature_row(
-                        Geometry::Point(Point::new(node.lon(), node.lat())),
-                        &tag_map,
-                        &table.columns,
-                        runtime,
-                        Some(build_metadata_from_info(node.id(), &node.info())),
-                        None,
-                        config,
-                    );
-                    rows.push(row);
+                if !table.geometry.node {
+                    continue;
                 }
+                raw_tags.clear();
+                raw_tags.extend(node.raw_tags());
+                rows.extend(node_feature(
+                    node.id(),
+                    Point::new(node.lon(), node.lat()),
+                    &raw_tags,
+                    || build_tag_map(node.tags()),
+                    || Some(build_metadata_from_info(node.id(), &node.info())),
+                    &block_filter,
+                    config,
+                    runtime,
+                    stats,
+                ));
             }
             Element::DenseNode(node) => {
-                let tag_map = build_tag_map(node.tags());
-                if table.geometry.node && evaluate_filter(&table.filter, &tag_map) {
-                    let metadata = node
-                        .info()
-                        .map(|info| build_metadata_from_dense_info(node.id(), info));
-                    let row = build_feature_row(
-                        Geometry::Point(Point::new(node.lon(), node.lat())),
-                        &tag_map,
-                        &table.columns,
-                        runtime,
-                        metadata,
-                        None,
-                        config,
-                    );
-                    rows.push(row);
+                if !table.geometry.node {
+                    continue;
                 }
+                raw_tags.clear();
+                raw_tags.extend(node.raw_tags().map(|(k, v)| (k as u32, v as u32)));
+                rows.extend(node_feature(
+                    node.id(),
+                    Point::new(node.lon(), node.lat()),
+                    &raw_tags,
+                    || build_tag_map(node.tags()),
+                    || {
+                        node.info()
+                            .map(|info| build_metadata_from_dense_info(node.id(), info))
+                    },
+                    &block_filter,
+                    config,
+                    runtime,
+                    stats,
+                ));
             }
             Element::Way(way) => {
-                let tag_map = build_tag_map(way.tags());
-                if table.geometry.way.enabled() && evaluate_filter(&table.filter, &tag_map) {
+                if !table.geometry.way.enabled() {
+                    continue;
+                }
+                stats.ways += 1;
+                raw_tags.clear();
+                raw_tags.extend(way.raw_tags());
+                let way_metadata = || build_metadata_from_info(way.id(), &way.info());
+                let metadata = block_filter.uses_metadata().then(way_metadata);
+                let element = ElementContext {
+                    kind: ElementKind::Way,
+                    id: way.id(),
+                    closed: block_filter.uses_element() && is_closed(way.refs()),
+                    metadata: metadata.as_ref(),
+                    geometry: None,
+                };
+                if !block_filter.matches(&raw_tags, &element) {
+                    stats.filter_rejected += 1;
+                } else {
+                    let tag_map = build_tag_map(way.tags());
                     let refs: Vec<i64> = way.refs().collect();
-                    let coords: Vec<(f64, f64)> = refs
-                        .iter()
-                        .filter_map(|&id| node_store.get(id as u64))
-                        .collect();
+                    let coords: Vec<(f64, f64)> =
+                        node_store.get_many(&refs).into_iter().flatten().collect();
 
-                    if coords.len() >= 2 {
+                    if coords.len() < 2 {
+                        stats.ways_missing_nodes += 1;
+                    } else if !block_filter
+                        .matches_geometry(&raw_tags, &element, || way_shape(&coords))
+                    {
+                        stats.filter_rejected += 1;
+                    } else {
                         let line_string = LineString::from(coords.clone());
                         let geometry = build_way_geometry(&table.geometry, line_string, &coords);
                         let row = build_feature_row(
@@ -252,9 +334,10 @@ pub fn process_block_collect(
                             &tag_map,
                             &table.columns,
                             runtime,
-                            Some(build_metadata_from_info(way.id(), &way.info())),
+                            Some(metadata.unwrap_or_else(way_metadata)),
                             Some(refs),
                             config,
+                            stats,
                         );
                         rows.push(row);
                     }
@@ -262,6 +345,9 @@ pub fn process_block_collect(
             }
             Element::Relation(_) => {
                 // TODO: Relation support
+                if table.geometry.relation {
+                    stats.relations += 1;
+                }
             }
         }
     }
@@ -269,48 +355,132 @@ pub fn process_block_collect(
     Ok(rows)
 }
 
+/// Filter a plain or dense node and build its feature row, counting it in `stats`. The tag
+/// map is only built for matching nodes, and metadata before filtering only if the filter
+/// reads it.
+#[allow(clippy::too_many_arguments)]
+fn node_feature(
+    id: i64,
+    point: Point<f64>,
+    raw_tags: &[(u32, u32)],
+    tags: impl FnOnce() -> HashMap<String, String>,
+    metadata: impl Fn() -> Option<MetadataFields>,
+    block_filter: &BlockFilter,
+    config: &CompiledConfig,
+    runtime: &RuntimeConfig,
+    stats: &mut BlockStats,
+) -> Option<FeatureRow> {
+    stats.nodes += 1;
+    let filter_metadata = block_filter.uses_metadata().then(&metadata);
+    let element = ElementContext {
+        kind: ElementKind::Node,
+        id,
+        closed: false,
+        metadata: filter_metadata.as_ref().and_then(Option::as_ref),
+        geometry: None,
+    };
+    if !block_filter.matches(raw_tags, &element)
+        || !block_filter.matches_geometry(raw_tags, &element, || point.into())
+    {
+        stats.filter_rejected += 1;
+        return None;
+    }
+    Some(build_feature_row(
+        Geometry::Point(point),
+        &tags(),
+        &config.table.columns,
+        runtime,
+        filter_metadata.unwrap_or_else(metadata),
+        None,
+        config,
+        stats,
+    ))
+}
+
+/// Whether a way's node list ends where it starts.
+fn is_closed(mut refs: impl Iterator<Item = i64>) -> bool {
+    let Some(first) = refs.next() else {
+        return false;
+    };
+    let mut count = 1;
+    let mut last = first;
+    for id in refs {
+        count += 1;
+        last = id;
+    }
+    count > 2 && first == last
+}
+
+/// Count the table's elements of a block the filter cannot match as rejected.
+fn count_rejected_block(block: &PrimitiveBlock, config: &CompiledConfig, stats: &mut BlockStats) {
+    let geometry = &config.table.geometry;
+    for element in block.elements() {
+        match element {
+            Element::Node(_) | Element::DenseNode(_) if geometry.node => stats.nodes += 1,
+            Element::Way(_) if geometry.way.enabled() => stats.ways += 1,
+            Element::Relation(_) if geometry.relation => stats.relations += 1,
+            _ => continue,
+        }
+        stats.filter_rejected += 1;
+    }
+}
+
 pub fn process_block_nodes_only_collect(
     block: PrimitiveBlock,
     config: &CompiledConfig,
     runtime: &RuntimeConfig,
+    stats: &mut BlockStats,
 ) -> Result<Vec<FeatureRow>> {
     let mut rows = Vec::new();
     let table = &config.table;
+    let block_filter = BlockFilter::new(&table.filter, &table.analysis, block.raw_stringtable());
+    if !block_filter.may_match() {
+        count_rejected_block(&block, config, stats);
+        return Ok(rows);
+    }
+    // Raw tag indices of the current element, reused across the block
+    let mut raw_tags: Vec<(u32, u32)> = Vec::new();
 
     for element in block.elements() {
         match element {
             Element::Node(node) => {
-                let tag_map = build_tag_map(node.tags());
-                if table.geometry.node && evaluate_filter(&table.filter, &tag_map) {
-                    let row = build_feature_row(
-                        Geometry::Point(Point::new(node.lon(), node.lat())),
-                        &tag_map,
-                        &table.columns,
-                        runtime,
-                        Some(build_metadata_from_info(node.id(), &node.info())),
-                        None,
-                        config,
-                    );
-                    rows.push(row);
+                if !table.geometry.node {
+                    continue;
                 }
+                raw_tags.clear();
+                raw_tags.extend(node.raw_tags());
+                rows.extend(node_feature(
+                    node.id(),
+                    Point::new(node.lon(), node.lat()),
+                    &raw_tags,
+                    || build_tag_map(node.tags()),
+                    || Some(build_metadata_from_info(node.id(), &node.info())),
+                    &block_filter,
+                    config,
+                    runtime,
+                    stats,
+                ));
             }
             Element::DenseNode(node) => {
-                let tag_map = build_tag_map(node.tags());
-                if table.geometry.node && evaluate_filter(&table.filter, &tag_map) {
-                    let metadata = node
-                        .info()
-                        .map(|info| build_metadata_from_dense_info(node.id(), info));
-                    let row = build_feature_row(
-                        Geometry::Point(Point::new(node.lon(), node.lat())),
-                        &tag_map,
-                        &table.columns,
-                        runtime,
-                        metadata,
-                        None,
-                        config,
-                    );
-                    rows.push(row);
+                if !table.geometry.node {
+                    continue;
                 }
+                raw_tags.clear();
+                raw_tags.extend(node.raw_tags().map(|(k, v)| (k as u32, v as u32)));
+                rows.extend(node_feature(
+                    node.id(),
+                    Point::new(node.lon(), node.lat()),
+                    &raw_tags,
+                    || build_tag_map(node.tags()),
+                    || {
+                        node.info()
+                            .map(|info| build_metadata_from_dense_info(node.id(), info))
+                    },
+                    &block_filter,
+                    config,
+                    runtime,
+                    stats,
+                ));
             }
             _ => {}
         }
@@ -352,6 +522,7 @@ mod tests {
             version: Some(1),
             changeset: Some(2),
             timestamp: Some("2024-01-01T00:00:00Z".to_string()),
+            timestamp_millis: Some(1_704_067_200_000),
             uid: Some(3),
             user: Some("tester".to_string()),
         };
@@ -359,6 +530,7 @@ mod tests {
             table: CompiledTable {
                 name: "test".to_string(),
                 filter: crate::dsl::FilterAst::True,
+                analysis: crate::dsl::FilterAst::True.analyze(),
                 columns: Vec::new(),
                 geometry: crate::config::GeometryConfig::default(),
             },
@@ -372,10 +544,45 @@ mod tests {
             Some(metadata),
             None,
             &config,
+            &mut BlockStats::default(),
         );
         assert!(matches!(
             row.columns.get("timestamp"),
             Some(ColumnValue::String(value)) if value == "2024-01-01T00:00:00Z"
         ));
     }
+
+    #[test]
+    fn refs_column_keeps_negative_and_large_ids() {
+        let columns = vec![CompiledColumn {
+            name: "refs".to_string(),
+            source: ColumnSource::Refs,
+            col_type: ColumnType::Json,
+        }];
+        let config = CompiledConfig {
+            table: CompiledTable {
+                name: "test".to_string(),
+                filter: crate::dsl::FilterAst::True,
+                analysis: crate::dsl::FilterAst::True.analyze(),
+                columns: Vec::new(),
+                geometry: crate::config::GeometryConfig::default(),
+            },
+            mappings: HashMap::new(),
+        };
+        let refs = vec![-5, 13_000_000_000, i64::MAX];
+        let row = build_feature_row(
+            Geometry::Point(Point::new(0.0, 0.0)),
+            &HashMap::new(),
+            &columns,
+            &RuntimeConfig::default(),
+            None,
+            Some(refs),
+            &config,
+            &mut BlockStats::default(),
+        );
+        let Some(ColumnValue::Json(value)) = row.columns.get("refs") else {
+            panic!("expected refs column");
+        };
+        assert_eq!(value, &serde_json::json!([-5, 13_000_000_000i64, i64::MAX]));
+    }
 }
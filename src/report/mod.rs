@@ -0,0 +1,276 @@
+//! Run summary written with `--report`: what was read, filtered and written, and how long
+//! each pass took.
+//!
+//! Workers count into a [`BlockStats`] per block without synchronisation and merge it into the
+//! shared [`RunStats`] once the block is done.
+
+use anyhow::{Context, Result};
+use geo_types::Geometry;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Counters of one block.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct BlockStats {
+    /// Elements of the table's element types in the block, matched or not
+    pub nodes: u64,
+    pub ways: u64,
+    pub relations: u64,
+    /// Elements of the table's element types that the filter rejected
+    pub filter_rejected: u64,
+    /// Matching ways dropped because fewer than two of their nodes are in the node cache
+    pub ways_missing_nodes: u64,
+    /// Features emitted per geometry type
+    pub features: BTreeMap<&'static str, u64>,
+    /// Failed CEL evaluations per column; the column is left empty
+    pub cel_errors: BTreeMap<String, u64>,
+}
+
+impl BlockStats {
+    pub fn add_feature(&mut self, geometry: &Geometry<f64>) {
+        *self.features.entry(geometry_type(geometry)).or_default() += 1;
+    }
+
+    pub fn add_cel_error(&mut self, column: &str) {
+        match self.cel_errors.get_mut(column) {
+            Some(count) => *count += 1,
+            None => {
+                self.cel_errors.insert(column.to_string(), 1);
+            }
+        }
+    }
+
+    fn merge(&mut self, other: BlockStats) {
+        self.nodes += other.nodes;
+        self.ways += other.ways;
+        self.relations += other.relations;
+        self.filter_rejected += other.filter_rejected;
+        self.ways_missing_nodes += other.ways_missing_nodes;
+        for (geometry, count) in other.features {
+            *self.features.entry(geometry).or_default() += count;
+        }
+        for (column, count) in other.cel_errors {
+            *self.cel_errors.entry(column).or_default() += count;
+        }
+    }
+}
+
+fn geometry_type(geometry: &Geometry<f64>) -> &'static str {
+    match geometry {
+        Geometry::Point(_) => "Point",
+        Geometry::Line(_) => "Line",
+        Geometry::LineString(_) => "LineString",
+        Geometry::Polygon(_) => "Polygon",
+        Geometry::MultiPoint(_) => "MultiPoint",
+        Geometry::MultiLineString(_) => "MultiLineString",
+        Geometry::MultiPolygon(_) => "MultiPolygon",
+        Geometry::GeometryCollection(_) => "GeometryCollection",
+        Geometry::Rect(_) => "Rect",
+        Geometry::Triangle(_) => "Triangle",
+    }
+}
+
+/// Statistics of a whole run, shared by the passes and their workers.
+#[derive(Debug, Default)]
+pub struct RunStats {
+    totals: Mutex<BlockStats>,
+    node_cache: Mutex<Option<NodeCacheReport>>,
+    passes: Mutex<Vec<PassTiming>>,
+}
+
+impl RunStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the counters of a finished block.
+    pub fn merge(&self, block: BlockStats) {
+        self.totals.lock().unwrap().merge(block);
+    }
+
+    /// Record the node cache used for the run.
+    pub fn set_node_cache(&self, mode: &str) {
+        *self.node_cache.lock().unwrap() = Some(NodeCacheReport {
+            mode: mode.to_string(),
+            nodes: None,
+        });
+    }
+
+    /// Record the number of nodes written to the node cache in pass 1.
+    pub fn set_nodes_indexed(&self, nodes: u64) {
+        let mut node_cache = self.node_cache.lock().unwrap();
+        node_cache
+            .get_or_insert_with(|| NodeCacheReport {
+                mode: "custom".to_string(),
+                nodes: None,
+            })
+            .nodes = Some(nodes);
+    }
+
+    /// Record how long a step of the run took, e.g. `pass1`.
+    pub fn record_pass(&self, pass: &'static str, elapsed: Duration) {
+        self.passes.lock().unwrap().push(PassTiming {
+            pass,
+            secs: elapsed.as_secs_f64(),
+        });
+    }
+
+    /// Summary of the run so far. `coercion_failures` come from the sink.
+    pub fn report(
+        &self,
+        features_written: u64,
+        coercion_failures: BTreeMap<String, u64>,
+        elapsed: Duration,
+    ) -> RunReport {
+        let totals = self.totals.lock().unwrap().clone();
+        RunReport {
+            input: None,
+            output: None,
+            format: None,
+            elements_considered: ElementCounts {
+                nodes: totals.nodes,
+                ways: totals.ways,
+                relations: totals.relations,
+            },
+            features_written,
+            features_by_geometry: totals.features,
+            filter_rejected: totals.filter_rejected,
+            ways_missing_nodes: totals.ways_missing_nodes,
+            cel_errors: totals.cel_errors,
+            coercion_failures,
+            node_cache: self.node_cache.lock().unwrap().clone(),
+            passes: self.passes.lock().unwrap().clone(),
+            elapsed_secs: elapsed.as_secs_f64(),
+        }
+    }
+}
+
+/// The `--report` file.
+#[derive(Debug, Clone, Serialize)]
+pub struct RunReport {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub input: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<String>,
+    /// Elements the filter was applied to: those of the table's element types in decoded
+    /// blocks. Other element types and blobs skipped via the blob index aren't counted.
+    pub elements_considered: ElementCounts,
+    pub features_written: u64,
+    pub features_by_geometry: BTreeMap<&'static str, u64>,
+    pub filter_rejected: u64,
+    pub ways_missing_nodes: u64,
+    pub cel_errors: BTreeMap<String, u64>,
+    pub coercion_failures: BTreeMap<String, u64>,
+    /// `None` for runs without a node cache (node-only tables)
+    pub node_cache: Option<NodeCacheReport>,
+    pub passes: Vec<PassTiming>,
+    pub elapsed_secs: f64,
+}
+
+impl RunReport {
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let data = serde_json::to_vec_pretty(self)?;
+        std::fs::write(path, data)
+            .with_context(|| format!("Report: Failed to write run report {:?}", path))
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct ElementCounts {
+    pub nodes: u64,
+    pub ways: u64,
+    pub relations: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct NodeCacheReport {
+    pub mode: String,
+    /// Nodes written in pass 1; `None` if the store was already populated
+    pub nodes: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PassTiming {
+    pub pass: &'static str,
+    pub secs: f64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use geo_types::{LineString, Point};
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn block_stats_merge_into_run_totals() {
+        let stats = RunStats::new();
+        for _ in 0..2 {
+            let mut block = BlockStats {
+                nodes: 10,
+                ways: 3,
+                filter_rejected: 7,
+                ways_missing_nodes: 1,
+                ..BlockStats::default()
+            };
+            block.add_feature(&Geometry::Point(Point::new(0.0, 0.0)));
+            block.add_feature(&Geometry::LineString(LineString::from(vec![
+                (0.0, 0.0),
+                (1.0, 1.0),
+            ])));
+            block.add_cel_error("name_en");
+            stats.merge(block);
+        }
+
+        let report = stats.report(4, BTreeMap::new(), Duration::from_secs(1));
+        assert_eq!(report.elements_considered.nodes, 20);
+        assert_eq!(report.elements_considered.ways, 6);
+        assert_eq!(report.filter_rejected, 14);
+        assert_eq!(report.ways_missing_nodes, 2);
+        assert_eq!(report.features_by_geometry["Point"], 2);
+        assert_eq!(report.features_by_geometry["LineString"], 2);
+        assert_eq!(report.cel_errors["name_en"], 2);
+    }
+
+    #[test]
+    fn report_serializes_node_cache_and_pass_timings() {
+        let stats = RunStats::new();
+        stats.set_node_cache("dense");
+        stats.set_nodes_indexed(1234);
+        stats.record_pass("pass1", Duration::from_millis(1500));
+        stats.record_pass("pass2", Duration::from_secs(3));
+
+        let mut failures = BTreeMap::new();
+        failures.insert("lanes".to_string(), 5);
+        let mut report = stats.report(0, failures, Duration::from_secs(5));
+        report.format = Some("geoparquet".to_string());
+
+        let file = NamedTempFile::new().unwrap();
+        report.write(file.path()).unwrap();
+        let json: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(file.path()).unwrap()).unwrap();
+
+        assert_eq!(json["format"], "geoparquet");
+        assert!(json.get("input").is_none());
+        assert_eq!(json["node_cache"]["mode"], "dense");
+        assert_eq!(json["node_cache"]["nodes"], 1234);
+        assert_eq!(json["passes"][0]["pass"], "pass1");
+        assert_eq!(json["passes"][0]["secs"], 1.5);
+        assert_eq!(json["coercion_failures"]["lanes"], 5);
+        assert_eq!(json["elapsed_secs"], 5.0);
+    }
+
+    #[test]
+    fn nodes_indexed_without_a_mode_is_a_custom_store() {
+        let stats = RunStats::new();
+        stats.set_nodes_indexed(3);
+        let report = stats.report(0, BTreeMap::new(), Duration::ZERO);
+        let node_cache = report.node_cache.unwrap();
+        assert_eq!(node_cache.mode, "custom");
+        assert_eq!(node_cache.nodes, Some(3));
+    }
+}
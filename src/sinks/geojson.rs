@@ -1,16 +1,74 @@
-use super::{ColumnValue, DataSink, FeatureRow};
+use super::{ColumnValue, DataSink, EncodedBatch, FeatureEncoder, FeatureRow};
 use anyhow::Result;
 use geojson::{Feature, GeoJson};
 use serde_json::Value;
 use std::fs::File;
 use std::io::{BufWriter, Write};
 use std::path::Path;
+use std::sync::Arc;
+
+/// Separator between features inside the FeatureCollection array
+const FEATURE_SEPARATOR: &[u8] = b",\n";
 
 pub struct GeoJsonSink {
     writer: BufWriter<File>,
     first_feature: bool,
 }
 
+/// Serializes rows as GeoJSON `Feature` objects. Columns are merged into `properties`;
+/// extras take precedence over columns of the same name.
+pub(crate) fn write_feature<W: Write>(writer: &mut W, row: FeatureRow) -> Result<()> {
+    let mut properties = row.extras;
+    for (name, value) in row.columns {
+        if properties.contains_key(&name) {
+            continue;
+        }
+        let json_value = match value {
+            ColumnValue::String(val) => Value::String(val),
+            ColumnValue::Integer(val) => Value::from(val),
+            ColumnValue::Float(val) => Value::from(val),
+            ColumnValue::Json(val) => val,
+        };
+        properties.insert(name, json_value);
+    }
+
+    let geometry = geojson::Geometry::from(&row.geometry);
+    let feature = Feature {
+        bbox: None,
+        geometry: Some(geometry),
+        id: None,
+        properties: Some(properties),
+        foreign_members: None,
+    };
+
+    let geojson = GeoJson::Feature(feature);
+    serde_json::to_writer(writer, &geojson)?;
+    Ok(())
+}
+
+/// Worker-side encoder shared by the GeoJSON and GeoJSONL sinks. Line-delimited output ends
+/// every feature with a newline; otherwise features are joined with the collection separator.
+pub(crate) struct GeoJsonEncoder {
+    pub line_delimited: bool,
+}
+
+impl FeatureEncoder for GeoJsonEncoder {
+    fn encode(&self, rows: Vec<FeatureRow>) -> Result<EncodedBatch> {
+        let count = rows.len();
+        let mut bytes = Vec::new();
+        for (index, row) in rows.into_iter().enumerate() {
+            if index > 0 && !self.line_delimited {
+                bytes.extend_from_slice(FEATURE_SEPARATOR);
+            }
+            write_feature(&mut bytes, row)?;
+            if self.line_delimited {
+                bytes.push(b'\n');
+            }
+        }
+        Ok(EncodedBatch::Json { bytes, count })
+    }
+}
+
 impl GeoJsonSink {
     pub fn new<P: AsRef<Path>>(path: P) -> Result<Self> {
         let file = File::create(path)?;
@@ -31,37 +89,37 @@ impl GeoJsonSink {
 impl DataSink for GeoJsonSink {
     fn add_feature(&mut self, row: FeatureRow) -> Result<()> {
         if !self.first_feature {
-            writeln!(self.writer, ",")?;
+            self.writer.write_all(FEATURE_SEPARATOR)?;
         }
         self.first_feature = false;
 
-        let mut properties = row.extras;
-        for (name, value) in row.columns {
-            if properties.contains_key(&name) {
-                continue;
-            }
-            let json_value = match value {
-                ColumnValue::String(val) => Value::String(val),
-                ColumnValue::Integer(val) => Value::from(val),
-                ColumnValue::Float(val) => Value::from(val),
-                ColumnValue::Json(val) => val,
-            };
-            properties.insert(name, json_value);
-        }
-
-        let geometry = geojson::Geometry::from(&row.geometry);
-        let feature = Feature {
-            bbox: None,
-            geometry: Some(geometry),
-            id: None,
-            properties: Some(properties),
-            foreign_members: None,
-        };
+        write_feature(&mut self.writer, row)
+    }
 
-        let geojson = GeoJson::Feature(feature);
-        serde_json::to_writer(&mut self.writer, &geojson)?;
+    fn encoder(&self) -> Option<Arc<dyn FeatureEncoder>> {
+        Some(Arc::new(GeoJsonEncoder {
+            line_delimited: false,
+        }))
+    }
 
-        Ok(())
+    fn add_encoded(&mut self, batch: EncodedBatch) -> Result<()> {
+        match batch {
+            EncodedBatch::Json { bytes, count } => {
+                if count == 0 {
+                    return Ok(());
+                }
+                if !self.first_feature {
+                    self.writer.write_all(FEATURE_SEPARATOR)?;
+                }
+                self.first_feature = false;
+                self.writer.write_all(&bytes)?;
+                Ok(())
+            }
+            EncodedBatch::Rows(rows) => self.add_features(rows),
+            EncodedBatch::Arrow(_) => Err(anyhow::anyhow!(
+                "Sink: GeoJSON sink cannot append Arrow batches"
+            )),
+        }
     }
 
     fn finish(&mut self) -> Result<()> {
@@ -259,4 +317,36 @@ mod tests {
 
         assert_eq!(parsed["features"][0]["geometry"]["type"], "Polygon");
     }
+
+    #[test]
+    fn encoded_batches_match_feature_by_feature_output() {
+        let direct_file = NamedTempFile::with_suffix(".geojson").unwrap();
+        let mut direct = GeoJsonSink::new(direct_file.path()).unwrap();
+        for name in ["First", "Second", "Third"] {
+            direct
+                .add_feature(create_point_row(name, 0.0, 0.0))
+                .unwrap();
+        }
+        direct.finish().unwrap();
+
+        let encoded_file = NamedTempFile::with_suffix(".geojson").unwrap();
+        let mut sink = GeoJsonSink::new(encoded_file.path()).unwrap();
+        let encoder = sink.encoder().unwrap();
+        let first = encoder
+            .encode(vec![
+                create_point_row("First", 0.0, 0.0),
+                create_point_row("Second", 0.0, 0.0),
+            ])
+            .unwrap();
+        assert_eq!(first.len(), 2);
+        sink.add_encoded(first).unwrap();
+        sink.add_encoded(encoder.encode(vec![]).unwrap()).unwrap();
+        let rows = EncodedBatch::Rows(vec![create_point_row("Third", 0.0, 0.0)]);
+        sink.add_encoded(rows).unwrap();
+        sink.finish().unwrap();
+
+        let direct_content = std::fs::read_to_string(direct_file.path()).unwrap();
+        let encoded_content = std::fs::read_to_string(encoded_file.path()).unwrap();
+        assert_eq!(direct_content, encoded_content);
+    }
 }
@@ -1,10 +1,10 @@
-use super::{ColumnValue, DataSink, FeatureRow};
+use super::geojson::{GeoJsonEncoder, write_feature};
+use super::{DataSink, EncodedBatch, FeatureEncoder, FeatureRow};
 use anyhow::Result;
-use geojson::{Feature, GeoJson};
-use serde_json::Value;
 use std::fs::File;
 use std::io::{BufWriter, Write};
 use std::path::Path;
+use std::sync::Arc;
 
 pub struct GeoJsonlSink {
     writer: BufWriter<Box<dyn Write + Send>>,
@@ -27,35 +27,31 @@ impl GeoJsonlSink {
 
 impl DataSink for GeoJsonlSink {
     fn add_feature(&mut self, row: FeatureRow) -> Result<()> {
-        let mut properties = row.extras;
-        for (name, value) in row.columns {
-            if properties.contains_key(&name) {
-                continue;
-            }
-            let json_value = match value {
-                ColumnValue::String(val) => Value::String(val),
-                ColumnValue::Integer(val) => Value::from(val),
-                ColumnValue::Float(val) => Value::from(val),
-                ColumnValue::Json(val) => val,
-            };
-            properties.insert(name, json_value);
-        }
-
-        let geometry = geojson::Geometry::from(&row.geometry);
-        let feature = Feature {
-            bbox: None,
-            geometry: Some(geometry),
-            id: None,
-            properties: Some(properties),
-            foreign_members: None,
-        };
-
-        let geojson = GeoJson::Feature(feature);
-        serde_json::to_writer(&mut self.writer, &geojson)?;
+        write_feature(&mut self.writer, row)?;
         writeln!(self.writer)?;
         Ok(())
     }
 
+    fn encoder(&self) -> Option<Arc<dyn FeatureEncoder>> {
+        Some(Arc::new(GeoJsonEncoder {
+            line_delimited: true,
+        }))
+    }
+
+    fn add_encoded(&mut self, batch: EncodedBatch) -> Result<()> {
+        match batch {
+            // Every encoded feature already ends with a newline
+            EncodedBatch::Json { bytes, .. } => {
+                self.writer.write_all(&bytes)?;
+                Ok(())
+            }
+            EncodedBatch::Rows(rows) => self.add_features(rows),
+            EncodedBatch::Arrow(_) => Err(anyhow::anyhow!(
+                "Sink: GeoJSONL sink cannot append Arrow batches"
+            )),
+        }
+    }
+
     fn finish(&mut self) -> Result<()> {
         self.writer.flush()?;
         Ok(())
@@ -65,6 +61,7 @@ impl DataSink for GeoJsonlSink {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::sinks::ColumnValue;
     use geo_types::{LineString, Point, Polygon};
     use serde_json::Map;
     use std::collections::HashMap;
@@ -264,4 +261,37 @@ mod tests {
             assert!(line.ends_with('}'));
         }
     }
+
+    #[test]
+    fn encoded_batches_match_feature_by_feature_output() {
+        let direct_file = NamedTempFile::with_suffix(".geojsonl").unwrap();
+        let mut direct = GeoJsonlSink::new(direct_file.path()).unwrap();
+        for name in ["First", "Second", "Third"] {
+            direct
+                .add_feature(create_point_row(name, 0.0, 0.0))
+                .unwrap();
+        }
+        direct.finish().unwrap();
+
+        let encoded_file = NamedTempFile::with_suffix(".geojsonl").unwrap();
+        let mut sink = GeoJsonlSink::new(encoded_file.path()).unwrap();
+        let encoder = sink.encoder().unwrap();
+        let batch = encoder
+            .encode(vec![
+                create_point_row("First", 0.0, 0.0),
+                create_point_row("Second", 0.0, 0.0),
+            ])
+            .unwrap();
+        assert_eq!(batch.len(), 2);
+        sink.add_encoded(batch).unwrap();
+        let batch = encoder
+            .encode(vec![create_point_row("Third", 0.0, 0.0)])
+            .unwrap();
+        sink.add_encoded(batch).unwrap();
+        sink.finish().unwrap();
+
+        let direct_content = std::fs::read_to_string(direct_file.path()).unwrap();
+        let encoded_content = std::fs::read_to_string(encoded_file.path()).unwrap();
+        assert_eq!(direct_content, encoded_content);
+    }
 }
@@ -1,30 +1,55 @@
-use super::{ColumnSpec, ColumnType, ColumnValue, DataSink, FeatureRow};
-use anyhow::{Context, Result};
-use arrow_array::{BinaryArray, Float64Array, Int64Array, RecordBatch, StringArray};
+use super::{
+    ColumnSpec, ColumnType, ColumnValue, DataSink, EncodedBatch, FeatureEncoder, FeatureRow,
+};
+use anyhow::{Context, Result, anyhow};
+use arrow_array::builder::{BinaryBuilder, Float64Builder, Int64Builder, StringBuilder};
+use arrow_array::{ArrayRef, RecordBatch};
 use arrow_schema::{DataType, Field, Schema};
 use geozero::{CoordDimensions, ToWkb};
 use parquet::arrow::ArrowWriter;
 use parquet::file::metadata::KeyValue;
 use parquet::file::properties::WriterProperties;
 use proj::Proj;
+use std::collections::BTreeMap;
 use std::fs::File;
 use std::path::Path;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
 
 pub struct GeoParquetSink {
     writer: Option<ArrowWriter<File>>,
     schema: Arc<Schema>,
     columns: Vec<ColumnSpec>,
-    column_buffers: Vec<ColumnBuffer>,
-    geometry_buf: Vec<Vec<u8>>,
-    properties_buf: Vec<String>,
+    /// Rows not yet written, converted to a record batch once `batch_size` rows or
+    /// `max_pending_bytes` are reached
+    pending: Vec<FeatureRow>,
+    pending_bytes: usize,
     batch_size: usize,
+    max_pending_bytes: usize,
+    /// The writer's in-progress row group is flushed once it grows past this size
+    max_row_group_bytes: usize,
+    /// Per explicit column, values that failed to coerce; shared with the encoder
+    coercion_failures: Arc<[AtomicU64]>,
 }
 
-enum ColumnBuffer {
-    String(Vec<Option<String>>),
-    Integer(Vec<Option<i64>>),
-    Float(Vec<Option<f64>>),
+// Share of `--memory-limit` for rows waiting to become a record batch.
+const PENDING_SHARE: u64 = 8;
+// Share of `--memory-limit` for the row group the Parquet writer buffers.
+const ROW_GROUP_SHARE: u64 = 4;
+
+/// Arrow builder for one explicit column, filled directly from the rows' values.
+enum ColumnBuilder {
+    String(StringBuilder),
+    Integer(Int64Builder),
+    Float(Float64Builder),
+}
+
+/// Worker-side encoder: WKB, column coercion and properties JSON are all done here, so the
+/// sink only hands finished record batches to the `ArrowWriter`.
+struct GeoParquetEncoder {
+    schema: Arc<Schema>,
+    columns: Vec<ColumnSpec>,
+    coercion_failures: Arc<[AtomicU64]>,
 }
 
 impl GeoParquetSink {
@@ -73,92 +98,117 @@ impl GeoParquetSink {
             .build();
 
         let writer = ArrowWriter::try_new(file, schema.clone(), Some(props))?;
-
-        let column_buffers = columns
-            .iter()
-            .map(|col| match col.col_type {
-                ColumnType::String => ColumnBuffer::String(Vec::new()),
-                ColumnType::Integer => ColumnBuffer::Integer(Vec::new()),
-                ColumnType::Float => ColumnBuffer::Float(Vec::new()),
-                ColumnType::Json => ColumnBuffer::String(Vec::new()),
-            })
-            .collect();
+        let coercion_failures = columns.iter().map(|_| AtomicU64::new(0)).collect();
 
         Ok(Self {
             writer: Some(writer),
             schema,
             columns,
-            column_buffers,
-            geometry_buf: Vec::new(),
-            properties_buf: Vec::new(),
+            pending: Vec::new(),
+            pending_bytes: 0,
             batch_size: 10_000,
+            max_pending_bytes: usize::MAX,
+            max_row_group_bytes: usize::MAX,
+            coercion_failures,
         })
     }
 
-    fn flush(&mut self) -> Result<()> {
-        if self.geometry_buf.is_empty() {
-            return Ok(());
-        }
+    /// Bound the rows and row group buffered in memory by bytes (`--memory-limit`), so wide
+    /// rows produce smaller batches and row groups instead of a fixed row count.
+    pub fn with_memory_limit(mut self, limit: u64) -> Self {
+        let share = |divisor: u64| {
+            usize::try_from(limit / divisor)
+                .unwrap_or(usize::MAX)
+                .max(1)
+        };
+        self.max_pending_bytes = share(PENDING_SHARE);
+        self.max_row_group_bytes = share(ROW_GROUP_SHARE);
+        self
+    }
 
-        let geometry_array = BinaryArray::from_iter_values(self.geometry_buf.iter().cloned());
-        let mut arrays: Vec<Arc<dyn arrow_array::Array>> = Vec::new();
-        arrays.push(Arc::new(geometry_array));
+    fn pending_is_full(&self) -> bool {
+        self.pending.len() >= self.batch_size || self.pending_bytes >= self.max_pending_bytes
+    }
 
-        for buffer in &self.column_buffers {
-            match buffer {
-                ColumnBuffer::String(values) => {
-                    arrays.push(Arc::new(StringArray::from(values.clone())));
-                }
-                ColumnBuffer::Integer(values) => {
-                    arrays.push(Arc::new(Int64Array::from(values.clone())));
-                }
-                ColumnBuffer::Float(values) => {
-                    arrays.push(Arc::new(Float64Array::from(values.clone())));
-                }
-            }
+    fn flush(&mut self) -> Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
         }
 
-        let properties_array = StringArray::from_iter_values(self.properties_buf.iter().cloned());
-        arrays.push(Arc::new(properties_array));
-
-        let batch = RecordBatch::try_new(self.schema.clone(), arrays)?;
+        let rows = std::mem::take(&mut self.pending);
+        self.pending_bytes = 0;
+        let batch = build_record_batch(&self.schema, &self.columns, &self.coercion_failures, rows)?;
+        self.write_batch(&batch)
+    }
 
+    fn write_batch(&mut self, batch: &RecordBatch) -> Result<()> {
         if let Some(writer) = self.writer.as_mut() {
-            writer.write(&batch)?;
-        }
-        self.geometry_buf.clear();
-        self.properties_buf.clear();
-        for buffer in &mut self.column_buffers {
-            match buffer {
-                ColumnBuffer::String(values) => values.clear(),
-                ColumnBuffer::Integer(values) => values.clear(),
-                ColumnBuffer::Float(values) => values.clear(),
+            writer.write(batch)?;
+            if writer.in_progress_size() >= self.max_row_group_bytes {
+                writer.flush()?;
             }
         }
-
         Ok(())
     }
 }
 
 impl DataSink for GeoParquetSink {
     fn add_feature(&mut self, row: FeatureRow) -> Result<()> {
-        let wkb = row
-            .geometry
-            .to_wkb(CoordDimensions::xy())
-            .context("Failed to convert geometry to WKB")?;
-        let properties_json = serde_json::to_string(&row.extras)?;
-
-        self.geometry_buf.push(wkb);
-        self.properties_buf.push(properties_json);
-        self.append_columns(&row);
+        self.pending_bytes += row.estimated_bytes();
+        self.pending.push(row);
 
-        if self.geometry_buf.len() >= self.batch_size {
+        if self.pending_is_full() {
             self.flush()?;
         }
 
         Ok(())
     }
 
+    fn add_features(&mut self, rows: Vec<FeatureRow>) -> Result<()> {
+        if self.pending.is_empty() && rows.len() >= self.batch_size {
+            // Large batches skip the pending buffer
+            let batch =
+                build_record_batch(&self.schema, &self.columns, &self.coercion_failures, rows)?;
+            return self.write_batch(&batch);
+        }
+
+        for row in rows {
+            self.add_feature(row)?;
+        }
+        Ok(())
+    }
+
+    fn encoder(&self) -> Option<Arc<dyn FeatureEncoder>> {
+        Some(Arc::new(GeoParquetEncoder {
+            schema: self.schema.clone(),
+            columns: self.columns.clone(),
+            coercion_failures: self.coercion_failures.clone(),
+        }))
+    }
+
+    fn add_encoded(&mut self, batch: EncodedBatch) -> Result<()> {
+        match batch {
+            EncodedBatch::Arrow(batch) => {
+                // Rows added one by one come first to keep the output order
+                self.flush()?;
+                self.write_batch(&batch)
+            }
+            EncodedBatch::Rows(rows) => self.add_features(rows),
+            EncodedBatch::Json { .. } => {
+                Err(anyhow!("Sink: GeoParquet sink cannot append JSON batches"))
+            }
+        }
+    }
+
+    fn coercion_failures(&self) -> BTreeMap<String, u64> {
+        self.columns
+            .iter()
+            .zip(self.coercion_failures.iter())
+            .map(|(col, failures)| (col.name.clone(), failures.load(Ordering::Relaxed)))
+            .filter(|(_, failures)| *failures > 0)
+            .collect()
+    }
+
     fn finish(&mut self) -> Result<()> {
         self.flush()?;
         if let Some(writer) = self.writer.take() {
@@ -168,27 +218,105 @@ impl DataSink for GeoParquetSink {
     }
 }
 
-impl GeoParquetSink {
-    fn append_columns(&mut self, row: &FeatureRow) {
-        for (index, col) in self.columns.iter().enumerate() {
-            match &mut self.column_buffers[index] {
-                ColumnBuffer::String(values) => {
-                    values.push(coerce_string(row.columns.get(&col.name)));
-                }
-                ColumnBuffer::Integer(values) => {
-                    values.push(coerce_i64(row.columns.get(&col.name)));
-                }
-                ColumnBuffer::Float(values) => {
-                    values.push(coerce_f64(row.columns.get(&col.name)));
-                }
+impl FeatureEncoder for GeoParquetEncoder {
+    fn encode(&self, rows: Vec<FeatureRow>) -> Result<EncodedBatch> {
+        let batch = build_record_batch(&self.schema, &self.columns, &self.coercion_failures, rows)?;
+        Ok(EncodedBatch::Arrow(batch))
+    }
+}
+
+/// Build a record batch column by column. Column values are moved out of the rows, and WKB and
+/// properties JSON go straight into the Arrow builders through reused scratch buffers.
+/// Values that can't be coerced to their column's type are written as null and counted in
+/// `coercion_failures`.
+fn build_record_batch(
+    schema: &Arc<Schema>,
+    columns: &[ColumnSpec],
+    coercion_failures: &[AtomicU64],
+    rows: Vec<FeatureRow>,
+) -> Result<RecordBatch> {
+    let len = rows.len();
+    let mut geometry = BinaryBuilder::with_capacity(len, len * 64);
+    let mut properties = StringBuilder::with_capacity(len, len * 16);
+    let mut builders: Vec<ColumnBuilder> = columns
+        .iter()
+        .map(|col| ColumnBuilder::new(col.col_type, len))
+        .collect();
+    let mut json = Vec::new();
+
+    for mut row in rows {
+        let wkb = row
+            .geometry
+            .to_wkb(CoordDimensions::xy())
+            .context("Failed to convert geometry to WKB")?;
+        geometry.append_value(&wkb);
+
+        json.clear();
+        serde_json::to_writer(&mut json, &row.extras)?;
+        properties.append_value(std::str::from_utf8(&json)?);
+
+        for ((col, builder), failures) in columns.iter().zip(&mut builders).zip(coercion_failures) {
+            if !builder.append(row.columns.remove(&col.name)) {
+                failures.fetch_add(1, Ordering::Relaxed);
             }
         }
     }
+
+    let mut arrays: Vec<ArrayRef> = Vec::with_capacity(columns.len() + 2);
+    arrays.push(Arc::new(geometry.finish()));
+    arrays.extend(builders.iter_mut().map(ColumnBuilder::finish));
+    arrays.push(Arc::new(properties.finish()));
+
+    Ok(RecordBatch::try_new(schema.clone(), arrays)?)
 }
 
-fn coerce_string(value: Option<&ColumnValue>) -> Option<String> {
+impl ColumnBuilder {
+    fn new(col_type: ColumnType, capacity: usize) -> Self {
+        match col_type {
+            ColumnType::String | ColumnType::Json => {
+                ColumnBuilder::String(StringBuilder::with_capacity(capacity, capacity * 8))
+            }
+            ColumnType::Integer => ColumnBuilder::Integer(Int64Builder::with_capacity(capacity)),
+            ColumnType::Float => ColumnBuilder::Float(Float64Builder::with_capacity(capacity)),
+        }
+    }
+
+    /// Append a value, returning false if it was present but couldn't be coerced.
+    fn append(&mut self, value: Option<ColumnValue>) -> bool {
+        let present = value.is_some();
+        let appended = match self {
+            ColumnBuilder::String(builder) => {
+                let value = coerce_string(value);
+                let appended = value.is_some();
+                builder.append_option(value);
+                appended
+            }
+            ColumnBuilder::Integer(builder) => {
+                let value = coerce_i64(value.as_ref());
+                builder.append_option(value);
+                value.is_some()
+            }
+            ColumnBuilder::Float(builder) => {
+                let value = coerce_f64(value.as_ref());
+                builder.append_option(value);
+                value.is_some()
+            }
+        };
+        appended || !present
+    }
+
+    fn finish(&mut self) -> ArrayRef {
+        match self {
+            ColumnBuilder::String(builder) => Arc::new(builder.finish()),
+            ColumnBuilder::Integer(builder) => Arc::new(builder.finish()),
+            ColumnBuilder::Float(builder) => Arc::new(builder.finish()),
+        }
+    }
+}
+
+fn coerce_string(value: Option<ColumnValue>) -> Option<String> {
     match value {
-        Some(ColumnValue::String(s)) => Some(s.clone()),
+        Some(ColumnValue::String(s)) => Some(s),
         Some(ColumnValue::Integer(n)) => Some(n.to_string()),
         Some(ColumnValue::Float(n)) => Some(n.to_string()),
         Some(ColumnValue::Json(v)) => Some(v.to_string()),
@@ -219,6 +347,7 @@ fn coerce_f64(value: Option<&ColumnValue>) -> Option<f64> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use arrow_array::{Array, Int64Array};
     use geo_types::{LineString, Point, Polygon};
     use serde_json::Map;
     use std::collections::HashMap;
@@ -231,19 +360,19 @@ mod tests {
     #[test]
     fn coerce_string_from_string() {
         let value = ColumnValue::String("hello".to_string());
-        assert_eq!(coerce_string(Some(&value)), Some("hello".to_string()));
+        assert_eq!(coerce_string(Some(value)), Some("hello".to_string()));
     }
 
     #[test]
     fn coerce_string_from_integer() {
         let value = ColumnValue::Integer(42);
-        assert_eq!(coerce_string(Some(&value)), Some("42".to_string()));
+        assert_eq!(coerce_string(Some(value)), Some("42".to_string()));
     }
 
     #[test]
     fn coerce_string_from_float() {
         let value = ColumnValue::Float(3.1);
-        assert_eq!(coerce_string(Some(&value)), Some("3.1".to_string()));
+        assert_eq!(coerce_string(Some(value)), Some("3.1".to_string()));
     }
 
     #[test]
@@ -324,7 +453,7 @@ mod tests {
     #[test]
     fn coerce_string_from_negative_integer() {
         let value = ColumnValue::Integer(-42);
-        assert_eq!(coerce_string(Some(&value)), Some("-42".to_string()));
+        assert_eq!(coerce_string(Some(value)), Some("-42".to_string()));
     }
 
     #[test]
@@ -553,4 +682,247 @@ mod tests {
         // Don't add any features
         assert!(sink.finish().is_ok());
     }
+
+    #[test]
+    fn encoded_batches_are_written_after_buffered_rows() {
+        use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+        let temp_file = NamedTempFile::with_suffix(".parquet").unwrap();
+        let columns = vec![ColumnSpec {
+            name: "population".to_string(),
+            col_type: ColumnType::Integer,
+        }];
+        let mut sink = GeoParquetSink::new(temp_file.path(), columns).unwrap();
+        let encoder = sink.encoder().unwrap();
+
+        let row = |population: i64| {
+            let mut col_map = HashMap::new();
+            col_map.insert("population".to_string(), ColumnValue::Integer(population));
+            FeatureRow {
+                geometry: geo_types::Geometry::Point(Point::new(0.0, 0.0)),
+                columns: col_map,
+                extras: Map::new(),
+            }
+        };
+
+        sink.add_feature(row(1)).unwrap();
+        let batch = encoder.encode(vec![row(2), row(3)]).unwrap();
+        let EncodedBatch::Arrow(record_batch) = &batch else {
+            panic!("expected an Arrow batch");
+        };
+        assert_eq!(record_batch.num_rows(), 2);
+        assert_eq!(record_batch.schema(), sink.schema);
+        sink.add_encoded(batch).unwrap();
+        sink.finish().unwrap();
+
+        let reader =
+            ParquetRecordBatchReaderBuilder::try_new(File::open(temp_file.path()).unwrap())
+                .unwrap()
+                .build()
+                .unwrap();
+        let mut populations = Vec::new();
+        for batch in reader {
+            let batch = batch.unwrap();
+            let column = batch
+                .column_by_name("population")
+                .unwrap()
+                .as_any()
+                .downcast_ref::<Int64Array>()
+                .unwrap()
+                .clone();
+            populations.extend(column.iter().flatten());
+        }
+        assert_eq!(populations, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn memory_limit_flushes_wide_rows_early() {
+        use parquet::file::reader::{FileReader, SerializedFileReader};
+
+        let temp_file = NamedTempFile::with_suffix(".parquet").unwrap();
+        let columns = vec![ColumnSpec {
+            name: "name".to_string(),
+            col_type: ColumnType::String,
+        }];
+        let mut sink = GeoParquetSink::new(temp_file.path(), columns)
+            .unwrap()
+            .with_memory_limit(80_000);
+        assert_eq!(sink.max_pending_bytes, 10_000);
+        assert_eq!(sink.max_row_group_bytes, 20_000);
+
+        let mut count = 0;
+        let mut wide_row = || {
+            count += 1;
+            let mut col_map = HashMap::new();
+            // Distinct values, so dictionary encoding doesn't shrink the row group
+            let name = format!("{count:08}").repeat(500);
+            col_map.insert("name".to_string(), ColumnValue::String(name));
+            FeatureRow {
+                geometry: geo_types::Geometry::Point(Point::new(0.0, 0.0)),
+                columns: col_map,
+                extras: Map::new(),
+            }
+        };
+
+        sink.add_feature(wide_row()).unwrap();
+        sink.add_feature(wide_row()).unwrap();
+        assert_eq!(sink.pending.len(), 2);
+        sink.add_feature(wide_row()).unwrap();
+        assert!(sink.pending.is_empty());
+        assert_eq!(sink.pending_bytes, 0);
+
+        for _ in 0..10 {
+            sink.add_feature(wide_row()).unwrap();
+        }
+        sink.finish().unwrap();
+
+        let reader = SerializedFileReader::new(File::open(temp_file.path()).unwrap()).unwrap();
+        let metadata = reader.metadata();
+        assert!(metadata.num_row_groups() > 1);
+        assert_eq!(metadata.file_metadata().num_rows(), 13);
+    }
+
+    #[test]
+    fn record_batch_moves_and_coerces_column_values() {
+        let columns = vec![
+            ColumnSpec {
+                name: "name".to_string(),
+                col_type: ColumnType::String,
+            },
+            ColumnSpec {
+                name: "height".to_string(),
+                col_type: ColumnType::Float,
+            },
+        ];
+        let temp_file = NamedTempFile::with_suffix(".parquet").unwrap();
+        let sink = GeoParquetSink::new(temp_file.path(), columns.clone()).unwrap();
+
+        let mut col_map = HashMap::new();
+        col_map.insert("name".to_string(), ColumnValue::Integer(7));
+        col_map.insert(
+            "height".to_string(),
+            ColumnValue::String("12.5".to_string()),
+        );
+        let mut extras = Map::new();
+        extras.insert("note".to_string(), serde_json::json!("x"));
+        let rows = vec![
+            FeatureRow {
+                geometry: geo_types::Geometry::Point(Point::new(0.0, 0.0)),
+                columns: col_map,
+                extras,
+            },
+            FeatureRow {
+                geometry: geo_types::Geometry::Point(Point::new(1.0, 1.0)),
+                columns: HashMap::new(),
+                extras: Map::new(),
+            },
+        ];
+
+        let batch =
+            build_record_batch(&sink.schema, &columns, &sink.coercion_failures, rows).unwrap();
+        assert_eq!(batch.num_rows(), 2);
+        assert!(sink.coercion_failures().is_empty());
+
+        let names = batch.column_by_name("name").unwrap().as_any();
+        let names = names.downcast_ref::<arrow_array::StringArray>().unwrap();
+        assert_eq!(names.value(0), "7");
+        assert!(names.is_null(1));
+
+        let heights = batch.column_by_name("height").unwrap().as_any();
+        let heights = heights.downcast_ref::<arrow_array::Float64Array>().unwrap();
+        assert_eq!(heights.value(0), 12.5);
+        assert!(heights.is_null(1));
+
+        let properties = batch.column_by_name("properties").unwrap().as_any();
+        let properties = properties
+            .downcast_ref::<arrow_array::StringArray>()
+            .unwrap();
+        assert_eq!(properties.value(0), r#"{"note":"x"}"#);
+        assert_eq!(properties.value(1), "{}");
+    }
+
+    #[test]
+    fn coercion_failures_are_counted_per_column() {
+        let temp_file = NamedTempFile::with_suffix(".parquet").unwrap();
+        let columns = vec![
+            ColumnSpec {
+                name: "lanes".to_string(),
+                col_type: ColumnType::Integer,
+            },
+            ColumnSpec {
+                name: "name".to_string(),
+                col_type: ColumnType::String,
+            },
+        ];
+        let mut sink = GeoParquetSink::new(temp_file.path(), columns).unwrap();
+        let encoder = sink.encoder().unwrap();
+
+        let row = |lanes: Option<&str>| {
+            let mut col_map = HashMap::new();
+            if let Some(lanes) = lanes {
+                col_map.insert("lanes".to_string(), ColumnValue::String(lanes.to_string()));
+            }
+            col_map.insert("name".to_string(), ColumnValue::String("x".to_string()));
+            FeatureRow {
+                geometry: geo_types::Geometry::Point(Point::new(0.0, 0.0)),
+                columns: col_map,
+                extras: Map::new(),
+            }
+        };
+
+        sink.add_feature(row(Some("two"))).unwrap();
+        sink.add_feature(row(None)).unwrap();
+        let batch = encoder
+            .encode(vec![row(Some("2")), row(Some("2;3"))])
+            .unwrap();
+        sink.add_encoded(batch).unwrap();
+        sink.finish().unwrap();
+
+        let failures = sink.coercion_failures();
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures["lanes"], 2);
+    }
+
+    #[test]
+    fn add_features_flushes_large_batches_in_order() {
+        use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+        let temp_file = NamedTempFile::with_suffix(".parquet").unwrap();
+        let columns = vec![ColumnSpec {
+            name: "population".to_string(),
+            col_type: ColumnType::Integer,
+        }];
+        let mut sink = GeoParquetSink::new(temp_file.path(), columns).unwrap();
+        sink.batch_size = 2;
+
+        let row = |population: i64| {
+            let mut col_map = HashMap::new();
+            col_map.insert("population".to_string(), ColumnValue::Integer(population));
+            FeatureRow {
+                geometry: geo_types::Geometry::Point(Point::new(0.0, 0.0)),
+                columns: col_map,
+                extras: Map::new(),
+            }
+        };
+
+        sink.add_feature(row(1)).unwrap();
+        sink.add_features(vec![row(2), row(3), row(4)]).unwrap();
+        assert!(sink.pending.is_empty());
+        sink.add_features(vec![row(5)]).unwrap();
+        sink.finish().unwrap();
+
+        let reader =
+            ParquetRecordBatchReaderBuilder::try_new(File::open(temp_file.path()).unwrap())
+                .unwrap()
+                .build()
+                .unwrap();
+        let mut populations = Vec::new();
+        for batch in reader {
+            let batch = batch.unwrap();
+            let column = batch.column_by_name("population").unwrap().as_any();
+            let column = column.downcast_ref::<Int64Array>().unwrap();
+            populations.extend(column.iter().flatten());
+        }
+        assert_eq!(populations, vec![1, 2, 3, 4, 5]);
+    }
 }
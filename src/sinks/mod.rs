@@ -1,7 +1,10 @@
-use anyhow::Result;
+use anyhow::{Result, anyhow};
+use arrow_array::RecordBatch;
+use geo::CoordsIter;
 use geo_types::Geometry;
 use serde_json::{Map, Value};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
 
 pub mod geojson;
 pub mod geojsonl;
@@ -36,7 +39,110 @@ pub struct FeatureRow {
     pub extras: Map<String, Value>,
 }
 
+impl FeatureRow {
+    /// Rough heap size of the row, used to bound memory held in channels and sink buffers.
+    pub fn estimated_bytes(&self) -> usize {
+        let geometry = self.geometry.coords_count() * std::mem::size_of::<(f64, f64)>();
+        let columns: usize = self
+            .columns
+            .iter()
+            .map(|(name, value)| {
+                name.len()
+                    + match value {
+                        ColumnValue::String(s) => s.len(),
+                        ColumnValue::Integer(_) | ColumnValue::Float(_) => 8,
+                        ColumnValue::Json(value) => json_bytes(value),
+                    }
+            })
+            .sum();
+        let extras: usize = self
+            .extras
+            .iter()
+            .map(|(key, value)| key.len() + json_bytes(value))
+            .sum();
+        std::mem::size_of::<FeatureRow>() + geometry + columns + extras
+    }
+}
+
+fn json_bytes(value: &Value) -> usize {
+    match value {
+        Value::String(s) => s.len(),
+        Value::Array(values) => values.iter().map(json_bytes).sum(),
+        Value::Object(map) => map.iter().map(|(k, v)| k.len() + json_bytes(v)).sum(),
+        Value::Null | Value::Bool(_) | Value::Number(_) => 8,
+    }
+}
+
+/// Features already encoded by a sink's `FeatureEncoder`, ready to be appended.
+pub enum EncodedBatch {
+    /// Rows for sinks without an encoder (or blocks without features)
+    Rows(Vec<FeatureRow>),
+    /// Serialized GeoJSON features, in the layout of the encoding sink
+    Json { bytes: Vec<u8>, count: usize },
+    /// Record batch matching the sink's Arrow schema
+    Arrow(RecordBatch),
+}
+
+impl EncodedBatch {
+    /// Number of features in the batch
+    pub fn len(&self) -> usize {
+        match self {
+            EncodedBatch::Rows(rows) => rows.len(),
+            EncodedBatch::Json { count, .. } => *count,
+            EncodedBatch::Arrow(batch) => batch.num_rows(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Approximate memory held by the batch
+    pub fn estimated_bytes(&self) -> usize {
+        match self {
+            EncodedBatch::Rows(rows) => rows.iter().map(FeatureRow::estimated_bytes).sum(),
+            EncodedBatch::Json { bytes, .. } => bytes.capacity(),
+            EncodedBatch::Arrow(batch) => batch.get_array_memory_size(),
+        }
+    }
+}
+
+/// Per-worker encoding stage of a sink. `encode` runs inside the rayon workers, so geometry
+/// and value encoding scale with `--threads`; the sink itself only appends the result.
+pub trait FeatureEncoder: Send + Sync {
+    fn encode(&self, rows: Vec<FeatureRow>) -> Result<EncodedBatch>;
+}
+
 pub trait DataSink: Send {
     fn add_feature(&mut self, row: FeatureRow) -> Result<()>;
+
+    /// Add all features of one block. Columnar sinks override this to build their arrays
+    /// from the whole batch instead of row by row.
+    fn add_features(&mut self, rows: Vec<FeatureRow>) -> Result<()> {
+        for row in rows {
+            self.add_feature(row)?;
+        }
+        Ok(())
+    }
+
+    /// Encoder for the worker stage. Without one, workers hand over plain rows.
+    fn encoder(&self) -> Option<Arc<dyn FeatureEncoder>> {
+        None
+    }
+
+    /// Append a batch produced by this sink's encoder.
+    fn add_encoded(&mut self, batch: EncodedBatch) -> Result<()> {
+        match batch {
+            EncodedBatch::Rows(rows) => self.add_features(rows),
+            _ => Err(anyhow!("Sink: Unsupported encoded batch for this sink")),
+        }
+    }
+
+    /// Values per column that could not be converted to the column type and were written
+    /// as null. Sinks that write values as they come report none.
+    fn coercion_failures(&self) -> BTreeMap<String, u64> {
+        BTreeMap::new()
+    }
+
     fn finish(&mut self) -> Result<()>;
 }
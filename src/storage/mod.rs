@@ -4,6 +4,9 @@ use std::collections::HashMap;
 use std::fs::OpenOptions;
 use std::io::{BufWriter, Write};
 use std::path::Path;
+use std::ptr::NonNull;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, PoisonError};
 use tempfile::NamedTempFile;
 
 // 8 bytes per node: 4 bytes lon (i32), 4 bytes lat (i32)
@@ -11,26 +14,37 @@ use tempfile::NamedTempFile;
 const NODE_SIZE: usize = 8;
 // 16 bytes per entry: 8 bytes node id (u64), 8 bytes packed coords (i64)
 const SPARSE_ENTRY_SIZE: usize = 16;
+// Nodes per compressed page. Each page is decoded linearly on lookup, so this
+// trades page index size against per-lookup decode work.
+const COMPRESSED_PAGE_NODES: usize = 256;
+// Shards of the in-memory store. Enough that pass 1 workers rarely contend on a lock.
+const MEMORY_SHARD_BITS: u32 = 6;
 const SCALE_FACTOR: f64 = 10_000_000.0;
 
 pub struct NodeStoreWriter {
     inner: NodeStoreWriterImpl,
+    /// Negative IDs (JOSM edits, osmium test data) are rare and sort differently from
+    /// positive ones, so every mode keeps them in a separate in-memory store.
+    negative: MemoryNodeStoreWriter,
 }
 
 pub struct NodeStoreReader {
     inner: NodeStoreReaderImpl,
+    negative: MemoryNodeStoreReader,
 }
 
 enum NodeStoreWriterImpl {
     Sparse(SparseNodeStoreWriter),
     Dense(DenseNodeStoreWriter),
-    Memory(MemoryNodeStore),
+    Compressed(CompressedNodeStoreWriter),
+    Memory(MemoryNodeStoreWriter),
 }
 
 enum NodeStoreReaderImpl {
     Sparse(SparseNodeStoreReader),
     Dense(DenseNodeStoreReader),
-    Memory(MemoryNodeStore),
+    Compressed(CompressedNodeStoreReader),
+    Memory(MemoryNodeStoreReader),
 }
 
 struct SparseNodeStoreWriter {
@@ -49,6 +63,8 @@ struct SparseNodeStoreReader {
 
 struct DenseNodeStoreWriter {
     mmap: MmapMut,
+    /// Start of `mmap`, viewed as one atomic 8-byte slot per node ID
+    slots: NonNull<AtomicU64>,
     max_nodes: u64,
     /// If Some, file is automatically deleted when this struct is dropped
     _temp_file: Option<NamedTempFile>,
@@ -61,9 +77,49 @@ struct DenseNodeStoreReader {
     _temp_file: Option<NamedTempFile>,
 }
 
-#[derive(Clone)]
-struct MemoryNodeStore {
-    nodes: HashMap<u64, (i32, i32)>,
+struct CompressedNodeStoreWriter {
+    writer: BufWriter<NamedTempFile>,
+    pages: Vec<CompressedPage>,
+    /// Entries written to the current (last) page
+    page_len: usize,
+    /// Bytes written so far, i.e. the offset of the next entry
+    offset: u64,
+    /// Previous entry of the current page as (id, lon, lat); deltas are taken against it
+    prev: (u64, i64, i64),
+    last_id: Option<u64>,
+    count: u64,
+    scratch: Vec<u8>,
+}
+
+struct CompressedNodeStoreReader {
+    mmap: Mmap,
+    pages: Vec<CompressedPage>,
+    /// If Some, file is automatically deleted when this struct is dropped
+    _temp_file: Option<NamedTempFile>,
+}
+
+/// Page index entry: first node ID of the page and its byte offset in the cache file.
+#[derive(Clone, Copy)]
+struct CompressedPage {
+    first_id: u64,
+    offset: u64,
+}
+
+// SAFETY: `slots` points into `mmap`, which the writer owns and never remaps, and every
+// access through it is atomic, so the writer can be shared between pass 1 workers.
+unsafe impl Send for DenseNodeStoreWriter {}
+unsafe impl Sync for DenseNodeStoreWriter {}
+
+/// In-memory store split into shards by ID hash, each behind its own lock, so pass 1
+/// workers can insert concurrently.
+struct MemoryNodeStoreWriter {
+    shards: Vec<Mutex<HashMap<i64, (i32, i32)>>>,
+}
+
+/// Read-only shards of a finalized `MemoryNodeStoreWriter`.
+#[derive(Default)]
+struct MemoryNodeStoreReader {
+    shards: Vec<HashMap<i64, (i32, i32)>>,
 }
 
 impl NodeStoreWriter {
@@ -78,6 +134,7 @@ impl NodeStoreWriter {
                 last_id: None,
                 is_sorted: true,
             }),
+            negative: MemoryNodeStoreWriter::new(),
         })
     }
 
@@ -105,18 +162,15 @@ impl NodeStoreWriter {
         // Map the file
         // SAFETY: The file handle is exclusively owned by this struct.
         // The mmap remains valid as long as the file exists (guaranteed by NamedTempFile).
-        // No other process accesses this file. The mmap is only accessed in single-threaded
-        // context during write phase, then converted to read-only before sharing.
+        // No other process accesses this file. During the write phase the mmap is only
+        // written through atomic slots, then converted to read-only before sharing.
         let mmap = unsafe {
             MmapMut::map_mut(&file).context("NodeStore: Failed to map dense cache file")?
         };
 
         Ok(Self {
-            inner: NodeStoreWriterImpl::Dense(DenseNodeStoreWriter {
-                mmap,
-                max_nodes,
-                _temp_file: None,
-            }),
+            inner: NodeStoreWriterImpl::Dense(DenseNodeStoreWriter::new(mmap, max_nodes, None)),
+            negative: MemoryNodeStoreWriter::new(),
         })
     }
 
@@ -139,55 +193,239 @@ impl NodeStoreWriter {
         // Map the file
         // SAFETY: The file handle is exclusively owned by this struct.
         // The mmap remains valid as long as the file exists (guaranteed by NamedTempFile).
-        // No other process accesses this file. The mmap is only accessed in single-threaded
-        // context during write phase, then converted to read-only before sharing.
+        // No other process accesses this file. During the write phase the mmap is only
+        // written through atomic slots, then converted to read-only before sharing.
         let mmap = unsafe {
             MmapMut::map_mut(temp_file.as_file())
                 .context("NodeStore: Failed to map temporary dense cache file")?
         };
 
         Ok(Self {
-            inner: NodeStoreWriterImpl::Dense(DenseNodeStoreWriter {
+            inner: NodeStoreWriterImpl::Dense(DenseNodeStoreWriter::new(
                 mmap,
                 max_nodes,
-                _temp_file: Some(temp_file),
+                Some(temp_file),
+            )),
+            negative: MemoryNodeStoreWriter::new(),
+        })
+    }
+
+    /// Create a block-compressed node store (delta-encoded pages, efficient for large sorted extracts).
+    pub fn new_compressed() -> Result<Self> {
+        let temp_file = NamedTempFile::new()
+            .context("NodeStore: Failed to create temporary compressed cache file")?;
+        Ok(Self {
+            inner: NodeStoreWriterImpl::Compressed(CompressedNodeStoreWriter {
+                writer: BufWriter::new(temp_file),
+                pages: Vec::new(),
+                page_len: 0,
+                offset: 0,
+                prev: (0, 0, 0),
+                last_id: None,
+                count: 0,
+                scratch: Vec::with_capacity(32),
             }),
+            negative: MemoryNodeStoreWriter::new(),
         })
     }
 
     pub fn new_memory() -> Self {
         Self {
-            inner: NodeStoreWriterImpl::Memory(MemoryNodeStore {
-                nodes: HashMap::new(),
-            }),
+            inner: NodeStoreWriterImpl::Memory(MemoryNodeStoreWriter::new()),
+            negative: MemoryNodeStoreWriter::new(),
         }
     }
 
-    pub fn put(&mut self, id: u64, lat: f64, lon: f64) -> Result<()> {
+    /// Whether `put_shared` can be used, i.e. pass 1 workers may write concurrently instead of
+    /// funnelling nodes through one writer thread. Dense slots are independent per ID and the
+    /// memory store is sharded; sparse and compressed stores need IDs in order.
+    pub fn supports_shared_put(&self) -> bool {
+        matches!(
+            self.inner,
+            NodeStoreWriterImpl::Dense(_) | NodeStoreWriterImpl::Memory(_)
+        )
+    }
+
+    /// Insert a node through a shared reference. Only valid when `supports_shared_put` is true.
+    pub fn put_shared(&self, id: i64, lat: f64, lon: f64) -> Result<()> {
+        match &self.inner {
+            NodeStoreWriterImpl::Memory(store) => store.put_shared(id, lat, lon),
+            _ if id < 0 => self.negative.put_shared(id, lat, lon),
+            NodeStoreWriterImpl::Dense(store) => store.put(id as u64, lat, lon),
+            NodeStoreWriterImpl::Sparse(_) | NodeStoreWriterImpl::Compressed(_) => Err(anyhow!(
+                "NodeStore: Concurrent writes are not supported by sorted node caches"
+            )),
+        }
+    }
+
+    pub fn put(&mut self, id: i64, lat: f64, lon: f64) -> Result<()> {
         match &mut self.inner {
-            NodeStoreWriterImpl::Sparse(store) => store.put(id, lat, lon),
-            NodeStoreWriterImpl::Dense(store) => store.put(id, lat, lon),
             NodeStoreWriterImpl::Memory(store) => store.put(id, lat, lon),
+            _ if id < 0 => self.negative.put(id, lat, lon),
+            NodeStoreWriterImpl::Sparse(store) => store.put(id as u64, lat, lon),
+            NodeStoreWriterImpl::Dense(store) => store.put(id as u64, lat, lon),
+            NodeStoreWriterImpl::Compressed(store) => store.put(id as u64, lat, lon),
         }
     }
 
     pub fn finalize(self) -> Result<NodeStoreReader> {
-        match self.inner {
-            NodeStoreWriterImpl::Sparse(store) => store.finalize(),
-            NodeStoreWriterImpl::Dense(store) => store.finalize(),
-            NodeStoreWriterImpl::Memory(store) => Ok(NodeStoreReader {
-                inner: NodeStoreReaderImpl::Memory(store),
-            }),
+        let negative = self.negative.finalize();
+        if !negative.is_empty() {
+            tracing::info!(
+                "NodeStore: {} nodes with negative IDs kept in memory",
+                negative.len()
+            );
         }
+        let mut reader = match self.inner {
+            NodeStoreWriterImpl::Sparse(store) => store.finalize()?,
+            NodeStoreWriterImpl::Dense(store) => store.finalize()?,
+            NodeStoreWriterImpl::Compressed(store) => store.finalize()?,
+            NodeStoreWriterImpl::Memory(store) => NodeStoreReader {
+                inner: NodeStoreReaderImpl::Memory(store.finalize()),
+                negative: MemoryNodeStoreReader::default(),
+            },
+        };
+        reader.negative = negative;
+        Ok(reader)
     }
 }
 
 impl NodeStoreReader {
-    pub fn get(&self, id: u64) -> Option<(f64, f64)> {
+    pub fn get(&self, id: i64) -> Option<(f64, f64)> {
         match &self.inner {
-            NodeStoreReaderImpl::Sparse(store) => store.get(id),
-            NodeStoreReaderImpl::Dense(store) => store.get(id),
             NodeStoreReaderImpl::Memory(store) => store.get(id),
+            _ if id < 0 => self.negative.get(id),
+            NodeStoreReaderImpl::Sparse(store) => store.get(id as u64),
+            NodeStoreReaderImpl::Dense(store) => store.get(id as u64),
+            NodeStoreReaderImpl::Compressed(store) => store.get(id as u64),
+        }
+    }
+}
+
+/// Node coordinate store used to resolve way geometries.
+///
+/// Pass 1 writes every node of the input through `put_batch`, then `finalize` is called once,
+/// then pass 2 workers look up coordinates concurrently. Implement this to plug in a custom
+/// backend and hand it to `app::process_pbf_with_node_store`; the built-in cache modes are
+/// provided by `BuiltinNodeStore`.
+///
+/// Coordinates are returned as `(lon, lat)`.
+pub trait NodeStore: Send + Sync {
+    /// Whether `put`/`put_batch` may be called from several pass 1 workers at once. If false,
+    /// nodes are written from a single thread in file order (ascending IDs for sorted input).
+    fn concurrent_writes(&self) -> bool {
+        false
+    }
+
+    /// Whether pass 1 must run. Return false for stores that are already populated (e.g. a
+    /// shared read-only cache); `put` is then never called.
+    fn requires_indexing(&self) -> bool {
+        true
+    }
+
+    fn put(&self, id: i64, lat: f64, lon: f64) -> Result<()>;
+
+    /// Write the nodes of one decoded block as `(id, lat, lon)`.
+    fn put_batch(&self, nodes: &[(i64, f64, f64)]) -> Result<()> {
+        for &(id, lat, lon) in nodes {
+            self.put(id, lat, lon)
+                .with_context(|| format!("NodeStore: Failed writing node {}", id))?;
+        }
+        Ok(())
+    }
+
+    /// Called once after pass 1, before the first `get`.
+    fn finalize(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn get(&self, id: i64) -> Option<(f64, f64)>;
+
+    /// Look up all node refs of a way, keeping their order. Missing nodes are `None`.
+    fn get_many(&self, ids: &[i64]) -> Vec<Option<(f64, f64)>> {
+        ids.iter().map(|&id| self.get(id)).collect()
+    }
+}
+
+/// `NodeStore` over the built-in cache modes: a `NodeStoreWriter` until `finalize`, then the
+/// resulting `NodeStoreReader`.
+pub struct BuiltinNodeStore {
+    state: BuiltinState,
+}
+
+enum BuiltinState {
+    /// Dense and memory stores, written concurrently through `put_shared`
+    Shared(NodeStoreWriter),
+    /// Sorted stores, written in order by a single thread
+    Sequential(Mutex<NodeStoreWriter>),
+    Ready(NodeStoreReader),
+    /// Only observable if `finalize` failed
+    Failed,
+}
+
+impl BuiltinNodeStore {
+    pub fn new(writer: NodeStoreWriter) -> Self {
+        let state = if writer.supports_shared_put() {
+            BuiltinState::Shared(writer)
+        } else {
+            BuiltinState::Sequential(Mutex::new(writer))
+        };
+        Self { state }
+    }
+}
+
+impl NodeStore for BuiltinNodeStore {
+    fn concurrent_writes(&self) -> bool {
+        matches!(self.state, BuiltinState::Shared(_))
+    }
+
+    fn put(&self, id: i64, lat: f64, lon: f64) -> Result<()> {
+        self.put_batch(&[(id, lat, lon)])
+    }
+
+    fn put_batch(&self, nodes: &[(i64, f64, f64)]) -> Result<()> {
+        match &self.state {
+            BuiltinState::Shared(writer) => {
+                for &(id, lat, lon) in nodes {
+                    writer
+                        .put_shared(id, lat, lon)
+                        .with_context(|| format!("NodeStore: Failed writing node {}", id))?;
+                }
+                Ok(())
+            }
+            BuiltinState::Sequential(writer) => {
+                let mut writer = writer.lock().unwrap_or_else(PoisonError::into_inner);
+                for &(id, lat, lon) in nodes {
+                    writer
+                        .put(id, lat, lon)
+                        .with_context(|| format!("NodeStore: Failed writing node {}", id))?;
+                }
+                Ok(())
+            }
+            BuiltinState::Ready(_) | BuiltinState::Failed => {
+                Err(anyhow!("NodeStore: Cannot write nodes after finalize"))
+            }
+        }
+    }
+
+    fn finalize(&mut self) -> Result<()> {
+        let writer = match std::mem::replace(&mut self.state, BuiltinState::Failed) {
+            BuiltinState::Shared(writer) => writer,
+            BuiltinState::Sequential(writer) => {
+                writer.into_inner().unwrap_or_else(PoisonError::into_inner)
+            }
+            BuiltinState::Ready(_) | BuiltinState::Failed => {
+                return Err(anyhow!("NodeStore: Node store finalized twice"));
+            }
+        };
+        self.state = BuiltinState::Ready(writer.finalize()?);
+        Ok(())
+    }
+
+    fn get(&self, id: i64) -> Option<(f64, f64)> {
+        match &self.state {
+            BuiltinState::Ready(reader) => reader.get(id),
+            _ => None,
         }
     }
 }
@@ -303,6 +541,7 @@ impl SparseNodeStoreWriter {
                 count: self.count,
                 _temp_file: Some(temp_file),
             }),
+            negative: MemoryNodeStoreReader::default(),
         })
     }
 }
@@ -331,7 +570,18 @@ impl SparseNodeStoreReader {
 }
 
 impl DenseNodeStoreWriter {
-    fn put(&mut self, id: u64, lat: f64, lon: f64) -> Result<()> {
+    fn new(mut mmap: MmapMut, max_nodes: u64, temp_file: Option<NamedTempFile>) -> Self {
+        let slots = NonNull::from(&mut mmap[..]).cast::<AtomicU64>();
+        Self {
+            mmap,
+            slots,
+            max_nodes,
+            _temp_file: temp_file,
+        }
+    }
+
+    /// Each node ID owns its own slot, so concurrent puts never touch the same bytes.
+    fn put(&self, id: u64, lat: f64, lon: f64) -> Result<()> {
         if id >= self.max_nodes {
             return Err(anyhow!(
                 "NodeStore: Node ID {id} exceeds node_cache_max_nodes ({}); increase --node-cache-max-nodes or use --node-cache-mode memory",
@@ -339,15 +589,20 @@ impl DenseNodeStoreWriter {
             ));
         }
 
-        let offset = (id as usize) * NODE_SIZE;
-
         // Convert to fixed precision i32
         let lat_fixed = (lat * SCALE_FACTOR) as i32;
         let lon_fixed = (lon * SCALE_FACTOR) as i32;
 
-        let data = &mut self.mmap[offset..offset + NODE_SIZE];
+        // Same little-endian layout as before, stored as one native-endian word
+        let mut data = [0u8; NODE_SIZE];
         data[0..4].copy_from_slice(&lon_fixed.to_le_bytes());
         data[4..8].copy_from_slice(&lat_fixed.to_le_bytes());
+
+        // SAFETY: id < max_nodes and the mapping is max_nodes * NODE_SIZE bytes, so the slot is
+        // in bounds. The mapping is page-aligned, so every slot is aligned for AtomicU64.
+        let slot = unsafe { &*self.slots.as_ptr().add(id as usize) };
+        // Relaxed suffices: readers only see the cache after pass 1 workers have been joined.
+        slot.store(u64::from_ne_bytes(data), Ordering::Relaxed);
         Ok(())
     }
 
@@ -362,6 +617,7 @@ impl DenseNodeStoreWriter {
                 max_nodes: self.max_nodes,
                 _temp_file: self._temp_file, // Pass ownership for cleanup on drop
             }),
+            negative: MemoryNodeStoreReader::default(),
         })
     }
 }
@@ -379,16 +635,221 @@ impl DenseNodeStoreReader {
     }
 }
 
-impl MemoryNodeStore {
+/// Compressed pages hold up to `COMPRESSED_PAGE_NODES` entries. Within a page every entry is
+/// stored as three varints: the ID delta to the previous entry and the zigzag-encoded lon/lat
+/// deltas (fixed precision). The first entry of a page is a delta against zero, so pages decode
+/// independently and only the page index (first ID + offset per page) is kept in memory.
+impl CompressedNodeStoreWriter {
     fn put(&mut self, id: u64, lat: f64, lon: f64) -> Result<()> {
+        if let Some(last_id) = self.last_id
+            && id < last_id
+        {
+            return Err(anyhow!(
+                "NodeStore: Node IDs are out of order for compressed cache; run `osmium sort` to sort by type then id"
+            ));
+        }
+
+        if self.pages.is_empty() || self.page_len == COMPRESSED_PAGE_NODES {
+            self.pages.push(CompressedPage {
+                first_id: id,
+                offset: self.offset,
+            });
+            self.page_len = 0;
+            self.prev = (0, 0, 0);
+        }
+
+        let lat_fixed = i64::from((lat * SCALE_FACTOR) as i32);
+        let lon_fixed = i64::from((lon * SCALE_FACTOR) as i32);
+        let (prev_id, prev_lon, prev_lat) = self.prev;
+
+        self.scratch.clear();
+        write_varint(&mut self.scratch, id - prev_id);
+        write_varint(&mut self.scratch, zigzag_encode(lon_fixed - prev_lon));
+        write_varint(&mut self.scratch, zigzag_encode(lat_fixed - prev_lat));
+        self.writer.write_all(&self.scratch)?;
+
+        self.offset += self.scratch.len() as u64;
+        self.prev = (id, lon_fixed, lat_fixed);
+        self.page_len += 1;
+        self.last_id = Some(id);
+        self.count = self.count.saturating_add(1);
+        Ok(())
+    }
+
+    fn finalize(mut self) -> Result<NodeStoreReader> {
+        self.writer
+            .flush()
+            .context("NodeStore: Failed to flush compressed cache file")?;
+        let temp_file = self
+            .writer
+            .into_inner()
+            .context("NodeStore: Failed to finalize compressed cache file")?;
+
+        let file_len = temp_file
+            .as_file()
+            .metadata()
+            .context("NodeStore: Failed to stat compressed cache file")?
+            .len();
+        if file_len != self.offset {
+            return Err(anyhow!(
+                "NodeStore: Compressed cache size mismatch: expected {} bytes, file has {}",
+                self.offset,
+                file_len
+            ));
+        }
+
+        tracing::debug!(
+            "NodeStore: Compressed {} nodes into {} bytes ({} pages)",
+            self.count,
+            file_len,
+            self.pages.len()
+        );
+
+        Ok(NodeStoreReader {
+            inner: NodeStoreReaderImpl::Compressed(CompressedNodeStoreReader {
+                // SAFETY: The file handle is exclusively owned by this struct.
+                // The mmap remains valid as long as the file exists (guaranteed by NamedTempFile).
+                // No other process accesses this file.
+                mmap: unsafe {
+                    Mmap::map(temp_file.as_file())
+                        .context("NodeStore: Failed to map compressed cache file")?
+                },
+                pages: self.pages,
+                _temp_file: Some(temp_file),
+            }),
+            negative: MemoryNodeStoreReader::default(),
+        })
+    }
+}
+
+impl CompressedNodeStoreReader {
+    fn get(&self, id: u64) -> Option<(f64, f64)> {
+        // Last page whose first ID is <= id
+        let page = self
+            .pages
+            .partition_point(|page| page.first_id <= id)
+            .checked_sub(1)?;
+        let start = self.pages[page].offset as usize;
+        let end = self
+            .pages
+            .get(page + 1)
+            .map_or(self.mmap.len(), |next| next.offset as usize);
+        let data = &self.mmap[start..end];
+
+        let mut pos = 0;
+        let (mut cur_id, mut lon_fixed, mut lat_fixed) = (0u64, 0i64, 0i64);
+        while pos < data.len() {
+            cur_id += read_varint(data, &mut pos)?;
+            lon_fixed += zigzag_decode(read_varint(data, &mut pos)?);
+            lat_fixed += zigzag_decode(read_varint(data, &mut pos)?);
+
+            match cur_id.cmp(&id) {
+                std::cmp::Ordering::Less => {}
+                std::cmp::Ordering::Greater => return None,
+                std::cmp::Ordering::Equal => {
+                    return Some((
+                        lon_fixed as f64 / SCALE_FACTOR,
+                        lat_fixed as f64 / SCALE_FACTOR,
+                    ));
+                }
+            }
+        }
+
+        None
+    }
+}
+
+/// LEB128-style unsigned varint
+fn write_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn read_varint(data: &[u8], pos: &mut usize) -> Option<u64> {
+    let mut value = 0u64;
+    let mut shift = 0;
+    loop {
+        let byte = *data.get(*pos)?;
+        *pos += 1;
+        value |= u64::from(byte & 0x7F) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+        shift += 7;
+        if shift >= 64 {
+            return None;
+        }
+    }
+}
+
+fn zigzag_encode(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+fn zigzag_decode(value: u64) -> i64 {
+    ((value >> 1) as i64) ^ -((value & 1) as i64)
+}
+
+/// Fibonacci hash of the ID, so consecutive IDs spread across shards.
+fn memory_shard(id: i64) -> usize {
+    ((id as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15) >> (64 - MEMORY_SHARD_BITS)) as usize
+}
+
+impl MemoryNodeStoreWriter {
+    fn new() -> Self {
+        Self {
+            shards: (0..1 << MEMORY_SHARD_BITS)
+                .map(|_| Mutex::new(HashMap::new()))
+                .collect(),
+        }
+    }
+
+    fn put(&mut self, id: i64, lat: f64, lon: f64) -> Result<()> {
         let lat_fixed = (lat * SCALE_FACTOR) as i32;
         let lon_fixed = (lon * SCALE_FACTOR) as i32;
-        self.nodes.insert(id, (lon_fixed, lat_fixed));
+        self.shards[memory_shard(id)]
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(id, (lon_fixed, lat_fixed));
         Ok(())
     }
 
-    fn get(&self, id: u64) -> Option<(f64, f64)> {
-        let (lon_fixed, lat_fixed) = self.nodes.get(&id)?;
+    fn put_shared(&self, id: i64, lat: f64, lon: f64) -> Result<()> {
+        let lat_fixed = (lat * SCALE_FACTOR) as i32;
+        let lon_fixed = (lon * SCALE_FACTOR) as i32;
+        self.shards[memory_shard(id)]
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(id, (lon_fixed, lat_fixed));
+        Ok(())
+    }
+
+    fn finalize(self) -> MemoryNodeStoreReader {
+        MemoryNodeStoreReader {
+            shards: self
+                .shards
+                .into_iter()
+                .map(|shard| shard.into_inner().unwrap_or_else(PoisonError::into_inner))
+                .collect(),
+        }
+    }
+}
+
+impl MemoryNodeStoreReader {
+    fn len(&self) -> usize {
+        self.shards.iter().map(HashMap::len).sum()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.shards.iter().all(HashMap::is_empty)
+    }
+
+    fn get(&self, id: i64) -> Option<(f64, f64)> {
+        let shard = self.shards.get(memory_shard(id))?;
+        let (lon_fixed, lat_fixed) = shard.get(&id)?;
         Some((
             *lon_fixed as f64 / SCALE_FACTOR,
             *lat_fixed as f64 / SCALE_FACTOR,
@@ -539,7 +1000,7 @@ mod tests {
     #[test]
     fn memory_store_handles_large_node_ids() {
         let mut writer = NodeStoreWriter::new_memory();
-        let large_id = u64::MAX - 1;
+        let large_id = i64::MAX - 1;
         writer.put(large_id, 51.5, -0.1).unwrap();
 
         let reader = writer.finalize().unwrap();
@@ -590,7 +1051,7 @@ mod tests {
     #[test]
     fn sparse_store_handles_large_node_ids() {
         let mut writer = NodeStoreWriter::new_sparse().unwrap();
-        let large_id = 13_000_000_000u64; // Typical max OSM node ID
+        let large_id = 13_000_000_000i64; // Typical max OSM node ID
         writer.put(large_id, 51.5, -0.1).unwrap();
 
         let reader = writer.finalize().unwrap();
@@ -672,6 +1133,315 @@ mod tests {
         assert_eq!(lat, 0.0);
     }
 
+    // ============================================
+    // Compressed node store tests
+    // ============================================
+
+    #[test]
+    fn compressed_store_put_and_get() {
+        let mut writer = NodeStoreWriter::new_compressed().unwrap();
+        writer.put(1, 51.5, -0.1).unwrap();
+        writer.put(2, 40.7, -74.0).unwrap();
+
+        let reader = writer.finalize().unwrap();
+
+        let (lon, lat) = reader.get(1).unwrap();
+        assert!((lat - 51.5).abs() < 1e-7);
+        assert!((lon - (-0.1)).abs() < 1e-7);
+
+        let (lon, lat) = reader.get(2).unwrap();
+        assert!((lat - 40.7).abs() < 1e-7);
+        assert!((lon - (-74.0)).abs() < 1e-7);
+    }
+
+    #[test]
+    fn compressed_store_returns_none_for_missing_node() {
+        let mut writer = NodeStoreWriter::new_compressed().unwrap();
+        writer.put(10, 51.5, -0.1).unwrap();
+        writer.put(20, 40.7, -74.0).unwrap();
+        let reader = writer.finalize().unwrap();
+
+        assert!(reader.get(5).is_none());
+        assert!(reader.get(15).is_none());
+        assert!(reader.get(999).is_none());
+    }
+
+    #[test]
+    fn compressed_store_empty_returns_none() {
+        let writer = NodeStoreWriter::new_compressed().unwrap();
+        let reader = writer.finalize().unwrap();
+
+        assert!(reader.get(1).is_none());
+    }
+
+    #[test]
+    fn compressed_store_rejects_unsorted_input() {
+        let mut writer = NodeStoreWriter::new_compressed().unwrap();
+        writer.put(5, 51.5, -0.1).unwrap();
+        let err = writer.put(1, 40.7, -74.0).unwrap_err();
+        assert!(err.to_string().contains("osmium sort"));
+    }
+
+    #[test]
+    fn compressed_store_spans_multiple_pages() {
+        let count = COMPRESSED_PAGE_NODES as i64 * 3 + 17;
+        let mut writer = NodeStoreWriter::new_compressed().unwrap();
+        for i in 0..count {
+            // Gaps in IDs and coordinates that move in both directions
+            let id = 1_000 + i * 3;
+            let lat = 45.0 + (i as f64 * 0.0001).sin();
+            let lon = -120.0 + (i as f64 * 0.0003).cos();
+            writer.put(id, lat, lon).unwrap();
+        }
+        let reader = writer.finalize().unwrap();
+
+        for i in 0..count {
+            let id = 1_000 + i * 3;
+            let (lon, lat) = reader.get(id).unwrap();
+            assert!((lat - (45.0 + (i as f64 * 0.0001).sin())).abs() < 1e-7);
+            assert!((lon - (-120.0 + (i as f64 * 0.0003).cos())).abs() < 1e-7);
+            assert!(reader.get(id + 1).is_none());
+        }
+    }
+
+    #[test]
+    fn compressed_store_handles_large_node_ids() {
+        let mut writer = NodeStoreWriter::new_compressed().unwrap();
+        let large_id = 13_000_000_000i64;
+        writer.put(1, 0.0, 0.0).unwrap();
+        writer.put(large_id, 51.5, -0.1).unwrap();
+
+        let reader = writer.finalize().unwrap();
+
+        let (lon, lat) = reader.get(large_id).unwrap();
+        assert!((lat - 51.5).abs() < 1e-7);
+        assert!((lon - (-0.1)).abs() < 1e-7);
+    }
+
+    #[test]
+    fn compressed_store_is_smaller_than_sparse_for_sequential_ids() {
+        let mut writer = NodeStoreWriter::new_compressed().unwrap();
+        for id in 1..=10_000i64 {
+            writer
+                .put(id, 51.5 + id as f64 * 1e-6, -0.1 - id as f64 * 1e-6)
+                .unwrap();
+        }
+        let reader = writer.finalize().unwrap();
+
+        let NodeStoreReaderImpl::Compressed(store) = &reader.inner else {
+            panic!("expected compressed reader");
+        };
+        assert!(store.mmap.len() * 3 < 10_000 * SPARSE_ENTRY_SIZE);
+    }
+
+    #[test]
+    fn varint_roundtrip() {
+        let values = [0u64, 1, 127, 128, 300, 16_384, u32::MAX as u64, u64::MAX];
+        let mut buf = Vec::new();
+        for value in values {
+            write_varint(&mut buf, value);
+        }
+
+        let mut pos = 0;
+        for value in values {
+            assert_eq!(read_varint(&buf, &mut pos), Some(value));
+        }
+        assert_eq!(pos, buf.len());
+        assert_eq!(read_varint(&buf, &mut pos), None);
+    }
+
+    #[test]
+    fn zigzag_roundtrip() {
+        for value in [
+            0i64,
+            1,
+            -1,
+            63,
+            -64,
+            1_800_000_000,
+            -3_600_000_000,
+            i64::MIN,
+            i64::MAX,
+        ] {
+            assert_eq!(zigzag_decode(zigzag_encode(value)), value);
+        }
+        assert_eq!(zigzag_encode(-1), 1);
+        assert_eq!(zigzag_encode(1), 2);
+    }
+
+    // ============================================
+    // Negative node ID tests
+    // ============================================
+
+    fn all_writers() -> Vec<(&'static str, NodeStoreWriter)> {
+        vec![
+            ("memory", NodeStoreWriter::new_memory()),
+            ("sparse", NodeStoreWriter::new_sparse().unwrap()),
+            ("dense", NodeStoreWriter::new_dense_temp(1000).unwrap()),
+            ("compressed", NodeStoreWriter::new_compressed().unwrap()),
+        ]
+    }
+
+    #[test]
+    fn all_stores_handle_negative_ids() {
+        for (mode, mut writer) in all_writers() {
+            // osmium orders negative IDs first, by absolute value
+            writer.put(-1, 10.0, 20.0).unwrap();
+            writer.put(-2, 11.0, 21.0).unwrap();
+            writer.put(-3_000_000_000, 12.0, 22.0).unwrap();
+            writer.put(1, 51.5, -0.1).unwrap();
+            writer.put(2, 40.7, -74.0).unwrap();
+            let reader = writer.finalize().unwrap();
+
+            for (id, lat, lon) in [
+                (-1, 10.0, 20.0),
+                (-2, 11.0, 21.0),
+                (-3_000_000_000, 12.0, 22.0),
+                (1, 51.5, -0.1),
+                (2, 40.7, -74.0),
+            ] {
+                let (got_lon, got_lat) = reader
+                    .get(id)
+                    .unwrap_or_else(|| panic!("{mode}: missing node {id}"));
+                assert!(
+                    (got_lat - lat).abs() < 1e-7,
+                    "{mode}: lat mismatch for {id}"
+                );
+                assert!(
+                    (got_lon - lon).abs() < 1e-7,
+                    "{mode}: lon mismatch for {id}"
+                );
+            }
+            assert!(reader.get(-4).is_none(), "{mode}: unexpected node -4");
+        }
+    }
+
+    #[test]
+    fn negative_ids_do_not_affect_sort_order_checks() {
+        let mut writer = NodeStoreWriter::new_sparse().unwrap();
+        writer.put(5, 51.5, -0.1).unwrap();
+        // Negative IDs after positive ones must not be reported as out of order
+        writer.put(-1, 40.7, -74.0).unwrap();
+        writer.put(6, 40.7, -74.0).unwrap();
+        let reader = writer.finalize().unwrap();
+        assert!(reader.get(-1).is_some());
+        assert!(reader.get(6).is_some());
+    }
+
+    #[test]
+    fn negative_id_does_not_wrap_into_dense_limit() {
+        let mut writer = NodeStoreWriter::new_dense_temp(100).unwrap();
+        assert!(writer.put(-1, 51.5, -0.1).is_ok());
+        let reader = writer.finalize().unwrap();
+        assert!(reader.get(-1).is_some());
+        // Slot 99 must be untouched by the negative ID
+        assert_eq!(reader.get(99), Some((0.0, 0.0)));
+    }
+
+    // ============================================
+    // Concurrent write tests
+    // ============================================
+
+    #[test]
+    fn shared_put_support_by_mode() {
+        for (mode, writer) in all_writers() {
+            let expected = matches!(mode, "memory" | "dense");
+            assert_eq!(writer.supports_shared_put(), expected, "{mode}");
+            assert_eq!(writer.put_shared(1, 51.5, -0.1).is_ok(), expected, "{mode}");
+        }
+    }
+
+    #[test]
+    fn concurrent_puts_are_all_visible_after_finalize() {
+        let writers = [
+            ("memory", NodeStoreWriter::new_memory()),
+            ("dense", NodeStoreWriter::new_dense_temp(4_000).unwrap()),
+        ];
+        for (mode, writer) in writers {
+            std::thread::scope(|scope| {
+                for thread in 0..4i64 {
+                    let writer = &writer;
+                    scope.spawn(move || {
+                        // Interleave IDs so neighbouring slots are written by different threads
+                        for id in (thread..4_000).step_by(4) {
+                            writer
+                                .put_shared(id, id as f64 * 1e-4, -(id as f64) * 1e-4)
+                                .unwrap();
+                        }
+                        writer.put_shared(-1 - thread, 1.0, 2.0).unwrap();
+                    });
+                }
+            });
+            let reader = writer.finalize().unwrap();
+
+            for id in 0..4_000i64 {
+                let (lon, lat) = reader
+                    .get(id)
+                    .unwrap_or_else(|| panic!("{mode}: missing node {id}"));
+                assert!(
+                    (lat - id as f64 * 1e-4).abs() < 1e-7,
+                    "{mode}: lat for {id}"
+                );
+                assert!(
+                    (lon + id as f64 * 1e-4).abs() < 1e-7,
+                    "{mode}: lon for {id}"
+                );
+            }
+            for id in -4..=-1 {
+                assert_eq!(reader.get(id), Some((2.0, 1.0)), "{mode}: node {id}");
+            }
+        }
+    }
+
+    // ============================================
+    // NodeStore trait tests
+    // ============================================
+
+    #[test]
+    fn builtin_store_roundtrip_through_trait() {
+        for (mode, writer) in all_writers() {
+            let mut store: Box<dyn NodeStore> = Box::new(BuiltinNodeStore::new(writer));
+            assert_eq!(
+                store.concurrent_writes(),
+                matches!(mode, "memory" | "dense"),
+                "{mode}"
+            );
+            store
+                .put_batch(&[(-1, 10.0, 20.0), (1, 51.5, -0.1), (3, 40.7, -74.0)])
+                .unwrap();
+            assert!(store.get(1).is_none(), "{mode}: readable before finalize");
+
+            store.finalize().unwrap();
+            let coords = store.get_many(&[3, 5_000, -1, 1]);
+            assert_eq!(coords.len(), 4, "{mode}");
+            assert!(coords[0].is_some() && coords[2].is_some() && coords[3].is_some());
+            assert!(coords[1].is_none(), "{mode}: unexpected node 5000");
+
+            assert!(
+                store.put(4, 0.0, 0.0).is_err(),
+                "{mode}: write after finalize"
+            );
+            assert!(store.finalize().is_err(), "{mode}: finalized twice");
+        }
+    }
+
+    #[test]
+    fn builtin_sequential_store_reports_out_of_order_ids() {
+        let store = BuiltinNodeStore::new(NodeStoreWriter::new_sparse().unwrap());
+        store.put(5, 51.5, -0.1).unwrap();
+        let err = store
+            .put_batch(&[(6, 0.0, 0.0), (4, 0.0, 0.0)])
+            .unwrap_err();
+        assert!(format!("{err:#}").contains("out of order"));
+    }
+
+    #[test]
+    fn dense_shared_put_rejects_id_beyond_limit() {
+        let writer = NodeStoreWriter::new_dense_temp(10).unwrap();
+        assert!(writer.put_shared(10, 51.5, -0.1).is_err());
+        assert!(writer.put_shared(9, 51.5, -0.1).is_ok());
+    }
+
     // ============================================
     // Pack/unpack coords tests
     // ============================================
@@ -714,7 +1484,7 @@ mod tests {
     #[test]
     fn all_stores_produce_same_results() {
         let test_coords = vec![
-            (1u64, 51.5073509, -0.1277583),
+            (1i64, 51.5073509, -0.1277583),
             (2, 40.7127753, -74.0059728),
             (3, 35.6761919, 139.6503106),
             (4, -33.8688197, 151.2092955),
@@ -741,11 +1511,19 @@ mod tests {
         }
         let dense_reader = dense_writer.finalize().unwrap();
 
+        // Compressed store
+        let mut compressed_writer = NodeStoreWriter::new_compressed().unwrap();
+        for (id, lat, lon) in &test_coords {
+            compressed_writer.put(*id, *lat, *lon).unwrap();
+        }
+        let compressed_reader = compressed_writer.finalize().unwrap();
+
         // Compare results
         for (id, _, _) in &test_coords {
             let mem_result = mem_reader.get(*id).unwrap();
             let sparse_result = sparse_reader.get(*id).unwrap();
             let dense_result = dense_reader.get(*id).unwrap();
+            let compressed_result = compressed_reader.get(*id).unwrap();
 
             assert!(
                 (mem_result.0 - sparse_result.0).abs() < 1e-10,
@@ -763,6 +1541,14 @@ mod tests {
                 (mem_result.1 - dense_result.1).abs() < 1e-10,
                 "lat mismatch between memory and dense for node {id}"
             );
+            assert!(
+                (mem_result.0 - compressed_result.0).abs() < 1e-10,
+                "lon mismatch between memory and compressed for node {id}"
+            );
+            assert!(
+                (mem_result.1 - compressed_result.1).abs() < 1e-10,
+                "lat mismatch between memory and compressed for node {id}"
+            );
         }
     }
 }
@@ -1,41 +1,247 @@
 use std::collections::HashMap;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
 use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-pub struct ProgressCounter {
+use crate::config::ProgressMode;
+
+/// Refresh interval of the status line and of JSON progress events.
+const TEXT_INTERVAL: Duration = Duration::from_millis(250);
+const JSON_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Progress of one pass over the input file, reported on stderr.
+///
+/// Progress is the reader's position in the input, so blobs skipped via the blob index count
+/// as done. Throughput and ETA are derived from it; the ETA assumes the rest of the file
+/// decodes at the rate seen so far.
+pub struct Progress {
+    pass: &'static str,
     label: &'static str,
-    interval: u64,
-    count: AtomicU64,
+    mode: ProgressMode,
+    total_bytes: u64,
+    position: Arc<AtomicU64>,
+    nodes: AtomicU64,
+    features: AtomicU64,
+    start: Instant,
+    last_report: Mutex<Instant>,
 }
 
-impl ProgressCounter {
-    pub fn new(label: &'static str, interval: u64) -> Self {
-        let counter = Self {
+impl Progress {
+    /// Start reporting a pass. `position` is updated by the pass's reader (see
+    /// [`PositionReader`]); `pass` identifies the pass in JSON events.
+    pub fn new(
+        pass: &'static str,
+        label: &'static str,
+        mode: ProgressMode,
+        total_bytes: u64,
+        position: Arc<AtomicU64>,
+    ) -> Self {
+        let start = Instant::now();
+        let progress = Self {
+            pass,
             label,
-            interval: interval.max(1),
-            count: AtomicU64::new(0),
+            mode,
+            total_bytes,
+            position,
+            nodes: AtomicU64::new(0),
+            features: AtomicU64::new(0),
+            start,
+            last_report: Mutex::new(start),
         };
-        counter.print(0);
-        counter
+        progress.report("start");
+        progress
+    }
+
+    pub fn add_nodes(&self, delta: u64) {
+        self.nodes.fetch_add(delta, Ordering::Relaxed);
+        self.tick();
+    }
+
+    pub fn add_features(&self, delta: u64) {
+        self.features.fetch_add(delta, Ordering::Relaxed);
+        self.tick();
     }
 
-    pub fn inc(&self, delta: u64) {
-        let prev = self.count.fetch_add(delta, Ordering::SeqCst);
-        let current = prev + delta;
-        // Print if we crossed an interval boundary
-        if prev / self.interval < current / self.interval {
-            self.print(current);
+    /// Report if the refresh interval has passed. Safe to call from every worker.
+    pub fn tick(&self) {
+        let interval = match self.mode {
+            ProgressMode::Text => TEXT_INTERVAL,
+            ProgressMode::Json => JSON_INTERVAL,
+        };
+        // Another thread holding the lock is reporting already
+        let Ok(mut last) = self.last_report.try_lock() else {
+            return;
+        };
+        if last.elapsed() < interval {
+            return;
         }
+        *last = Instant::now();
+        drop(last);
+        self.report("progress");
     }
 
     pub fn finish(&self) {
-        self.print(self.count.load(Ordering::SeqCst));
-        eprintln!();
+        self.report("finish");
+        if self.mode == ProgressMode::Text {
+            eprintln!();
+        }
+    }
+
+    fn snapshot(&self) -> ProgressSnapshot {
+        ProgressSnapshot {
+            bytes: self.position.load(Ordering::Relaxed).min(self.total_bytes),
+            total_bytes: self.total_bytes,
+            nodes: self.nodes.load(Ordering::Relaxed),
+            features: self.features.load(Ordering::Relaxed),
+            elapsed: self.start.elapsed(),
+        }
     }
 
-    fn print(&self, current: u64) {
+    fn report(&self, event: &str) {
         use std::io::Write;
-        eprint!("\r{}: {}", self.label, current);
-        let _ = std::io::stderr().flush();
+        let snapshot = self.snapshot();
+        let mut stderr = std::io::stderr().lock();
+        let _ = match self.mode {
+            ProgressMode::Text => write!(stderr, "\r{}: {}", self.label, snapshot.status_line()),
+            ProgressMode::Json => writeln!(stderr, "{}", snapshot.event(event, self.pass)),
+        };
+        let _ = stderr.flush();
+    }
+}
+
+/// Counters of a pass at one point in time.
+#[derive(Debug, Clone, Copy)]
+struct ProgressSnapshot {
+    bytes: u64,
+    total_bytes: u64,
+    nodes: u64,
+    features: u64,
+    elapsed: Duration,
+}
+
+impl ProgressSnapshot {
+    fn percent(&self) -> f64 {
+        if self.total_bytes == 0 {
+            return 100.0;
+        }
+        self.bytes as f64 * 100.0 / self.total_bytes as f64
+    }
+
+    fn bytes_per_sec(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs <= 0.0 {
+            return 0.0;
+        }
+        self.bytes as f64 / secs
+    }
+
+    /// Remaining time at the average rate so far, `None` until anything was read.
+    fn eta(&self) -> Option<Duration> {
+        if self.bytes == 0 {
+            return None;
+        }
+        let remaining = self.total_bytes.saturating_sub(self.bytes) as f64;
+        Some(Duration::from_secs_f64(
+            self.elapsed.as_secs_f64() * remaining / self.bytes as f64,
+        ))
+    }
+
+    fn status_line(&self) -> String {
+        let eta = match self.eta() {
+            Some(eta) => format_duration(eta),
+            None => "-".to_string(),
+        };
+        let mut line = format!(
+            "{:5.1}% of {}, {}/s, ETA {}",
+            self.percent(),
+            format_bytes(self.total_bytes),
+            format_bytes(self.bytes_per_sec() as u64),
+            eta
+        );
+        if self.nodes > 0 {
+            line.push_str(&format!(", {} nodes", self.nodes));
+        }
+        if self.features > 0 {
+            line.push_str(&format!(", {} features", self.features));
+        }
+        // Pad so a shorter line fully overwrites the previous one
+        format!("{line:<80}")
+    }
+
+    fn event(&self, event: &str, pass: &str) -> serde_json::Value {
+        serde_json::json!({
+            "event": event,
+            "pass": pass,
+            "bytes": self.bytes,
+            "total_bytes": self.total_bytes,
+            "percent": (self.percent() * 10.0).round() / 10.0,
+            "nodes": self.nodes,
+            "features": self.features,
+            "elapsed_secs": self.elapsed.as_secs_f64(),
+            "bytes_per_sec": self.bytes_per_sec().round() as u64,
+            "eta_secs": self.eta().map(|eta| eta.as_secs()),
+        })
+    }
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{value:.1} {}", UNITS[unit])
+    }
+}
+
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if secs >= 3600 {
+        format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+    } else {
+        format!("{}:{:02}", secs / 60, secs % 60)
+    }
+}
+
+/// Reader wrapper that publishes its position in the underlying file, for [`Progress`].
+pub struct PositionReader<R> {
+    inner: R,
+    position: Arc<AtomicU64>,
+}
+
+impl<R> PositionReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            position: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    pub fn position(&self) -> Arc<AtomicU64> {
+        Arc::clone(&self.position)
+    }
+}
+
+impl<R: Read> Read for PositionReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.position.fetch_add(read as u64, Ordering::Relaxed);
+        Ok(read)
+    }
+}
+
+impl<R: Seek> Seek for PositionReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let position = self.inner.seek(pos)?;
+        self.position.store(position, Ordering::Relaxed);
+        Ok(position)
     }
 }
 
@@ -45,3 +251,144 @@ where
 {
     tags.map(|(k, v)| (k.to_string(), v.to_string())).collect()
 }
+
+/// Memory available to this process in bytes, if it can be determined.
+/// Uses `MemAvailable` from /proc/meminfo, capped by a cgroup v2 limit when running in a container.
+pub fn available_memory_bytes() -> Option<u64> {
+    let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+    let available_kb = meminfo.lines().find_map(|line| {
+        line.strip_prefix("MemAvailable:")?
+            .trim()
+            .trim_end_matches("kB")
+            .trim()
+            .parse::<u64>()
+            .ok()
+    })?;
+    let available = available_kb.saturating_mul(1024);
+
+    let read_cgroup = |name: &str| -> Option<u64> {
+        std::fs::read_to_string(Path::new("/sys/fs/cgroup").join(name))
+            .ok()?
+            .trim()
+            .parse::<u64>()
+            .ok()
+    };
+    // memory.max is "max" when unlimited, which fails to parse and leaves the host value
+    match (read_cgroup("memory.max"), read_cgroup("memory.current")) {
+        (Some(limit), Some(current)) => Some(available.min(limit.saturating_sub(current))),
+        _ => Some(available),
+    }
+}
+
+/// Free space in bytes on the filesystem containing `path`, if it can be determined.
+#[cfg(unix)]
+pub fn available_disk_bytes(path: &Path) -> Option<u64> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = std::ffi::CString::new(path.as_os_str().as_bytes()).ok()?;
+    // SAFETY: statvfs is plain old data, so an all-zero value is valid.
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: c_path is a valid NUL-terminated string and stat is a valid, writable statvfs.
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    // Field widths differ between platforms (u32 on macOS, u64 on Linux)
+    #[allow(clippy::useless_conversion)]
+    let free = u64::from(stat.f_bavail).saturating_mul(u64::from(stat.f_frsize));
+    Some(free)
+}
+
+#[cfg(not(unix))]
+pub fn available_disk_bytes(_path: &Path) -> Option<u64> {
+    None
+}
+
+/// Check whether the filesystem at `dir` allocates sparse files lazily, by creating a
+/// 1 GiB file without writing to it and comparing allocated blocks to its length.
+#[cfg(unix)]
+pub fn supports_sparse_files(dir: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    const PROBE_LEN: u64 = 1024 * 1024 * 1024;
+    let Ok(file) = tempfile::NamedTempFile::new_in(dir) else {
+        return false;
+    };
+    if file.as_file().set_len(PROBE_LEN).is_err() {
+        return false;
+    }
+    match file.as_file().metadata() {
+        // st_blocks is always counted in 512-byte units
+        Ok(metadata) => metadata.blocks().saturating_mul(512) < PROBE_LEN / 2,
+        Err(_) => false,
+    }
+}
+
+#[cfg(not(unix))]
+pub fn supports_sparse_files(_dir: &Path) -> bool {
+    // No cheap probe here; keep the previous behaviour of assuming support
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn snapshot(bytes: u64, total_bytes: u64, secs: u64) -> ProgressSnapshot {
+        ProgressSnapshot {
+            bytes,
+            total_bytes,
+            nodes: 0,
+            features: 42,
+            elapsed: Duration::from_secs(secs),
+        }
+    }
+
+    #[test]
+    fn snapshot_derives_rate_and_eta_from_bytes_read() {
+        let half = snapshot(500, 1000, 10);
+        assert_eq!(half.percent(), 50.0);
+        assert_eq!(half.bytes_per_sec(), 50.0);
+        assert_eq!(half.eta(), Some(Duration::from_secs(10)));
+
+        assert_eq!(snapshot(0, 1000, 10).eta(), None);
+        assert_eq!(snapshot(0, 0, 0).percent(), 100.0);
+    }
+
+    #[test]
+    fn json_event_has_pass_bytes_features_and_elapsed() {
+        let event = snapshot(250, 1000, 5).event("progress", "pass2");
+        assert_eq!(event["event"], "progress");
+        assert_eq!(event["pass"], "pass2");
+        assert_eq!(event["bytes"], 250);
+        assert_eq!(event["total_bytes"], 1000);
+        assert_eq!(event["percent"], 25.0);
+        assert_eq!(event["features"], 42);
+        assert_eq!(event["elapsed_secs"], 5.0);
+        assert_eq!(event["eta_secs"], 15);
+    }
+
+    #[test]
+    fn status_line_formats_sizes_and_durations() {
+        let line = snapshot(3 << 30, 4 << 30, 60).status_line();
+        assert!(line.starts_with(" 75.0% of 4.0 GB, 51.2 MB/s, ETA 0:20, 42 features"));
+
+        assert_eq!(format_bytes(512), "512 B");
+        assert_eq!(format_bytes(1536), "1.5 KB");
+        assert_eq!(format_duration(Duration::from_secs(3725)), "1:02:05");
+    }
+
+    #[test]
+    fn position_reader_follows_reads_and_seeks() {
+        let mut reader = PositionReader::new(Cursor::new(vec![0u8; 100]));
+        let position = reader.position();
+
+        reader.read_exact(&mut [0u8; 30]).unwrap();
+        assert_eq!(position.load(Ordering::Relaxed), 30);
+
+        reader.seek(SeekFrom::Start(80)).unwrap();
+        assert_eq!(position.load(Ordering::Relaxed), 80);
+        reader.read_exact(&mut [0u8; 20]).unwrap();
+        assert_eq!(position.load(Ordering::Relaxed), 100);
+    }
+}
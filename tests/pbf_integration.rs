@@ -590,6 +590,71 @@ table:
     }
 }
 
+#[test]
+fn dsl_filter_with_element_predicates() {
+    // Nodes and open ways are enabled but excluded by the filter
+    let filters = r#"
+table:
+  name: closed_buildings
+  filter: '@type=way & @closed & building & @version>=1'
+  geometry:
+    node: true
+    way: linestring
+    closed_way: polygon
+    relation: false
+  columns:
+    - name: osm_id
+      source: meta:id
+      type: string
+"#;
+    let lines = run_cosmo(filters);
+    let features = parse_features(&lines);
+
+    assert!(!features.is_empty(), "should find closed building ways");
+    for feature in &features {
+        assert_eq!(
+            geometry_type(feature),
+            "Polygon",
+            "only closed ways should match, got osm_id={}",
+            get_osm_id(feature).unwrap_or("?")
+        );
+    }
+}
+
+#[test]
+fn dsl_filter_with_spatial_predicates() {
+    let buildings = |filter: &str| {
+        let filters = format!(
+            r#"
+table:
+  name: buildings
+  filter: '{}'
+  geometry:
+    node: true
+    way: linestring
+    closed_way: centroid
+    relation: false
+  columns:
+    - name: osm_id
+      source: meta:id
+      type: string
+"#,
+            filter
+        );
+        parse_features(&run_cosmo(&filters)).len()
+    };
+
+    let all = buildings("building");
+    assert!(all > 0, "should find buildings");
+    // Closed ways are measured as polygons even though the output is a centroid
+    assert_eq!(
+        buildings("building & @area>=0 & @within(bbox:-180,-90,180,90)"),
+        all
+    );
+    assert_eq!(buildings("building & @area>1000000000000"), 0);
+    assert_eq!(buildings("building & @intersects(bbox:0,0,0.001,0.001)"), 0);
+}
+
 // =============================================================================
 // Regression Tests - Bugs Found in Production
 // =============================================================================
@@ -956,3 +1021,237 @@ table:
         );
     }
 }
+
+// =============================================================================
+// Ordered Output Tests
+// =============================================================================
+
+/// Run cosmo on the fixture with a filters file and extra arguments, returning the output lines.
+fn run_cosmo_args(filters_path: &Path, args: &[&str]) -> Vec<String> {
+    let exe = env!("CARGO_BIN_EXE_cosmo");
+    let output = Command::new(exe)
+        .arg("--input")
+        .arg(fixture_path())
+        .arg("--output")
+        .arg("-")
+        .arg("--format")
+        .arg("geojsonl")
+        .arg("--filters")
+        .arg(filters_path)
+        .arg("--node-cache-mode")
+        .arg("memory")
+        .args(args)
+        .output()
+        .expect("run cosmo");
+
+    assert!(
+        output.status.success(),
+        "cosmo {:?} should succeed: {}",
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| l.to_string())
+        .collect()
+}
+
+#[test]
+fn ordered_flag_follows_input_order() {
+    let filters = r#"
+table:
+  name: features
+  filter: 'amenity | highway'
+  geometry:
+    node: true
+    way: linestring
+    relation: false
+  columns:
+    - name: osm_id
+      source: meta:id
+      type: string
+"#;
+    let filters_path = write_temp_filters(filters);
+    let first = run_cosmo_args(&filters_path, &["--ordered", "--threads", "4"]);
+    let second = run_cosmo_args(&filters_path, &["--ordered", "--threads", "4"]);
+    let _ = std::fs::remove_file(&filters_path);
+
+    assert!(!first.is_empty(), "should extract some features");
+    assert_eq!(
+        first, second,
+        "ordered output should be identical across runs"
+    );
+
+    // Read the fixture sequentially; features must come out in exactly this element order
+    let mut element_order = Vec::new();
+    osmpbf::ElementReader::from_path(fixture_path())
+        .expect("open fixture")
+        .for_each(|element| {
+            element_order.push(match element {
+                osmpbf::Element::Node(node) => ('n', node.id()),
+                osmpbf::Element::DenseNode(node) => ('n', node.id()),
+                osmpbf::Element::Way(way) => ('w', way.id()),
+                osmpbf::Element::Relation(relation) => ('r', relation.id()),
+            })
+        })
+        .expect("read fixture");
+    let actual: Vec<(char, i64)> = parse_features(&first).iter().map(feature_element).collect();
+    let expected: Vec<(char, i64)> = element_order
+        .into_iter()
+        .filter(|element| actual.contains(element))
+        .collect();
+    assert_eq!(actual, expected, "features should follow PBF element order");
+}
+
+/// The element a feature of the ordered test comes from; only nodes become points there.
+fn feature_element(feature: &Value) -> (char, i64) {
+    let kind = if geometry_type(feature) == "Point" {
+        'n'
+    } else {
+        'w'
+    };
+    let id = get_osm_id(feature)
+        .and_then(|id| id.parse().ok())
+        .expect("osm_id should be an integer");
+    (kind, id)
+}
+
+// =============================================================================
+// Blob Index Tests
+// =============================================================================
+
+#[test]
+fn blob_index_sidecar_is_written_and_reused() {
+    let filters = r#"
+table:
+  name: features
+  filter: 'highway'
+  geometry:
+    node: false
+    way: linestring
+    relation: false
+  columns:
+    - name: osm_id
+      source: meta:id
+      type: string
+"#;
+    let filters_path = write_temp_filters(filters);
+    let index_path = filters_path.with_extension("blobindex");
+    let _ = std::fs::remove_file(&index_path);
+    let blob_index_args = ["--ordered", "--blob-index", index_path.to_str().unwrap()];
+
+    let without_index = run_cosmo_args(&filters_path, &["--ordered", "--threads", "4"]);
+    let first = run_cosmo_args(&filters_path, &blob_index_args);
+    let index: Value =
+        serde_json::from_slice(&std::fs::read(&index_path).expect("sidecar should be written"))
+            .expect("sidecar should be JSON");
+    let second = run_cosmo_args(&filters_path, &blob_index_args);
+
+    let _ = std::fs::remove_file(&filters_path);
+    let _ = std::fs::remove_file(&index_path);
+
+    assert!(!first.is_empty(), "should extract some ways");
+    assert_eq!(first, without_index, "index must not change the output");
+    assert_eq!(
+        first, second,
+        "reusing the sidecar must not change the output"
+    );
+    assert!(
+        index["unindexed_from"].is_null(),
+        "sidecar should cover the whole file"
+    );
+    assert!(
+        !index["entries"].as_array().unwrap().is_empty(),
+        "sidecar should list the data blobs"
+    );
+}
+
+// ============================================
+// Run report and progress events
+// ============================================
+
+#[test]
+fn report_file_summarizes_the_run() {
+    let filters = r#"
+table:
+  name: features
+  filter: 'highway'
+  geometry:
+    node: false
+    way: linestring
+    relation: false
+  columns:
+    - name: osm_id
+      source: meta:id
+      type: string
+"#;
+    let filters_path = write_temp_filters(filters);
+    let report_path = filters_path.with_extension("report.json");
+    let exe = env!("CARGO_BIN_EXE_cosmo");
+    let output = Command::new(exe)
+        .arg("--input")
+        .arg(fixture_path())
+        .arg("--output")
+        .arg("-")
+        .arg("--format")
+        .arg("geojsonl")
+        .arg("--filters")
+        .arg(&filters_path)
+        .arg("--node-cache-mode")
+        .arg("memory")
+        .arg("--progress")
+        .arg("json")
+        .arg("--report")
+        .arg(&report_path)
+        .output()
+        .expect("run cosmo");
+    let report = std::fs::read(&report_path);
+    let _ = std::fs::remove_file(&filters_path);
+    let _ = std::fs::remove_file(&report_path);
+
+    assert!(
+        output.status.success(),
+        "cosmo --report should succeed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let features = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter(|l| !l.trim().is_empty())
+        .count() as u64;
+    let report: Value =
+        serde_json::from_slice(&report.expect("report should be written")).expect("report JSON");
+
+    assert!(features > 0, "should extract some ways");
+    assert_eq!(report["features_written"], features);
+    assert_eq!(report["format"], "geojsonl");
+    assert_eq!(
+        report["elements_considered"]["nodes"], 0,
+        "nodes are not part of the table"
+    );
+    let ways = report["elements_considered"]["ways"].as_u64().unwrap();
+    let rejected = report["filter_rejected"].as_u64().unwrap();
+    let dropped = report["ways_missing_nodes"].as_u64().unwrap();
+    assert_eq!(ways, features + rejected + dropped);
+    assert_eq!(report["features_by_geometry"]["LineString"], features);
+    assert_eq!(report["node_cache"]["mode"], "memory");
+    assert!(report["node_cache"]["nodes"].as_u64().unwrap() > 0);
+    let passes: Vec<&str> = report["passes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|pass| pass["pass"].as_str().unwrap())
+        .collect();
+    assert_eq!(passes, vec!["pass1", "node_cache", "pass2"]);
+
+    let events: Vec<Value> = String::from_utf8_lossy(&output.stderr)
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect();
+    let finish = events
+        .iter()
+        .find(|event| event["event"] == "finish" && event["pass"] == "pass2")
+        .expect("pass 2 should emit a finish event");
+    assert_eq!(finish["features"], features);
+    assert_eq!(finish["bytes"], finish["total_bytes"]);
+}